@@ -12,6 +12,7 @@ const MODELS_OUT_DIR: &str = "src/models/imported";
 const ONNX_DIR: &str = "models";
 const ONNX_DIR_HASH: &str = "89bccae035e26f635866d12e0b5c030cfa2e7bf7a8889812043c0c08cf7e6126";
 const WEIGHTS_DIR: &str = "weights";
+const WEIGHTS_DIGESTS_FILE_NAME: &str = "weights_digests.rs";
 const TARGET_DIR_ERROR: &str =
 	"Failed to get OUT_DIR parent when trying to get to target directory!";
 const TRY_UNZIPPING_MODELS: &str = "Try running `oblichey/scripts/unzip_models.sh`";
@@ -54,10 +55,19 @@ fn main() {
 		);
 	};
 
+	let mut weights_digests = String::new();
 	for model_name in MODEL_NAMES {
 		import_onnx_model(model_name);
 		copy_weights_next_to_executable(model_name, &source_weights_dir, &new_weights_dir);
+		weights_digests += &get_weights_digest_constant(model_name, &source_weights_dir);
 	}
+
+	// The digests are checked when loading the weights at runtime to detect tampering
+	fs::write(
+		out_dir_path.join(WEIGHTS_DIGESTS_FILE_NAME),
+		weights_digests,
+	)
+	.expect("Failed to write weights digests!");
 }
 
 fn get_onnx_file_path(model_name: &str) -> String {
@@ -71,6 +81,17 @@ fn import_onnx_model(model_name: &str) {
 		.run_from_script();
 }
 
+fn get_weights_digest_constant(model_name: &str, source_weights_dir: &Path) -> String {
+	let weights = fs::read(source_weights_dir.join(format!("{model_name}.mpk")))
+		.expect("Failed to read weights when computing their digest!");
+	let digest = blake3::hash(&weights).to_hex();
+
+	format!(
+		"pub const {}: &str = \"{digest}\";\n",
+		model_name.to_uppercase()
+	)
+}
+
 fn copy_weights_next_to_executable(
 	model_name: &str,
 	source_weights_dir: &Path,
@@ -1,10 +1,13 @@
+pub mod replay;
 mod utils;
 
+use crate::config::CameraConfig;
 use crate::geometry::Vec2D;
 use image::{ImageBuffer, ImageError, Rgb};
 use log::{error, trace};
 use std::fmt::Display;
 use std::io;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use strum::IntoEnumIterator;
@@ -17,9 +20,9 @@ use v4l::video::Capture;
 use v4l::{Device, FourCC};
 
 /// Some (at least mine) IR cameras occasionally produce very dark frames which we ignore
-const MAX_BRIGHTNESS_DECREASE: f32 = 24.0;
+pub const DEFAULT_MAX_BRIGHTNESS_DECREASE: f32 = 24.0;
 /// How many times we are allowed to fail getting a frame before we return an error
-const MAX_FAILED_FRAMES_IN_ROW: u8 = 10;
+pub const DEFAULT_MAX_FAILED_FRAMES_IN_ROW: u8 = 10;
 
 /// The type of a frame coming from the camera
 pub type Frame = ImageBuffer<Rgb<u8>, Vec<u8>>;
@@ -40,11 +43,13 @@ impl SupportedPixelFormat {
 	}
 }
 
+#[derive(Debug)]
 pub enum Error {
 	Io(io::Error),
 	Image(ImageError),
 	CannotSetFormat,
 	OnlyGrayScaleSupported,
+	NoFrames(PathBuf),
 }
 
 impl From<io::Error> for Error {
@@ -66,6 +71,7 @@ impl Display for Error {
 			Self::Image(e) => write!(f, "Image error: {e}"),
 			Self::CannotSetFormat => write!(f, "Failed to set desired format"),
 			Self::OnlyGrayScaleSupported => write!(f, "Only gray scale cameras are supported"),
+			Self::NoFrames(p) => write!(f, "No frames found in {}", p.display()),
 		}
 	}
 }
@@ -123,9 +129,9 @@ impl Camera {
 pub fn start(
 	frame: &Arc<Mutex<Option<Frame>>>,
 	finished: &Arc<AtomicBool>,
-	camera_path: &str,
+	camera_config: &CameraConfig,
 ) -> Result<(), String> {
-	let mut camera = match Camera::new(camera_path) {
+	let mut camera = match Camera::new(&camera_config.path) {
 		Ok(c) => c,
 		Err(e) => return Err(format!("Failed to create camera: {e}")),
 	};
@@ -142,7 +148,7 @@ pub fn start(
 				error!("Failed to get frame: {e}");
 
 				failed_frames_in_row += 1;
-				if failed_frames_in_row < MAX_FAILED_FRAMES_IN_ROW {
+				if failed_frames_in_row >= camera_config.max_failed_frames_in_row {
 					return Err(String::from("Failed to get too many frames in a row"));
 				}
 				continue;
@@ -157,7 +163,7 @@ pub fn start(
 				let brightness = brightness(&new_frame, camera.frame_size);
 				let brightness_decrease = last_brightness - brightness;
 				last_brightness = brightness;
-				if brightness_decrease > MAX_BRIGHTNESS_DECREASE {
+				if brightness_decrease > camera_config.max_brightness_decrease {
 					continue;
 				}
 
@@ -0,0 +1,122 @@
+use super::{utils::reshape_frame, Error, Frame};
+use crate::geometry::Vec2D;
+use image::ImageFormat;
+use log::{trace, warn};
+use std::{
+	collections::HashSet,
+	fs::read_dir,
+	path::{Path, PathBuf},
+};
+
+/// Returns the paths of the frames to replay. The path can either point to a single image or to a
+/// directory of images (such as a recording split into frames) which are replayed in the order of
+/// their file names
+pub fn get_frame_paths(path: &Path) -> Result<Vec<PathBuf>, Error> {
+	if !path.is_dir() {
+		return Ok(vec![path.to_path_buf()]);
+	}
+
+	let mut frame_paths = Vec::new();
+	for entry in read_dir(path)? {
+		let entry_path = entry?.path();
+		if entry_path.is_file() && ImageFormat::from_path(&entry_path).is_ok() {
+			frame_paths.push(entry_path);
+		}
+	}
+	frame_paths.sort();
+
+	Ok(frame_paths)
+}
+
+/// Loads an image and reshapes it the same way frames from the camera are
+pub fn load_frame(path: &Path) -> Result<Frame, Error> {
+	let image = image::open(path)?.to_rgb8();
+	let frame_size = Vec2D::new(image.width(), image.height());
+
+	Ok(reshape_frame(&image, frame_size))
+}
+
+/// Returns an iterator over frames loaded from an image or a directory of images. This can be
+/// used instead of the camera. Each frame is replayed once, as copies of a single image would look
+/// like a perfectly still face to the processors, so images which are identical to a previous one
+/// are skipped along with images which cannot be loaded
+pub fn replay(path: &Path) -> Result<impl Iterator<Item = Frame>, Error> {
+	let frame_paths = get_frame_paths(path)?;
+	if frame_paths.is_empty() {
+		return Err(Error::NoFrames(path.to_path_buf()));
+	}
+	trace!("Replaying {} frames", frame_paths.len());
+
+	let mut frame_digests = HashSet::new();
+	Ok(frame_paths
+		.into_iter()
+		.filter_map(move |frame_path| match load_frame(&frame_path) {
+			Ok(f) if frame_digests.insert(blake3::hash(f.as_raw())) => Some(f),
+			Ok(_) => {
+				warn!(
+					"Frame {} is identical to a previous one - skipping",
+					frame_path.display()
+				);
+				None
+			}
+			Err(e) => {
+				warn!(
+					"Failed to load frame from {} - skipping: {e}",
+					frame_path.display()
+				);
+				None
+			}
+		}))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{get_frame_paths, replay};
+	use image::{Rgb, RgbImage};
+	use std::{env, fs, path::PathBuf};
+
+	#[test]
+	fn gets_frame_paths() {
+		let directory = env::temp_dir().join(format!("oblichey-replay-{}", std::process::id()));
+		fs::create_dir_all(&directory).expect("Failed to create directory");
+		for file_name in ["2.png", "10.jpg", "1.png", "notes.txt"] {
+			fs::write(directory.join(file_name), []).expect("Failed to create file");
+		}
+		fs::create_dir_all(directory.join("3.png")).expect("Failed to create directory");
+
+		let frame_paths = get_frame_paths(&directory).expect("Failed to get frame paths");
+		let single_frame_path =
+			get_frame_paths(&directory.join("1.png")).expect("Failed to get frame paths");
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_eq!(
+			frame_paths,
+			["1.png", "10.jpg", "2.png"]
+				.iter()
+				.map(|file_name| directory.join(file_name))
+				.collect::<Vec<PathBuf>>()
+		);
+		assert_eq!(single_frame_path, vec![directory.join("1.png")]);
+	}
+
+	#[test]
+	fn skips_identical_frames() {
+		let directory =
+			env::temp_dir().join(format!("oblichey-replay-identical-{}", std::process::id()));
+		fs::create_dir_all(&directory).expect("Failed to create directory");
+		for (file_name, color) in [("1.png", 0), ("2.png", 0), ("3.png", 255)] {
+			RgbImage::from_pixel(8, 8, Rgb([color; 3]))
+				.save(directory.join(file_name))
+				.expect("Failed to save image");
+		}
+
+		let frame_count = replay(&directory).expect("Failed to replay").count();
+		let single_frame_count = replay(&directory.join("1.png"))
+			.expect("Failed to replay")
+			.count();
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_eq!(frame_count, 2);
+		assert_eq!(single_frame_count, 1);
+	}
+}
@@ -1,11 +1,26 @@
+use crate::{
+	camera::{DEFAULT_MAX_BRIGHTNESS_DECREASE, DEFAULT_MAX_FAILED_FRAMES_IN_ROW},
+	models::{detector::DEFAULT_CONFIDENCE_THRESHOLD, registry::ModelsConfig},
+	processors::{
+		auth_processor::{AuthSettings, MultiFacePolicy, DEFAULT_AUTH_TIMEOUT},
+		consensus::Consensus,
+		face::{Aggregation, DEFAULT_SIMILARITY_THRESHOLD},
+		scan_processor::DEFAULT_SCAN_SAMPLE_COUNT,
+	},
+	store::{attempts::Lockout, snapshots::SNAPSHOTS_DIRECTORY},
+};
 use serde::Deserialize;
-use std::{fmt::Display, fs, io};
+use std::{collections::HashMap, fmt::Display, fs, io, path::PathBuf, time::Duration};
 
 const CONFIG_PATH: &str = "/etc/oblichey.toml";
+const DEFAULT_DATABASE_PATH: &str = "/var/lib/oblichey/faces.db";
+const DEFAULT_AUDIT_LOG_PATH: &str = "/var/log/oblichey/audit.jsonl";
 
+#[derive(Debug)]
 pub enum Error {
 	TomlDeserialize(toml::de::Error),
 	Io(io::Error),
+	Invalid(String),
 }
 
 impl Display for Error {
@@ -13,6 +28,7 @@ impl Display for Error {
 		match self {
 			Self::TomlDeserialize(e) => write!(f, "TOML deserialization failed: {e}"),
 			Self::Io(e) => write!(f, "IO error: {e}"),
+			Self::Invalid(e) => write!(f, "Invalid configuration: {e}"),
 		}
 	}
 }
@@ -29,19 +45,480 @@ impl From<io::Error> for Error {
 	}
 }
 
+const fn get_default_max_brightness_decrease() -> f32 {
+	DEFAULT_MAX_BRIGHTNESS_DECREASE
+}
+
+const fn get_default_max_failed_frames_in_row() -> u8 {
+	DEFAULT_MAX_FAILED_FRAMES_IN_ROW
+}
+
 #[derive(Deserialize, Clone)]
 pub struct CameraConfig {
 	pub path: String,
+	/// Frames darker than the previous one by more than this are skipped
+	#[serde(default = "get_default_max_brightness_decrease")]
+	pub max_brightness_decrease: f32,
+	/// How many frames in a row may fail to be captured before giving up
+	#[serde(default = "get_default_max_failed_frames_in_row")]
+	pub max_failed_frames_in_row: u8,
+}
+
+/// The backend used for running the neural network models
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+	/// Use WGPU if a working GPU adapter can be found and fall back to the CPU otherwise
+	#[default]
+	Auto,
+	/// Run on the GPU using WGPU (Vulkan, Metal, DX12 or OpenGL)
+	Wgpu,
+	/// Run on the CPU using ndarray
+	Ndarray,
+}
+
+impl Display for Backend {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Auto => write!(f, "auto"),
+			Self::Wgpu => write!(f, "wgpu"),
+			Self::Ndarray => write!(f, "ndarray"),
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct InferenceConfig {
+	#[serde(default)]
+	pub backend: Backend,
+}
+
+/// Settings which can be overridden for individual users
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct UserAuthConfig {
+	pub similarity_threshold: Option<f32>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AuthConfig {
+	/// How the similarities to the templates of a face are combined
+	pub aggregation: Aggregation,
+	/// How many frames have to agree before the user is authenticated
+	pub consensus: Consensus,
+	/// Faces at least this similar to a scanned face are recognized as it
+	pub similarity_threshold: f32,
+	/// How long to look for a known face before giving up, in seconds
+	pub timeout: u64,
+	/// What to do when there are multiple faces in a frame
+	pub multi_face_policy: MultiFacePolicy,
+	/// Overrides for individual users, keyed by their user name
+	pub users: HashMap<String, UserAuthConfig>,
+}
+
+impl Default for AuthConfig {
+	fn default() -> Self {
+		Self {
+			aggregation: Aggregation::default(),
+			consensus: Consensus::default(),
+			similarity_threshold: DEFAULT_SIMILARITY_THRESHOLD,
+			timeout: DEFAULT_AUTH_TIMEOUT,
+			multi_face_policy: MultiFacePolicy::default(),
+			users: HashMap::new(),
+		}
+	}
+}
+
+impl AuthConfig {
+	/// Returns the settings for the given user, taking their overrides into account
+	pub fn get_settings(&self, user: Option<&str>) -> AuthSettings {
+		AuthSettings {
+			similarity_threshold: self.get_similarity_threshold(user, None),
+			aggregation: self.aggregation,
+			consensus: self.consensus,
+			timeout: Duration::from_secs(self.timeout),
+			multi_face_policy: self.multi_face_policy,
+			keep_snapshot: false,
+		}
+	}
+
+	/// Returns the similarity threshold for a face of the given user. An override for the user
+	/// takes precedence over the threshold calibrated for the face, which may only raise the
+	/// configured one
+	pub fn get_similarity_threshold(
+		&self,
+		user: Option<&str>,
+		calibrated_threshold: Option<f32>,
+	) -> f32 {
+		user.and_then(|u| self.users.get(u))
+			.and_then(|c| c.similarity_threshold)
+			.or_else(|| calibrated_threshold.map(|t| t.max(self.similarity_threshold)))
+			.unwrap_or(self.similarity_threshold)
+	}
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ScanConfig {
+	/// The number of samples captured during a scan which is not guided
+	pub sample_count: usize,
+}
+
+impl Default for ScanConfig {
+	fn default() -> Self {
+		Self {
+			sample_count: DEFAULT_SCAN_SAMPLE_COUNT,
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct DetectorConfig {
+	/// Detections with a lower confidence are discarded
+	pub confidence_threshold: f32,
+}
+
+impl Default for DetectorConfig {
+	fn default() -> Self {
+		Self {
+			confidence_threshold: DEFAULT_CONFIDENCE_THRESHOLD,
+		}
+	}
+}
+
+fn get_default_database_path() -> PathBuf {
+	PathBuf::from(DEFAULT_DATABASE_PATH)
+}
+
+/// Where faces are stored
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", tag = "backend")]
+pub enum StoreConfig {
+	/// Store each face in a separate file in the state directory of the user
+	#[default]
+	Directory,
+	/// Store the faces of all users in a single `SQLite` database
+	Sqlite {
+		#[serde(default = "get_default_database_path")]
+		path: PathBuf,
+	},
+}
+
+/// Where authentication attempts are recorded
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct AuditConfig {
+	pub enabled: bool,
+	pub path: PathBuf,
+}
+
+impl Default for AuditConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			path: PathBuf::from(DEFAULT_AUDIT_LOG_PATH),
+		}
+	}
+}
+
+/// What part of the frame is saved as the snapshot of a failed attempt
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotContent {
+	/// Only the face most similar to a scanned face, or the whole frame if there was none
+	#[default]
+	Face,
+	Frame,
+}
+
+/// Whether and how images of failed authentication attempts are kept. They show whoever was in
+/// front of the camera, so this is disabled by default
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct SnapshotConfig {
+	pub enabled: bool,
+	pub content: SnapshotContent,
+	pub directory: PathBuf,
+	/// The maximum number of kept snapshots. The oldest ones are removed first
+	pub max_count: usize,
+	/// How long snapshots are kept, in seconds
+	pub max_age: u64,
+}
+
+impl Default for SnapshotConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			content: SnapshotContent::default(),
+			directory: PathBuf::from(SNAPSHOTS_DIRECTORY),
+			max_count: 20,
+			max_age: 30 * 24 * 60 * 60,
+		}
+	}
+}
+
+/// Whether and how the templates of a face are adapted as it changes over time. Only very confident
+/// authentications are learned from, so that the templates cannot be gradually poisoned
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct AdaptationConfig {
+	pub enabled: bool,
+	/// How far above the similarity threshold an authentication has to be to be learned from
+	pub margin: f32,
+	/// How much an adapted template is moved from the scanned templates towards the embedding
+	/// learned from, between 0 and 1
+	pub influence: f32,
+	/// The maximum number of adapted templates of a face. The oldest ones are replaced first
+	pub max_history: usize,
+	/// The maximum number of adaptations of a face within a day, so that the face cannot be moved
+	/// quickly by many authentications
+	pub max_per_day: usize,
+	/// Only learn from authentications in which liveness was verified. Liveness cannot be verified
+	/// yet, so adaptation can only be enabled if this is turned off
+	pub require_liveness: bool,
+}
+
+impl Default for AdaptationConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			margin: 0.05,
+			influence: 0.2,
+			max_history: 10,
+			max_per_day: 1,
+			require_liveness: true,
+		}
+	}
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
 	pub camera: CameraConfig,
+	#[serde(default)]
+	pub auth: AuthConfig,
+	#[serde(default)]
+	pub scan: ScanConfig,
+	#[serde(default)]
+	pub detector: DetectorConfig,
+	#[serde(default)]
+	pub inference: InferenceConfig,
+	#[serde(default)]
+	pub models: ModelsConfig,
+	#[serde(default)]
+	pub store: StoreConfig,
+	#[serde(default)]
+	pub lockout: Lockout,
+	#[serde(default)]
+	pub audit: AuditConfig,
+	#[serde(default)]
+	pub snapshots: SnapshotConfig,
+	#[serde(default)]
+	pub adaptation: AdaptationConfig,
 }
 
 impl Config {
 	pub fn load() -> Result<Self, Error> {
-		let serialized = fs::read_to_string(CONFIG_PATH)?;
-		Ok(toml::from_str(&serialized)?)
+		Self::parse(&fs::read_to_string(CONFIG_PATH)?)
+	}
+
+	/// Loads the config, if there is one
+	pub fn load_optional() -> Result<Option<Self>, Error> {
+		match fs::read_to_string(CONFIG_PATH) {
+			Ok(s) => Ok(Some(Self::parse(&s)?)),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(Error::from(e)),
+		}
+	}
+
+	fn parse(serialized: &str) -> Result<Self, Error> {
+		let config: Self = toml::from_str(serialized)?;
+		config.validate()?;
+
+		Ok(config)
+	}
+
+	/// Checks that the values make sense, so that a typo cannot silently make authentication
+	/// impossible or accept anyone
+	fn validate(&self) -> Result<(), Error> {
+		let is_similarity = |s: f32| (0.0..=1.0).contains(&s);
+
+		if !is_similarity(self.auth.similarity_threshold) {
+			return Err(Error::Invalid(String::from(
+				"auth.similarity_threshold must be between 0 and 1",
+			)));
+		}
+		for (user, user_config) in &self.auth.users {
+			if user_config
+				.similarity_threshold
+				.is_some_and(|s| !is_similarity(s))
+			{
+				return Err(Error::Invalid(format!(
+					"auth.users.{user}.similarity_threshold must be between 0 and 1"
+				)));
+			}
+		}
+		if self.auth.timeout == 0 {
+			return Err(Error::Invalid(String::from(
+				"auth.timeout must be greater than 0",
+			)));
+		}
+		if self.auth.aggregation == Aggregation::TopKMean(0) {
+			return Err(Error::Invalid(String::from(
+				"auth.aggregation.top-k-mean must be greater than 0",
+			)));
+		}
+		let consensus = &self.auth.consensus;
+		if consensus.required_matches == 0 || consensus.required_matches > consensus.window {
+			return Err(Error::Invalid(String::from(
+				"auth.consensus.required_matches must be between 1 and auth.consensus.window",
+			)));
+		}
+		if consensus
+			.min_mean_similarity
+			.is_some_and(|s| !is_similarity(s))
+		{
+			return Err(Error::Invalid(String::from(
+				"auth.consensus.min_mean_similarity must be between 0 and 1",
+			)));
+		}
+		if self.scan.sample_count == 0 {
+			return Err(Error::Invalid(String::from(
+				"scan.sample_count must be greater than 0",
+			)));
+		}
+		if !(self.detector.confidence_threshold > 0.0 && self.detector.confidence_threshold < 1.0) {
+			return Err(Error::Invalid(String::from(
+				"detector.confidence_threshold must be between 0 and 1",
+			)));
+		}
+		if !(0.0..).contains(&self.camera.max_brightness_decrease) {
+			return Err(Error::Invalid(String::from(
+				"camera.max_brightness_decrease must not be negative",
+			)));
+		}
+		if self.camera.max_failed_frames_in_row == 0 {
+			return Err(Error::Invalid(String::from(
+				"camera.max_failed_frames_in_row must be greater than 0",
+			)));
+		}
+		if self.lockout.max_failures == 0 || self.lockout.window == 0 || self.lockout.duration == 0
+		{
+			return Err(Error::Invalid(String::from(
+				"lockout.max_failures, lockout.window and lockout.duration must be greater than 0",
+			)));
+		}
+		if self.snapshots.max_count == 0 || self.snapshots.max_age == 0 {
+			return Err(Error::Invalid(String::from(
+				"snapshots.max_count and snapshots.max_age must be greater than 0",
+			)));
+		}
+		if !is_similarity(self.adaptation.margin) || !is_similarity(self.adaptation.influence) {
+			return Err(Error::Invalid(String::from(
+				"adaptation.margin and adaptation.influence must be between 0 and 1",
+			)));
+		}
+		if self.adaptation.max_history == 0 || self.adaptation.max_per_day == 0 {
+			return Err(Error::Invalid(String::from(
+				"adaptation.max_history and adaptation.max_per_day must be greater than 0",
+			)));
+		}
+		// Oblichey cannot detect spoofing yet, so adaptation would never happen
+		if self.adaptation.enabled && self.adaptation.require_liveness {
+			return Err(Error::Invalid(String::from(
+				"adaptation.enabled requires adaptation.require_liveness to be false, as liveness detection is not available yet - adapting without it lets a photo which is accepted once gradually become a part of the face",
+			)));
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Config;
+
+	#[test]
+	fn validates_config() {
+		let test_cases = vec![
+			("", true),
+			("[auth]\nsimilarity_threshold = 0.8\ntimeout = 5", true),
+			("[auth]\nsimilarity_threshold = 1.5", false),
+			("[auth]\ntimeout = 0", false),
+			("[auth.users.alice]\nsimilarity_threshold = 0.95", true),
+			("[auth.users.alice]\nsimilarity_threshold = -0.1", false),
+			("[auth.consensus]\nrequired_matches = 6\nwindow = 5", false),
+			("[auth.consensus]\nrequired_matches = 0", false),
+			("[auth]\naggregation = { top-k-mean = 2 }", true),
+			("[auth]\naggregation = { top-k-mean = 0 }", false),
+			("[scan]\nsample_count = 0", false),
+			("[detector]\nconfidence_threshold = 0.5", true),
+			("[detector]\nconfidence_threshold = 1.0", false),
+			(
+				"[camera]\npath = \"/dev/video0\"\nmax_brightness_decrease = -1.0",
+				false,
+			),
+			(
+				"[camera]\npath = \"/dev/video0\"\nmax_failed_frames_in_row = 0",
+				false,
+			),
+			("[lockout]\nenabled = false", true),
+			("[lockout]\nmax_failures = 0", false),
+			("[snapshots]\nenabled = true\ncontent = \"frame\"", true),
+			("[snapshots]\nmax_age = 0", false),
+			("[adaptation]\nenabled = true\nmargin = 0.1", false),
+			(
+				"[adaptation]\nenabled = true\nmargin = 0.1\nrequire_liveness = false",
+				true,
+			),
+			("[adaptation]\ninfluence = 1.5", false),
+			("[adaptation]\nmax_per_day = 0", false),
+		];
+
+		for (serialized, expected_result) in test_cases {
+			let serialized = if serialized.starts_with("[camera]") {
+				serialized.to_owned()
+			} else {
+				format!("[camera]\npath = \"/dev/video0\"\n{serialized}")
+			};
+			assert_eq!(
+				Config::parse(&serialized).is_ok(),
+				expected_result,
+				"{serialized}"
+			);
+		}
+	}
+
+	#[test]
+	fn applies_user_overrides() {
+		let config = Config::parse(
+			"[camera]\npath = \"/dev/video0\"\n[auth]\nsimilarity_threshold = 0.8\n[auth.users.alice]\nsimilarity_threshold = 0.95",
+		)
+		.expect("Failed to parse config");
+
+		let test_cases = vec![(Some("alice"), 0.95), (Some("bob"), 0.8), (None, 0.8)];
+
+		for (user, expected_threshold) in test_cases {
+			let settings = config.auth.get_settings(user);
+			assert!((settings.similarity_threshold - expected_threshold).abs() < f32::EPSILON);
+		}
+
+		// Calibrated thresholds only apply to users without an override and never lower the
+		// configured one
+		let test_cases = vec![
+			(Some("alice"), Some(0.9), 0.95),
+			(Some("bob"), Some(0.9), 0.9),
+			(None, Some(0.9), 0.9),
+			(Some("bob"), Some(0.7), 0.8),
+			(None, Some(0.7), 0.8),
+		];
+
+		for (user, calibrated_threshold, expected_threshold) in test_cases {
+			let similarity_threshold = config
+				.auth
+				.get_similarity_threshold(user, calibrated_threshold);
+			assert!((similarity_threshold - expected_threshold).abs() < f32::EPSILON);
+		}
 	}
 }
@@ -0,0 +1,470 @@
+use crate::{
+	camera::{self, replay, Frame},
+	processors::face::{FaceEmbedding, FaceForProcessing},
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+	fmt::Display,
+	fs::{self, read_dir},
+	io,
+	path::{Path, PathBuf},
+};
+
+/// The number of buckets the similarities from -1 to 1 are split into in the distributions
+const HISTOGRAM_BUCKET_COUNT: usize = 40;
+/// The number of steps between the thresholds of 0 and 1 at which the ROC curve is sampled
+const ROC_STEP_COUNT: u16 = 100;
+/// The share of faces of other people calibrated thresholds accept, unless requested otherwise
+pub const DEFAULT_TARGET_FALSE_ACCEPT_RATE: f32 = 0.001;
+/// The highest share of faces of other people a threshold can be calibrated to accept
+pub const MAX_TARGET_FALSE_ACCEPT_RATE: f32 = 0.01;
+/// How far above the most similar pair of different people which has to be rejected a calibrated
+/// threshold is
+const CALIBRATION_THRESHOLD_MARGIN: f32 = 0.001;
+
+#[derive(Debug)]
+pub enum Error {
+	Io(io::Error),
+	Camera(camera::Error),
+	Json(serde_json::Error),
+	/// Error rates cannot be computed without both pairs of the same and of different people
+	NotEnoughSamples,
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Io(e) => write!(f, "IO error: {e}"),
+			Self::Camera(e) => write!(f, "Failed to load images: {e}"),
+			Self::Json(e) => write!(f, "JSON error: {e}"),
+			Self::NotEnoughSamples => write!(
+				f,
+				"At least two people with at least two usable samples each are needed"
+			),
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(value: io::Error) -> Self {
+		Self::Io(value)
+	}
+}
+
+impl From<camera::Error> for Error {
+	fn from(value: camera::Error) -> Self {
+		Self::Camera(value)
+	}
+}
+
+impl From<serde_json::Error> for Error {
+	fn from(value: serde_json::Error) -> Self {
+		Self::Json(value)
+	}
+}
+
+/// The embedding of a single image or recording of a person
+#[derive(Debug, Clone)]
+pub struct Sample {
+	pub label: String,
+	pub path: PathBuf,
+	pub embedding: FaceEmbedding,
+}
+
+/// Labelled samples loaded from a directory with a subdirectory for each person
+#[derive(Debug, Default)]
+pub struct Dataset {
+	pub samples: Vec<Sample>,
+	/// Images and recordings in which no face could be recognized
+	pub skipped: Vec<PathBuf>,
+}
+
+impl Dataset {
+	/// Loads a dataset in which each subdirectory contains the images of a single person, named
+	/// after the subdirectory. Each image is a sample, as is each directory of frames of a recording.
+	/// The embeddings are computed by `embed` and averaged over the frames of recordings
+	pub fn load(
+		path: &Path,
+		mut embed: impl FnMut(&Frame) -> Option<FaceEmbedding>,
+	) -> Result<Self, Error> {
+		let mut person_paths = Vec::new();
+		for entry in read_dir(path)? {
+			let entry_path = entry?.path();
+			if entry_path.is_dir() {
+				person_paths.push(entry_path);
+			}
+		}
+		person_paths.sort();
+
+		let mut dataset = Self::default();
+		for person_path in person_paths {
+			let Some(label) = person_path
+				.file_name()
+				.map(|n| n.to_string_lossy().into_owned())
+			else {
+				continue;
+			};
+			let mut sample_paths: Vec<PathBuf> = read_dir(&person_path)?
+				.map(|e| e.map(|e| e.path()))
+				.collect::<Result<_, _>>()?;
+			sample_paths.sort();
+			info!("Loading {} samples of {label}", sample_paths.len());
+
+			for sample_path in sample_paths {
+				let frame_paths = replay::get_frame_paths(&sample_path)?;
+				if frame_paths.is_empty() {
+					continue;
+				}
+				let embeddings: Vec<FaceEmbedding> = frame_paths
+					.iter()
+					.filter_map(|p| match replay::load_frame(p) {
+						Ok(f) => embed(&f),
+						Err(e) => {
+							warn!("Failed to load {} - skipping: {e}", p.display());
+							None
+						}
+					})
+					.collect();
+				if embeddings.is_empty() {
+					dataset.skipped.push(sample_path);
+					continue;
+				}
+				dataset.samples.push(Sample {
+					label: label.clone(),
+					path: sample_path,
+					embedding: FaceEmbedding::average_embedding(&embeddings),
+				});
+			}
+		}
+
+		Ok(dataset)
+	}
+}
+
+/// Returns the embedding of the largest face which could be recognized. Datasets usually show a
+/// single person, so smaller faces are assumed to be in the background
+pub fn get_largest_face_embedding(faces: Vec<FaceForProcessing>) -> Option<FaceEmbedding> {
+	faces
+		.into_iter()
+		.filter_map(|f| Some((f.rectangle, f.face_data.ok()?)))
+		.max_by_key(|(r, _)| r.size().map_or(0, |s| u64::from(s.x) * u64::from(s.y)))
+		.map(|(_, d)| d.embedding)
+}
+
+/// How often faces are falsely accepted and rejected at a similarity threshold
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ErrorRates {
+	pub threshold: f32,
+	/// The share of pairs of different people which are at least as similar as the threshold
+	pub false_accept_rate: f32,
+	/// The share of pairs of the same person which are less similar than the threshold
+	pub false_reject_rate: f32,
+}
+
+impl Display for ErrorRates {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"FAR {:.3}%, FRR {:.3}%",
+			self.false_accept_rate * 100.0,
+			self.false_reject_rate * 100.0
+		)
+	}
+}
+
+/// The similarities of pairs of samples
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Distribution {
+	pub count: usize,
+	pub mean: f32,
+	pub standard_deviation: f32,
+	pub min: f32,
+	pub max: f32,
+	/// The number of similarities in each of the equally sized buckets from -1 to 1
+	pub histogram: Vec<usize>,
+}
+
+impl Distribution {
+	fn new(similarities: &[f32]) -> Self {
+		let count = similarities.len();
+		let mean = similarities.iter().sum::<f32>() / count as f32;
+		let variance = similarities.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / count as f32;
+		let mut histogram = vec![0; HISTOGRAM_BUCKET_COUNT];
+		for similarity in similarities {
+			// The similarity is clamped, so the bucket cannot be negative
+			#[allow(clippy::cast_sign_loss)]
+			let bucket = (((similarity + 1.0) / 2.0).clamp(0.0, 1.0) * HISTOGRAM_BUCKET_COUNT as f32)
+				as usize;
+			histogram[bucket.min(HISTOGRAM_BUCKET_COUNT - 1)] += 1;
+		}
+
+		Self {
+			count,
+			mean,
+			standard_deviation: variance.sqrt(),
+			min: similarities.iter().copied().fold(f32::INFINITY, f32::min),
+			max: similarities
+				.iter()
+				.copied()
+				.fold(f32::NEG_INFINITY, f32::max),
+			histogram,
+		}
+	}
+}
+
+impl Display for Distribution {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{} pairs, similarity mean {:.3} (standard deviation {:.3}, min {:.3}, max {:.3})",
+			self.count, self.mean, self.standard_deviation, self.min, self.max
+		)
+	}
+}
+
+/// The similarities of all pairs of samples of the same person and of different people, sorted
+/// in ascending order
+pub struct Scores {
+	genuine: Vec<f32>,
+	impostor: Vec<f32>,
+}
+
+impl Scores {
+	pub fn from_similarities(mut genuine: Vec<f32>, mut impostor: Vec<f32>) -> Self {
+		genuine.sort_by(f32::total_cmp);
+		impostor.sort_by(f32::total_cmp);
+
+		Self { genuine, impostor }
+	}
+
+	fn new(samples: &[Sample]) -> Self {
+		let mut genuine = Vec::new();
+		let mut impostor = Vec::new();
+		for (index, sample) in samples.iter().enumerate() {
+			for other in &samples[index + 1..] {
+				let Some(similarity) = sample.embedding.cosine_similarity(&other.embedding) else {
+					continue;
+				};
+				if sample.label == other.label {
+					genuine.push(similarity);
+				} else {
+					impostor.push(similarity);
+				}
+			}
+		}
+		Self::from_similarities(genuine, impostor)
+	}
+
+	pub fn get_error_rates(&self, threshold: f32) -> ErrorRates {
+		let rejected_genuine_count = self.genuine.partition_point(|s| *s < threshold);
+		let accepted_impostor_count =
+			self.impostor.len() - self.impostor.partition_point(|s| *s < threshold);
+
+		ErrorRates {
+			threshold,
+			false_accept_rate: accepted_impostor_count as f32 / self.impostor.len() as f32,
+			false_reject_rate: rejected_genuine_count as f32 / self.genuine.len() as f32,
+		}
+	}
+
+	/// Returns the error rates at the lowest threshold at which no more than the given share of
+	/// pairs of different people is accepted, or `None` if there are no pairs to tell
+	pub fn get_threshold_for_false_accept_rate(
+		&self,
+		target_false_accept_rate: f32,
+	) -> Option<ErrorRates> {
+		if self.genuine.is_empty() || self.impostor.is_empty() {
+			return None;
+		}
+
+		// The target is not negative, so neither is the count
+		#[allow(clippy::cast_sign_loss)]
+		let accepted_count = (target_false_accept_rate * self.impostor.len() as f32).floor() as usize;
+		let threshold = self
+			.impostor
+			.len()
+			.checked_sub(accepted_count + 1)
+			.map_or(-1.0, |i| {
+				(self.impostor[i] + CALIBRATION_THRESHOLD_MARGIN).min(1.0)
+			});
+
+		Some(self.get_error_rates(threshold))
+	}
+
+	/// Returns the error rates at the threshold where they are the closest to each other. The
+	/// equal error rate is their mean
+	fn get_equal_error_rates(&self) -> Option<ErrorRates> {
+		self.genuine
+			.iter()
+			.chain(&self.impostor)
+			.map(|t| self.get_error_rates(*t))
+			.min_by(|a, b| {
+				(a.false_accept_rate - a.false_reject_rate)
+					.abs()
+					.total_cmp(&(b.false_accept_rate - b.false_reject_rate).abs())
+			})
+	}
+}
+
+/// The results of an evaluation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EvaluationReport {
+	pub person_count: usize,
+	pub sample_count: usize,
+	/// Images and recordings in which no face could be recognized
+	pub skipped: Vec<PathBuf>,
+	pub genuine: Distribution,
+	pub impostor: Distribution,
+	pub equal_error_rates: Option<ErrorRates>,
+	/// The error rates at the requested thresholds
+	pub thresholds: Vec<ErrorRates>,
+	/// The error rates at thresholds from 0 to 1
+	pub roc: Vec<ErrorRates>,
+}
+
+impl EvaluationReport {
+	pub fn new(dataset: &Dataset, thresholds: &[f32]) -> Result<Self, Error> {
+		let scores = Scores::new(&dataset.samples);
+		if scores.genuine.is_empty() || scores.impostor.is_empty() {
+			return Err(Error::NotEnoughSamples);
+		}
+		let mut labels: Vec<&str> = dataset.samples.iter().map(|s| s.label.as_str()).collect();
+		labels.sort_unstable();
+		labels.dedup();
+
+		Ok(Self {
+			person_count: labels.len(),
+			sample_count: dataset.samples.len(),
+			skipped: dataset.skipped.clone(),
+			genuine: Distribution::new(&scores.genuine),
+			impostor: Distribution::new(&scores.impostor),
+			equal_error_rates: scores.get_equal_error_rates(),
+			thresholds: thresholds
+				.iter()
+				.map(|t| scores.get_error_rates(*t))
+				.collect(),
+			roc: (0..=ROC_STEP_COUNT)
+				.map(|i| scores.get_error_rates(f32::from(i) / f32::from(ROC_STEP_COUNT)))
+				.collect(),
+		})
+	}
+
+	/// Writes the report including the distributions and the ROC curve into a JSON file
+	pub fn export(&self, path: &Path) -> Result<(), Error> {
+		fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+		Ok(())
+	}
+}
+
+impl Display for EvaluationReport {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		writeln!(
+			f,
+			"{} people, {} samples ({} skipped as no face was recognized)",
+			self.person_count,
+			self.sample_count,
+			self.skipped.len()
+		)?;
+		writeln!(f, "Same person: {}", self.genuine)?;
+		writeln!(f, "Different people: {}", self.impostor)?;
+		if let Some(equal_error_rates) = &self.equal_error_rates {
+			writeln!(
+				f,
+				"Equal error rate: {:.3}% at threshold {:.3}",
+				(equal_error_rates.false_accept_rate + equal_error_rates.false_reject_rate) * 50.0,
+				equal_error_rates.threshold
+			)?;
+		}
+		for error_rates in &self.thresholds {
+			writeln!(f, "Threshold {:.3}: {error_rates}", error_rates.threshold)?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Dataset, ErrorRates, EvaluationReport, Sample, Scores};
+	use crate::processors::face::{FaceEmbedding, EMBEDDING_LENGTH};
+	use std::path::PathBuf;
+
+	/// Returns a sample whose embedding has the given similarity to the embedding of the
+	/// samples with the similarity of 1
+	fn get_sample(label: &str, similarity: f32) -> Sample {
+		let mut embedding = [0.0; EMBEDDING_LENGTH];
+		embedding[0] = similarity;
+		embedding[1] = similarity.mul_add(-similarity, 1.0).sqrt();
+		Sample {
+			label: label.to_owned(),
+			path: PathBuf::from(label),
+			embedding: FaceEmbedding::new(&embedding),
+		}
+	}
+
+	#[test]
+	fn computes_error_rates() {
+		let dataset = Dataset {
+			samples: vec![
+				get_sample("alice", 1.0),
+				get_sample("alice", 0.95),
+				get_sample("bob", 0.5),
+				get_sample("bob", 0.0),
+			],
+			skipped: vec![],
+		};
+
+		let report = EvaluationReport::new(&dataset, &[0.9, 0.4]).expect("Failed to evaluate");
+
+		assert_eq!(report.person_count, 2);
+		assert_eq!(report.genuine.count, 2);
+		assert_eq!(report.impostor.count, 4);
+		assert_eq!(
+			report.thresholds[0],
+			ErrorRates {
+				threshold: 0.9,
+				false_accept_rate: 0.0,
+				false_reject_rate: 0.5,
+			}
+		);
+		// Half of the pairs of different people are at least 0.4 similar
+		assert!((report.thresholds[1].false_accept_rate - 0.5).abs() < 1e-6);
+		assert!(report.thresholds[1].false_reject_rate.abs() <= f32::EPSILON);
+		assert_eq!(report.roc.len(), 101);
+		assert!(report.roc[0].false_reject_rate.abs() <= f32::EPSILON);
+		assert!(report
+			.roc
+			.windows(2)
+			.all(|w| w[0].false_accept_rate >= w[1].false_accept_rate));
+		assert!(report.equal_error_rates.is_some());
+
+		let one_person = Dataset {
+			samples: vec![get_sample("alice", 1.0), get_sample("alice", 0.9)],
+			skipped: vec![],
+		};
+		assert!(EvaluationReport::new(&one_person, &[]).is_err());
+	}
+
+	#[test]
+	fn calibrates_threshold() {
+		let scores = Scores::from_similarities(
+			vec![0.9, 0.5, 0.8, 0.7],
+			vec![0.1, 0.6, 0.3, 0.2, 0.4, 0.0, 0.1, 0.2, 0.3, 0.35],
+		);
+		let test_cases = vec![(0.05, 0.601, 0.0, 0.25), (0.1, 0.401, 0.1, 0.0)];
+
+		for (target, expected_threshold, expected_far, expected_frr) in test_cases {
+			let Some(error_rates) = scores.get_threshold_for_false_accept_rate(target) else {
+				panic!("No threshold was found");
+			};
+			assert!((error_rates.threshold - expected_threshold).abs() < 1e-6);
+			assert!((error_rates.false_accept_rate - expected_far).abs() < 1e-6);
+			assert!((error_rates.false_reject_rate - expected_frr).abs() < 1e-6);
+		}
+		assert!(Scores::from_similarities(vec![0.9], vec![])
+			.get_threshold_for_false_accept_rate(0.1)
+			.is_none());
+	}
+}
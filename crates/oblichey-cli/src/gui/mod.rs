@@ -3,6 +3,7 @@ mod poi;
 
 use crate::{
 	camera::{self, Frame},
+	geometry::Rectangle,
 	gui::{
 		geometry::{RectangleToEgui, Vec2DToEgui},
 		poi::draw_poi_square,
@@ -16,6 +17,7 @@ use eframe::{
 	},
 	EventLoopBuilderHook, NativeOptions,
 };
+use image::Rgb;
 use log::{trace, warn};
 use std::{
 	fmt::Display,
@@ -136,27 +138,8 @@ impl Gui {
 
 	/// Draws a face
 	fn draw_face(ui: &Ui, face_for_gui: FaceForGUI) {
-		let (text, color) = match face_for_gui.annotation {
-			FaceForGUIAnnotation::Name(n) => (n, FACE_RECTANGLE_YELLOW_COLOR),
-			FaceForGUIAnnotation::Warning(w) => match w {
-				FaceForGUIAnnotationWarning::TooSmall => {
-					("Too small".to_owned(), FACE_RECTANGLE_GREY_COLOR)
-				}
-				FaceForGUIAnnotationWarning::NotRecognized => {
-					("Not recognized".to_owned(), FACE_RECTANGLE_GREY_COLOR)
-				}
-				FaceForGUIAnnotationWarning::TooManyFaces => {
-					("Too many faces".to_owned(), FACE_RECTANGLE_GREY_COLOR)
-				}
-			},
-			FaceForGUIAnnotation::ScanningState {
-				scanned_sample_count,
-				required_sample_count,
-			} => (
-				format!("Scanning: {scanned_sample_count}/{required_sample_count}",),
-				FACE_RECTANGLE_WHITE_COLOR,
-			),
-		};
+		let color = get_annotation_color(&face_for_gui.annotation);
+		let text = get_annotation_text(face_for_gui.annotation);
 		let (rectangles, top_right_position) = draw_poi_square(face_for_gui.rectangle);
 
 		ui.painter().text(
@@ -181,6 +164,73 @@ impl Gui {
 	}
 }
 
+/// Returns the color of the square around a face with the given annotation
+const fn get_annotation_color(annotation: &FaceForGUIAnnotation) -> Color32 {
+	match annotation {
+		FaceForGUIAnnotation::Name(_) => FACE_RECTANGLE_YELLOW_COLOR,
+		FaceForGUIAnnotation::Warning(_) => FACE_RECTANGLE_GREY_COLOR,
+		FaceForGUIAnnotation::ScanningState { .. } => FACE_RECTANGLE_WHITE_COLOR,
+	}
+}
+
+/// Returns the label shown next to a face with the given annotation
+pub fn get_annotation_text(annotation: FaceForGUIAnnotation) -> String {
+	match annotation {
+		FaceForGUIAnnotation::Name(n) => n,
+		FaceForGUIAnnotation::Warning(w) => match w {
+			FaceForGUIAnnotationWarning::TooSmall => "Too small".to_owned(),
+			FaceForGUIAnnotationWarning::NotRecognized => "Not recognized".to_owned(),
+			FaceForGUIAnnotationWarning::TooManyFaces => "Too many faces".to_owned(),
+			FaceForGUIAnnotationWarning::UnknownFacePresent => "Unknown face present".to_owned(),
+			FaceForGUIAnnotationWarning::NotLargest => "Not the closest face".to_owned(),
+		},
+		FaceForGUIAnnotation::ScanningState {
+			scanned_sample_count,
+			required_sample_count,
+			pose,
+			captured_pose_count,
+			required_pose_count,
+		} => pose.map_or_else(
+			|| format!("Scanning: {scanned_sample_count}/{required_sample_count}",),
+			|p| {
+				format!(
+					"{}\nStep {}/{required_pose_count}: {scanned_sample_count}/{required_sample_count}",
+					p.instruction(),
+					captured_pose_count + 1,
+				)
+			},
+		),
+	}
+}
+
+/// Draws the squares around the faces into the frame the same way as the window does, so that
+/// the annotated frame can be saved. The labels are not drawn as there is no font to render them
+pub fn annotate_frame(frame: &mut Frame, faces_for_gui: &[FaceForGUI]) {
+	for face_for_gui in faces_for_gui {
+		let color = get_annotation_color(&face_for_gui.annotation);
+		let (rectangles, _) = draw_poi_square(face_for_gui.rectangle);
+		for rectangle in rectangles {
+			fill_rectangle(frame, rectangle, Rgb([color.r(), color.g(), color.b()]));
+		}
+	}
+}
+
+/// Fills the part of the rectangle which is inside the frame
+fn fill_rectangle(frame: &mut Frame, rectangle: Rectangle<i32>, color: Rgb<u8>) {
+	// Negative coordinates are outside of the frame
+	let clamp = |value: i32, limit: u32| u32::try_from(value).map_or(0, |v| v.min(limit));
+	let min_x = clamp(rectangle.min.x.min(rectangle.max.x), frame.width());
+	let max_x = clamp(rectangle.min.x.max(rectangle.max.x), frame.width());
+	let min_y = clamp(rectangle.min.y.min(rectangle.max.y), frame.height());
+	let max_y = clamp(rectangle.min.y.max(rectangle.max.y), frame.height());
+
+	for y in min_y..max_y {
+		for x in min_x..max_x {
+			frame.put_pixel(x, y, color);
+		}
+	}
+}
+
 impl eframe::App for Gui {
 	fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
 		if self.finished.load(Ordering::SeqCst) {
@@ -7,6 +7,15 @@ macro_rules! log_and_print_error {
 	};
 }
 
+#[macro_export]
+macro_rules! log_and_print_info {
+	($($arg:tt)*) => {
+		let text = format!($($arg)*);
+		log::info!("{}", text);
+		println!("{}", text);
+	};
+}
+
 #[macro_export]
 macro_rules! log_and_print_warn {
 	($($arg:tt)*) => {
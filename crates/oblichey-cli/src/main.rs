@@ -1,5 +1,6 @@
 mod camera;
 mod config;
+mod evaluation;
 mod geometry;
 mod gui;
 mod logging;
@@ -7,45 +8,267 @@ mod models;
 mod processors;
 mod store;
 
-use camera::Frame;
+use camera::{replay, Frame};
+use chrono::{DateTime, Local, Utc};
 use clap::Parser;
-use config::Config;
+use config::{Backend, Config, DetectorConfig, SnapshotConfig, SnapshotContent};
+use evaluation::{
+	get_largest_face_embedding, Dataset, EvaluationReport, Scores,
+	DEFAULT_TARGET_FALSE_ACCEPT_RATE, MAX_TARGET_FALSE_ACCEPT_RATE,
+};
 use flexi_logger::{FileSpec, Logger};
-use log::trace;
-use processors::auth_processor::AuthProcessor;
-use processors::face::{FaceEmbedding, FaceForGUI};
+use log::{info, trace};
+use models::registry::{ModelRegistry, ModelsConfig};
+use processors::auth_processor::{AuthProcessor, AuthProcessorResult, AuthSettings, AuthSnapshot};
+use processors::calibration_processor::{CalibrationProcessor, DEFAULT_CALIBRATION_SAMPLE_COUNT};
+use processors::face::{
+	Aggregation, FaceEmbedding, FaceForGUI, FaceForGUIAnnotation, FaceTemplate, RecognizerId,
+	DEFAULT_SIMILARITY_THRESHOLD,
+};
 use processors::face_processor::FaceProcessor;
+use processors::frame_processor::{self, FrameProcessing};
+use processors::projection::Projection;
 use processors::scan_processor::ScanProcessor;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{self};
-use store::{get_log_directory, load_face_embeddings, remove_face_embedding, save_face_embedding};
+use std::time::Instant;
+use store::attempts::{AttemptTracker, Lockout, ATTEMPTS_DIRECTORY};
+use store::audit::{AuditEntry, AuditOutcome, AuditSummary};
+use store::export::ExportFile;
+use store::filesystem::FilesystemFaceStore;
+use store::keys::{ProjectionKey, StoreKeys, KEYS_DIRECTORY};
+use store::profile::{Calibration, FaceProfile};
+use store::snapshots::SnapshotStore;
+use store::{
+	get_current_user, get_embeddings_directory, get_face_templates, get_log_directory,
+	load_face_profiles, open_audit_log, open_face_store, save_face_profile, update_face_profile,
+	FaceStore,
+};
 
 const LOG_LEVEL: &str = "trace";
 const LOG_FILE_BASE_NAME: &str = "oblichey";
+/// The exit code of the auth subcommand when the user is locked out. The PAM module relies on it
+const LOCKED_OUT_EXIT_CODE: u8 = 2;
 
-#[derive(PartialEq, Eq, Debug, Clone, clap::Subcommand)]
+#[derive(PartialEq, Debug, Clone, clap::Subcommand)]
 enum Command {
 	/// Scan a new face
 	Scan {
 		/// Name of the newly scanned face
 		name: String,
+		/// Add the scan to the templates of an existing face instead of creating a new one
+		#[arg(long)]
+		append: bool,
+		/// Ask the user to take multiple poses and capture a template for each of them
+		#[arg(long)]
+		guided: bool,
+		/// Scan the face from a directory of images (such as a recording split into frames) instead
+		/// of the camera. Each image is used once, so there have to be enough frames of the face
+		#[arg(long)]
+		from: Option<PathBuf>,
 	},
 	/// Remove an existing scanned face
 	Remove {
 		/// Name of the scanned face to remove
 		name: String,
 	},
+	/// Rename an existing scanned face
+	Rename {
+		/// Current name of the scanned face
+		old_name: String,
+		/// New name of the scanned face
+		new_name: String,
+	},
+	/// Change the metadata of an existing scanned face
+	Edit {
+		/// Name of the scanned face to edit
+		name: String,
+		/// Name of the face shown to the user
+		#[arg(long)]
+		display_name: Option<String>,
+		/// Notes about the face, such as in which conditions it was scanned. An empty string removes
+		/// them
+		#[arg(long)]
+		notes: Option<String>,
+		/// Keep the face, but stop using it for authentication
+		#[arg(long, conflicts_with = "enable")]
+		disable: bool,
+		/// Use a disabled face for authentication again
+		#[arg(long)]
+		enable: bool,
+	},
+	/// Undo the adaptation of a scanned face to successful authentications
+	Rollback {
+		/// Name of the scanned face
+		name: String,
+		/// Remove all adapted templates instead of only the most recent one
+		#[arg(long)]
+		all: bool,
+	},
+	/// Calibrate the similarity threshold of a scanned face, so that faces of other people are
+	/// accepted at most as often as targeted. The samples of the face are captured using the
+	/// camera and compared with faces of other users and optionally with images of other people
+	Calibrate {
+		/// Name of the scanned face to calibrate
+		name: String,
+		#[command(flatten)]
+		options: CalibrationOptions,
+	},
 	/// List scanned faces
 	List,
+	/// Export a scanned face into a file, so that it can be backed up or imported on a different
+	/// machine
+	Export {
+		/// Name of the scanned face to export
+		name: String,
+		/// Path of the file to export the face into
+		file: PathBuf,
+		/// Encrypt the exported face using a passphrase
+		#[arg(long)]
+		encrypt: bool,
+	},
+	/// Import a face exported using the export subcommand
+	Import {
+		/// Path of the exported face
+		file: PathBuf,
+		/// Import the face under a different name than it was exported with
+		#[arg(long = "as", value_name = "NAME")]
+		name: Option<String>,
+	},
+	/// Find faces in images and print which scanned faces they are the most similar to. The images
+	/// are reshaped like frames from the camera, so the positions of the faces are in the reshaped
+	/// images. This can be used for debugging without a camera
+	Identify {
+		/// Paths of the images or of directories of images
+		#[arg(required = true)]
+		images: Vec<PathBuf>,
+		/// Save the reshaped images into this directory as PNGs named after them, with squares
+		/// around the faces colored like in the window of the test subcommand. Only the squares are
+		/// drawn, the names are printed
+		#[arg(long)]
+		output: Option<PathBuf>,
+	},
 	/// Opens a window with the camera's annotated output which can be used for testing if everything is
 	/// working as expected
 	Test,
+	/// Manage how scanned faces are stored
+	Store {
+		#[command(subcommand)]
+		command: StoreCommand,
+	},
 	/// This subcommand is mostly meant to be used by the PAM module. It authenticates the user.
-	/// It will return 0, if authentication was successful, and a non-zero error code, if it failed
-	Auth,
+	/// It will return 0, if authentication was successful, 2, if the user is locked out after too
+	/// many failed attempts, and a different non-zero error code, if it failed
+	Auth {
+		/// The PAM service which requested the authentication, used in the audit log
+		#[arg(long)]
+		service: Option<String>,
+		/// The user to authenticate, which is passed by the PAM module. Only root can authenticate
+		/// a user other than the one it runs as
+		#[arg(long)]
+		user: Option<String>,
+	},
+	/// Show the authentication attempts recorded in the audit log. This has to be run as root
+	Audit {
+		/// Only show attempts of this user
+		#[arg(long)]
+		user: Option<String>,
+		/// Only show attempts since this time, such as 2024-01-31T12:00:00Z
+		#[arg(long)]
+		since: Option<DateTime<Utc>>,
+		/// Only show attempts which did not succeed
+		#[arg(long)]
+		failed: bool,
+		/// Show the number of attempts of each user and the reasons of failures instead of the
+		/// individual attempts
+		#[arg(long)]
+		summary: bool,
+	},
+	/// Measure how often faces are falsely accepted or rejected using a directory of labelled
+	/// images. It has a subdirectory for each person with either images or subdirectories of
+	/// frames of recordings. This does not require Oblichey to be set up, so it can also be run on
+	/// build machines
+	Evaluate {
+		/// Path of the directory of labelled images
+		dataset: PathBuf,
+		/// Similarity thresholds to report the error rates at, separated by commas. The configured
+		/// threshold is used by default
+		#[arg(long, value_delimiter = ',')]
+		thresholds: Vec<f32>,
+		/// The backend to run the models on. Use ndarray to run them on the CPU
+		#[arg(long)]
+		backend: Option<Backend>,
+		/// Export the report including the similarity distributions and the ROC curve into this
+		/// JSON file
+		#[arg(long)]
+		output: Option<PathBuf>,
+	},
+	/// Lift the lockout of a user caused by too many failed authentication attempts. This has to
+	/// be run as root
+	Unlock {
+		/// Name of the user to unlock
+		user: String,
+	},
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, clap::Subcommand)]
+enum StoreCommand {
+	/// Rewrite all scanned faces of the current user in the current format. This has to be run as
+	/// root if the faces are encrypted. Faces of other users are only rewritten when they run it
+	Migrate {
+		/// Encrypt the templates using a key only root can read, generating the key if there is
+		/// none yet
+		#[arg(long)]
+		encrypt: bool,
+		/// Copy the faces from the state directory of the user into the configured store. This is
+		/// used when switching to another store backend. The original files are kept
+		#[arg(long)]
+		from_directory: bool,
+	},
+	/// Generate a new key and re-encrypt the scanned faces of the current user using it. Previous
+	/// keys are kept, so that faces of other users can still be used until they run `store migrate`
+	RotateKey,
+	/// Transform the templates of the current user using a secret random projection, so that
+	/// templates copied without the projection key cannot be compared with faces. The key is only
+	/// readable by the user and root. This has to be run as root
+	Project {
+		/// Replace the projection key of the user with a new one. Faces transformed using the
+		/// previous key cannot be used anymore and have to be re-scanned
+		#[arg(long)]
+		new_key: bool,
+	},
+}
+
+/// How a face is calibrated
+#[derive(PartialEq, Debug, Clone, clap::Args)]
+struct CalibrationOptions {
+	/// The highest acceptable share of faces of other people which are accepted, at most 0.01
+	#[arg(long = "target-far", value_name = "RATE", default_value_t = DEFAULT_TARGET_FALSE_ACCEPT_RATE)]
+	target_false_accept_rate: f32,
+	/// Directory of images of other people, laid out like the dataset of the evaluate subcommand
+	#[arg(long)]
+	impostors: Option<PathBuf>,
+	/// The number of samples of the face to capture
+	#[arg(long = "samples", value_name = "COUNT", default_value_t = DEFAULT_CALIBRATION_SAMPLE_COUNT)]
+	sample_count: usize,
+	/// Capture the samples from a directory of images (such as a recording split into frames)
+	/// instead of the camera. Each image is used once, so there have to be enough frames of the
+	/// face
+	#[arg(long)]
+	from: Option<PathBuf>,
+	/// Only show the proposed threshold without saving it
+	#[arg(long)]
+	dry_run: bool,
+	/// Remove the calibrated threshold, so that the configured one is used again
+	#[arg(long, conflicts_with_all = ["target_false_accept_rate", "impostors", "sample_count", "from", "dry_run"])]
+	reset: bool,
 }
 
 #[derive(clap::Parser, Debug)]
@@ -77,6 +300,21 @@ fn main() -> ExitCode {
 	log_panics::init();
 
 	let args = Args::parse();
+	// Evaluating uses neither the camera nor the scanned faces, so it is handled before they are
+	// required to be set up
+	if let Command::Evaluate {
+		dataset,
+		thresholds,
+		backend,
+		output,
+	} = args.command
+	{
+		if let Err(e) = evaluate(&dataset, thresholds, backend, output.as_deref()) {
+			log_and_print_error!("{e}");
+			return ExitCode::FAILURE;
+		}
+		return ExitCode::SUCCESS;
+	}
 	let config = match Config::load() {
 		Ok(c) => c,
 		Err(e) => {
@@ -84,102 +322,1498 @@ fn main() -> ExitCode {
 			return ExitCode::FAILURE;
 		}
 	};
-	let face_embeddings = match load_face_embeddings() {
-		Ok(e) => e,
+	let (model_registry, recognizer_id) = match load_models(&config.models) {
+		Ok(m) => m,
+		Err(e) => {
+			log_and_print_error!("{e}");
+			return ExitCode::FAILURE;
+		}
+	};
+	let requested_user = match &args.command {
+		Command::Auth { user, .. } => user.as_deref(),
+		_ => None,
+	};
+	let user = match get_current_user(requested_user) {
+		Ok(u) => u,
+		Err(e) => {
+			log_and_print_error!("Failed to get the current user: {e}");
+			return ExitCode::FAILURE;
+		}
+	};
+	let face_store = match open_face_store(&config.store, &user) {
+		Ok(s) => s,
+		Err(e) => {
+			log_and_print_error!("Failed to open face store: {e}");
+			return ExitCode::FAILURE;
+		}
+	};
+	let face_profiles = match load_face_profiles(face_store.as_ref()) {
+		Ok(p) => p,
 		Err(e) => {
 			log_and_print_error!("Failed to load face scans: {e}");
 			return ExitCode::FAILURE;
 		}
 	};
 
-	handle_command(args.command, &config, face_embeddings)
+	handle_command(
+		args.command,
+		&config,
+		&model_registry,
+		&recognizer_id,
+		&user,
+		face_store.as_ref(),
+		face_profiles,
+	)
 }
 
 fn handle_command(
 	command: Command,
 	config: &Config,
-	face_embeddings: HashMap<String, FaceEmbedding>,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+	user: &str,
+	face_store: &dyn FaceStore,
+	mut face_profiles: HashMap<String, FaceProfile>,
 ) -> ExitCode {
 	trace!("Handling command: {command:?}");
 
-	match command {
-		Command::Remove { name } => {
-			if !face_embeddings.contains_key(&name) {
-				log_and_print_error!("Face scan of this name does not exist.");
-				return ExitCode::FAILURE;
-			}
-			if let Err(e) = remove_face_embedding(&name) {
-				log_and_print_error!("Failed remove face scan: {e}");
-				return ExitCode::FAILURE;
+	let result = match command {
+		Command::Remove { name } => remove_face(&name, face_store),
+		Command::Rename { old_name, new_name } => rename_face(&old_name, &new_name, face_store),
+		Command::Edit {
+			name,
+			display_name,
+			notes,
+			disable,
+			enable,
+		} => edit_face(
+			&name,
+			display_name.as_deref(),
+			notes.as_deref(),
+			(disable || enable).then_some(disable),
+			face_store,
+		),
+		Command::Rollback { name, all } => roll_back_face(&name, all, face_store, face_profiles),
+		Command::Calibrate { name, options } => handle_calibrate_command(
+			&name,
+			&options,
+			config,
+			model_registry,
+			recognizer_id,
+			user,
+			face_store,
+			face_profiles,
+		),
+		Command::List => {
+			list_faces(face_profiles, recognizer_id);
+			Ok(())
+		}
+		Command::Export {
+			name,
+			file,
+			encrypt,
+		} => export_face(&name, &file, encrypt, face_profiles),
+		Command::Import { file, name } => import_face(&file, name, recognizer_id, face_store),
+		Command::Store { command } => {
+			handle_store_command(&command, user, face_store, &mut face_profiles)
+		}
+		Command::Identify { images, output } => identify_faces(
+			&images,
+			output.as_deref(),
+			config,
+			model_registry,
+			recognizer_id,
+			user,
+			face_profiles,
+		),
+		Command::Test => run_test(face_profiles, config, model_registry, recognizer_id, user),
+		Command::Auth { service, .. } => {
+			return run_auth(
+				service,
+				user,
+				face_store,
+				face_profiles,
+				config,
+				model_registry,
+				recognizer_id,
+			)
+		}
+		Command::Audit {
+			user,
+			since,
+			failed,
+			summary,
+		} => show_audit_log(config, user.as_deref(), since, failed, summary),
+		Command::Evaluate {
+			dataset,
+			thresholds,
+			backend,
+			output,
+		} => evaluate(&dataset, thresholds, backend, output.as_deref()),
+		Command::Unlock { user } => unlock_user(&user, config),
+		Command::Scan {
+			name,
+			append,
+			guided,
+			from,
+		} => scan_face(
+			&name,
+			append,
+			guided,
+			from,
+			config,
+			model_registry,
+			recognizer_id,
+			user,
+			face_store,
+			face_profiles,
+		),
+	};
+
+	if let Err(e) = result {
+		log_and_print_error!("{e}");
+		return ExitCode::FAILURE;
+	}
+
+	ExitCode::SUCCESS
+}
+
+/// Loads the models and verifies their integrity
+fn load_models(models_config: &ModelsConfig) -> Result<(ModelRegistry, RecognizerId), String> {
+	let model_registry = ModelRegistry::from_config(models_config)
+		.map_err(|e| format!("Failed to load models: {e}"))?;
+	if let Err(e) = model_registry.verify_integrity() {
+		return Err(format!(
+			"Refusing to continue as the models could not be verified: {e}"
+		));
+	}
+	let recognizer_id = model_registry
+		.recognizer_id()
+		.map_err(|e| format!("Failed to identify the recognizer model: {e}"))?;
+
+	Ok((model_registry, recognizer_id))
+}
+
+/// Runs the models over a labelled dataset and reports how often faces are falsely accepted or
+/// rejected. The config is optional, so that this works on machines where Oblichey is not set up
+fn evaluate(
+	dataset: &Path,
+	thresholds: Vec<f32>,
+	backend: Option<Backend>,
+	output: Option<&Path>,
+) -> Result<(), String> {
+	let config = Config::load_optional().map_err(|e| format!("Failed to load config: {e}"))?;
+	let config = config.as_ref();
+	if thresholds.iter().any(|t| !(-1.0..=1.0).contains(t)) {
+		return Err(String::from("Thresholds must be between -1 and 1"));
+	}
+	let thresholds = if thresholds.is_empty() {
+		vec![config.map_or(DEFAULT_SIMILARITY_THRESHOLD, |c| {
+			c.auth.similarity_threshold
+		})]
+	} else {
+		thresholds
+	};
+
+	let (model_registry, _) = load_models(&config.map(|c| c.models.clone()).unwrap_or_default())?;
+	let frame_processor = frame_processor::create_frame_processor(
+		backend
+			.or_else(|| config.map(|c| c.inference.backend))
+			.unwrap_or_default(),
+		&model_registry,
+		config.map_or_else(DetectorConfig::default, |c| c.detector),
+	)
+	.map_err(|e| format!("Failed to create frame processor: {e}"))?;
+
+	let dataset = Dataset::load(dataset, |frame| {
+		get_largest_face_embedding(frame_processor.process_frame(frame))
+	})
+	.map_err(|e| format!("Failed to load the dataset: {e}"))?;
+	let report = EvaluationReport::new(&dataset, &thresholds)
+		.map_err(|e| format!("Failed to evaluate: {e}"))?;
+	print!("{report}");
+	if let Some(output) = output {
+		report
+			.export(output)
+			.map_err(|e| format!("Failed to export the report: {e}"))?;
+		println!("The report was exported to {}", output.display());
+	}
+
+	Ok(())
+}
+
+/// Changes the metadata of the face. Only the given values are changed. The face is changed
+/// while no other process can change it, so that templates added in the meantime are kept
+fn edit_face(
+	name: &str,
+	display_name: Option<&str>,
+	notes: Option<&str>,
+	disabled: Option<bool>,
+	face_store: &dyn FaceStore,
+) -> Result<(), String> {
+	let result = face_store.update(name, &|face_profile| {
+		let Some(mut face_profile) = face_profile else {
+			return Err(store::Error::NotFound(name.to_owned()));
+		};
+		if let Some(display_name) = display_name {
+			display_name.clone_into(&mut face_profile.display_name);
+		}
+		if let Some(notes) = notes {
+			face_profile.notes = Some(notes.to_owned()).filter(|n| !n.is_empty());
+		}
+		if let Some(disabled) = disabled {
+			face_profile.disabled = disabled;
+		}
+		face_profile.updated_at = Utc::now();
+
+		Ok(face_profile)
+	});
+
+	match result {
+		Ok(()) => Ok(()),
+		Err(store::Error::NotFound(_)) => {
+			Err(String::from("Face scan of this name does not exist."))
+		}
+		Err(e) => Err(format!("Failed to save face scan: {e}")),
+	}
+}
+
+/// Prints the names of the faces along with their display names and state
+fn list_faces(face_profiles: HashMap<String, FaceProfile>, recognizer_id: &RecognizerId) {
+	for (name, face_profile) in face_profiles {
+		let mut labels = Vec::new();
+		if face_profile.display_name != name {
+			labels.push(face_profile.display_name.clone());
+		}
+		if face_profile.disabled {
+			labels.push(String::from("disabled"));
+		}
+		if face_profile.needs_reenrollment(recognizer_id) {
+			labels.push(String::from("reenroll-needed"));
+		}
+		if let Some(calibration) = face_profile.calibration {
+			labels.push(format!(
+				"calibrated threshold {:.3}",
+				calibration.similarity_threshold
+			));
+		}
+		if labels.is_empty() {
+			println!("{name}");
+		} else {
+			println!("{name} ({})", labels.join(", "));
+		}
+	}
+}
+
+/// Removes the face from the store
+fn remove_face(name: &str, face_store: &dyn FaceStore) -> Result<(), String> {
+	match face_store.delete(name) {
+		Ok(()) => Ok(()),
+		Err(store::Error::NotFound(_)) => {
+			Err(String::from("Face scan of this name does not exist."))
+		}
+		Err(e) => Err(format!("Failed remove face scan: {e}")),
+	}
+}
+
+/// Renames the face. If its display name is the same as its name, it is changed as well
+fn rename_face(old_name: &str, new_name: &str, face_store: &dyn FaceStore) -> Result<(), String> {
+	match face_store.rename(old_name, new_name) {
+		Ok(()) => {}
+		Err(store::Error::NotFound(_)) => {
+			return Err(String::from("Face scan of this name does not exist."))
+		}
+		Err(store::Error::AlreadyExists(_)) => {
+			return Err(String::from("Face scan of the new name already exists."))
+		}
+		Err(e) => return Err(format!("Failed to rename face scan: {e}")),
+	}
+
+	// The face is changed while no other process can change it, so that templates added in the
+	// meantime are kept
+	let result = face_store.update(new_name, &|face_profile| {
+		let Some(mut face_profile) = face_profile else {
+			return Err(store::Error::NotFound(new_name.to_owned()));
+		};
+		if face_profile.display_name == old_name {
+			new_name.clone_into(&mut face_profile.display_name);
+			face_profile.updated_at = Utc::now();
+		}
+
+		Ok(face_profile)
+	});
+	if let Err(e) = result {
+		log_and_print_warn!("Failed to update the display name of the face scan: {e}");
+	}
+
+	Ok(())
+}
+
+fn export_face(
+	name: &str,
+	file: &Path,
+	encrypt: bool,
+	mut face_profiles: HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	if let Err(e) = unseal_face_profiles(&mut face_profiles) {
+		return Err(format!("Failed to decrypt face scans: {e}"));
+	}
+	let Some(face_profile) = face_profiles.get(name) else {
+		return Err(String::from(
+			"Face of this name does not exist or could not be loaded.",
+		));
+	};
+	let passphrase = if encrypt {
+		match prompt_passphrase(true) {
+			Ok(p) => Some(p),
+			Err(e) => return Err(format!("Failed to get passphrase: {e}")),
+		}
+	} else {
+		None
+	};
+	let export_file = match ExportFile::new(name, face_profile, passphrase.as_deref()) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("Failed to export face: {e}")),
+	};
+	if let Err(e) = export_file.save(file) {
+		return Err(format!("Failed to write {}: {e}", file.display()));
+	}
+	println!("Face was exported to {}", file.display());
+
+	Ok(())
+}
+
+fn import_face(
+	file: &Path,
+	name: Option<String>,
+	recognizer_id: &RecognizerId,
+	face_store: &dyn FaceStore,
+) -> Result<(), String> {
+	let export_file = match ExportFile::load(file) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("Failed to read {}: {e}", file.display())),
+	};
+	let exported_recognizer_ids = export_file.recognizer_ids();
+	if exported_recognizer_ids.is_empty() {
+		log_and_print_warn!("The face does not record which recognizer model scanned it, so it cannot be verified that it is compatible with this machine");
+	} else if !exported_recognizer_ids.contains(recognizer_id) {
+		let exported_recognizer_ids: Vec<String> = exported_recognizer_ids
+			.iter()
+			.map(ToString::to_string)
+			.collect();
+		return Err(format!(
+			"The face was scanned using a different recognizer model ({}) than the one used on this machine ({recognizer_id}), so it cannot be imported",
+			exported_recognizer_ids.join(", ")
+		));
+	}
+
+	let passphrase = if export_file.is_encrypted() {
+		match prompt_passphrase(false) {
+			Ok(p) => Some(p),
+			Err(e) => return Err(format!("Failed to get passphrase: {e}")),
+		}
+	} else {
+		None
+	};
+	let (exported_name, face_profile) = match export_file.open(passphrase.as_deref()) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("Failed to import face: {e}")),
+	};
+	let name = name.unwrap_or(exported_name);
+	match face_store.contains(&name) {
+		Ok(false) => {}
+		Ok(true) => return Err(String::from("Face of this name already exists. Either remove the existing face or import it under a different name using --as.")),
+		Err(e) => return Err(format!("Failed to check whether the face scan exists: {e}")),
+	}
+
+	let incompatible_template_count = face_profile
+		.templates
+		.iter()
+		.filter(|t| !t.is_compatible_with(recognizer_id))
+		.count();
+	if incompatible_template_count > 0 {
+		log_and_print_warn!("{incompatible_template_count} of the templates were scanned using a different recognizer model and will be ignored");
+	}
+	if let Err(e) = save_face_profile(face_store, &name, &face_profile) {
+		return Err(format!("Failed to save face scan: {e}"));
+	};
+	println!("Face was imported as {name}");
+
+	Ok(())
+}
+
+/// Creates an `AuthProcessor` comparing against the templates of all faces which can be used
+fn create_auth_processor(
+	mut face_profiles: HashMap<String, FaceProfile>,
+	config: &Config,
+	recognizer_id: &RecognizerId,
+	user: &str,
+	testing_mode: bool,
+) -> Result<AuthProcessor, String> {
+	if let Err(e) = unseal_face_profiles(&mut face_profiles) {
+		return Err(format!("Failed to decrypt face scans: {e}"));
+	}
+	let projection = load_projection(user)?;
+	let face_similarity_thresholds = face_profiles
+		.iter()
+		.filter_map(|(name, face_profile)| {
+			let calibration = face_profile.calibration?;
+			Some((
+				name.clone(),
+				config
+					.auth
+					.get_similarity_threshold(Some(user), Some(calibration.similarity_threshold)),
+			))
+		})
+		.collect();
+	let face_templates = get_face_templates(face_profiles, projection.as_ref());
+	if !testing_mode {
+		if face_templates.is_empty() {
+			return Err(String::from(
+				"No faces have been scanned yet or all of them are disabled",
+			));
+		}
+		if !face_templates
+			.values()
+			.flatten()
+			.any(|t| t.is_compatible_with(recognizer_id))
+		{
+			return Err(String::from("All faces were scanned using a different recognizer model and have to be re-scanned"));
+		}
+	}
+
+	Ok(AuthProcessor::new(
+		face_templates,
+		recognizer_id,
+		AuthSettings {
+			keep_snapshot: config.snapshots.enabled,
+			..config.auth.get_settings(Some(user))
+		},
+		projection,
+		testing_mode,
+	)
+	.with_face_similarity_thresholds(face_similarity_thresholds))
+}
+
+/// Starts an authentication attempt of the user, so that it is counted as failed unless it
+/// succeeds. Returns the time until which the user is locked out, if they are
+fn begin_auth_attempt(lockout: Lockout, user: &str) -> Result<Option<DateTime<Utc>>, String> {
+	if !lockout.enabled {
+		return Ok(None);
+	}
+
+	match AttemptTracker::new(PathBuf::from(ATTEMPTS_DIRECTORY), lockout)
+		.begin_attempt(user, Utc::now())
+	{
+		Ok(l) => Ok(l),
+		// Processes not running as root, such as screen lockers, cannot access the attempts
+		Err(store::Error::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+			if !lockout.allow_unprivileged {
+				return Err(String::from("Failed authentication attempts cannot be counted without running as root. Set lockout.allow_unprivileged to authenticate without the lockout."));
 			}
+			log_and_print_warn!("Failed authentication attempts cannot be counted without running as root, so the lockout is not enforced");
+			Ok(None)
 		}
-		Command::List => {
-			for (name, _) in face_embeddings {
-				println!("{name}",);
+		Err(e) => Err(format!("Failed to record the authentication attempt: {e}")),
+	}
+}
+
+/// Lifts the lockout of the user
+fn unlock_user(user: &str, config: &Config) -> Result<(), String> {
+	AttemptTracker::new(PathBuf::from(ATTEMPTS_DIRECTORY), config.lockout)
+		.unlock(user)
+		.map_err(|e| format!("Failed to unlock {user}: {e}"))
+}
+
+/// Shows the window of the test subcommand until it is closed
+fn run_test(
+	face_profiles: HashMap<String, FaceProfile>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+	user: &str,
+) -> Result<(), String> {
+	let auth_processor = create_auth_processor(face_profiles, config, recognizer_id, user, true)?;
+	start_threads(
+		Arc::new(Mutex::new(auth_processor)),
+		config,
+		model_registry,
+		true,
+	);
+	Ok(())
+}
+
+/// Authenticates the user and records the attempt in the audit log
+fn run_auth(
+	service: Option<String>,
+	user: &str,
+	face_store: &dyn FaceStore,
+	face_profiles: HashMap<String, FaceProfile>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+) -> ExitCode {
+	let start_time = Instant::now();
+	let mut audit_entry = AuditEntry::new(
+		Utc::now(),
+		service,
+		Some(user.to_owned()),
+		AuditOutcome::Error,
+	);
+
+	let exit_code = match begin_auth_attempt(config.lockout, user) {
+		Ok(Some(locked_until)) => {
+			let reason = format!(
+				"Too many failed attempts - authentication is locked until {}",
+				locked_until
+					.with_timezone(&Local)
+					.format("%Y-%m-%d %H:%M:%S")
+			);
+			log_and_print_error!("{reason}");
+			audit_entry.outcome = AuditOutcome::LockedOut;
+			audit_entry.reason = Some(reason);
+			ExitCode::from(LOCKED_OUT_EXIT_CODE)
+		}
+		Ok(None) => match authenticate(
+			user,
+			face_store,
+			face_profiles,
+			config,
+			model_registry,
+			recognizer_id,
+		) {
+			Ok(result) => {
+				audit_entry.reason = result.failure_reason.map(|r| r.to_string());
+				audit_entry.matched_face = result.matched_face;
+				audit_entry.best_similarity = result.best_similarity;
+				audit_entry.frame_count = result.frame_count;
+				if result.authenticated {
+					audit_entry.outcome = AuditOutcome::Success;
+					println!("Authenticated!");
+					ExitCode::SUCCESS
+				} else {
+					audit_entry.outcome = AuditOutcome::Failure;
+					audit_entry.snapshot =
+						save_snapshot(result.snapshot.as_ref(), &config.snapshots, user);
+					log_and_print_error!(
+						"Authentication failed: {}",
+						audit_entry.reason.as_deref().unwrap_or("unknown reason")
+					);
+					ExitCode::FAILURE
+				}
 			}
+			Err(e) => {
+				log_and_print_error!("{e}");
+				audit_entry.reason = Some(e);
+				ExitCode::FAILURE
+			}
+		},
+		Err(e) => {
+			log_and_print_error!("{e}");
+			audit_entry.reason = Some(e);
+			ExitCode::FAILURE
 		}
-		Command::Test => {
-			let auth_processor = Arc::new(Mutex::new(AuthProcessor::new(face_embeddings, true)));
-			start_threads(auth_processor, config, true);
+	};
+
+	audit_entry.duration_ms = start_time
+		.elapsed()
+		.as_millis()
+		.try_into()
+		.unwrap_or(u64::MAX);
+	if config.audit.enabled {
+		if let Err(e) =
+			open_audit_log(&config.audit, &config.store).and_then(|l| l.append(&audit_entry))
+		{
+			log_and_print_warn!(
+				"Failed to record the authentication attempt in the audit log: {e}"
+			);
+		}
+	}
+
+	exit_code
+}
+
+/// Saves the snapshot of a failed authentication attempt, if enabled. Returns its path
+fn save_snapshot(
+	snapshot: Option<&AuthSnapshot>,
+	snapshot_config: &SnapshotConfig,
+	user: &str,
+) -> Option<PathBuf> {
+	if !snapshot_config.enabled {
+		return None;
+	}
+	let snapshot = snapshot?;
+	let image = match snapshot_config.content {
+		SnapshotContent::Face => snapshot
+			.get_face_image()
+			.unwrap_or_else(|| snapshot.frame.clone()),
+		SnapshotContent::Frame => snapshot.frame.clone(),
+	};
+
+	match SnapshotStore::new(
+		snapshot_config.directory.clone(),
+		snapshot_config.max_count,
+		snapshot_config.max_age,
+	)
+	.save(&image, user, Utc::now())
+	{
+		Ok(p) => Some(p),
+		Err(e) => {
+			log_and_print_warn!("Failed to save the snapshot of the authentication attempt: {e}");
+			None
 		}
-		Command::Auth => {
-			if face_embeddings.is_empty() {
-				log_and_print_error!("No faces have been scanned yet");
-				return ExitCode::FAILURE;
+	}
+}
+
+fn authenticate(
+	user: &str,
+	face_store: &dyn FaceStore,
+	face_profiles: HashMap<String, FaceProfile>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+) -> Result<AuthProcessorResult, String> {
+	let auth_processor = Arc::new(Mutex::new(create_auth_processor(
+		face_profiles,
+		config,
+		recognizer_id,
+		user,
+		false,
+	)?));
+
+	start_threads(auth_processor.clone(), config, model_registry, false);
+
+	let result = match auth_processor.lock() {
+		Ok(l) => l.get_result(),
+		Err(e) => return Err(format!("Failed to get lock: {e}")),
+	};
+	let Some(result) = result else {
+		return Err(String::from("Getting auth result failed!"));
+	};
+	if result.authenticated && config.lockout.enabled {
+		let attempt_tracker =
+			AttemptTracker::new(PathBuf::from(ATTEMPTS_DIRECTORY), config.lockout);
+		match attempt_tracker.record_success(user) {
+			Ok(()) => {}
+			// The attempts were not counted, which was already reported
+			Err(store::Error::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied => {}
+			Err(e) => {
+				log_and_print_warn!("Failed to reset the failed authentication attempts: {e}");
 			}
-			let auth_processor = Arc::new(Mutex::new(AuthProcessor::new(face_embeddings, false)));
+		}
+	}
+	if result.authenticated && config.adaptation.enabled {
+		if let Err(e) = adapt_face(&result, config, user, face_store, recognizer_id) {
+			log_and_print_warn!("Failed to adapt the face scan: {e}");
+		}
+	}
 
-			start_threads(auth_processor.clone(), config, false);
+	Ok(result)
+}
 
-			let auth_processor_lock = match auth_processor.lock() {
-				Ok(l) => l,
-				Err(e) => {
-					log_and_print_error!("Failed to get lock: {e}");
-					return ExitCode::FAILURE;
-				}
-			};
-			let Some(result) = auth_processor_lock.get_result() else {
-				log_and_print_error!("Getting auth result failed!");
-				return ExitCode::FAILURE;
+/// Learns from the embedding the user was authenticated with, so that the face keeps being
+/// recognized as it changes. Authentications which were not confident enough are skipped
+fn adapt_face(
+	result: &AuthProcessorResult,
+	config: &Config,
+	user: &str,
+	face_store: &dyn FaceStore,
+	recognizer_id: &RecognizerId,
+) -> Result<(), String> {
+	let (Some(name), Some((embedding, similarity))) =
+		(&result.matched_face, result.matched_embedding)
+	else {
+		return Ok(());
+	};
+	let face_profile = match face_store.get(name) {
+		Ok(Some(p)) => p,
+		Ok(None) => return Err(format!("Face scan {name} does not exist anymore")),
+		Err(e) => return Err(format!("Failed to load face scan {name}: {e}")),
+	};
+	let similarity_threshold = config.auth.get_similarity_threshold(
+		Some(user),
+		face_profile.calibration.map(|c| c.similarity_threshold),
+	);
+	if similarity < similarity_threshold + config.adaptation.margin {
+		info!("Not adapting face scan {name} as the authentication was not confident enough");
+		return Ok(());
+	}
+
+	// The face is read again while the store is locked, so that changes made by other processes
+	// since it was loaded are not lost
+	let update = |face_profile: Option<FaceProfile>| {
+		let Some(mut face_profile) = face_profile else {
+			return Err(store::Error::NotFound(name.clone()));
+		};
+		if face_profile.is_sealed() {
+			let Some(keys) = StoreKeys::load(Path::new(KEYS_DIRECTORY))? else {
+				return Err(store::Error::KeyUnavailable);
 			};
-			drop(auth_processor_lock);
-			if result.authenticated {
-				println!("Authenticated!");
-			} else {
-				println!("Authentication failed!");
-				return ExitCode::FAILURE;
-			}
+			face_profile.unseal(&keys)?;
+		}
+		face_profile.adapt(
+			&embedding,
+			recognizer_id,
+			config.adaptation.influence,
+			config.adaptation.max_history,
+			config.adaptation.max_per_day,
+		)?;
+		Ok(face_profile)
+	};
+	match update_face_profile(face_store, name, &update) {
+		Ok(()) => {
+			info!("Adapted face scan {name}");
+			Ok(())
 		}
-		Command::Scan { name } => {
-			if face_embeddings.contains_key(&name) {
-				log_and_print_error!("Face of this name already exists. Either pick a different name or remove the existing face.");
-				return ExitCode::FAILURE;
+		Err(store::Error::AdaptationLimitReached) => {
+			info!("Not adapting face scan {name} as it was adapted too many times within the last day");
+			Ok(())
+		}
+		Err(e) => Err(format!("Failed to adapt face scan {name}: {e}")),
+	}
+}
+
+/// Removes the most recent or all templates of the face which were learned from authentications
+fn roll_back_face(
+	name: &str,
+	all: bool,
+	face_store: &dyn FaceStore,
+	mut face_profiles: HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	let Some(face_profile) = face_profiles.remove(name) else {
+		return Err(String::from(
+			"Face of this name does not exist or could not be loaded.",
+		));
+	};
+	let mut face_profile = unseal_face_profile(name, face_profile)?;
+
+	let removed_count = face_profile.roll_back(if all { usize::MAX } else { 1 }, false);
+	if removed_count == 0 {
+		println!("Face scan {name} has no adapted templates");
+		return Ok(());
+	}
+	save_face_profile(face_store, name, &face_profile)
+		.map_err(|e| format!("Failed to save face scan: {e}"))?;
+	println!("Removed {removed_count} adapted templates of face scan {name}");
+
+	Ok(())
+}
+
+/// Calibrates the similarity threshold of a face or removes the calibrated one
+#[allow(clippy::too_many_arguments)]
+fn handle_calibrate_command(
+	name: &str,
+	options: &CalibrationOptions,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+	user: &str,
+	face_store: &dyn FaceStore,
+	face_profiles: HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	if options.reset {
+		return reset_calibration(name, face_store, face_profiles);
+	}
+	calibrate_face(
+		name,
+		options,
+		config,
+		model_registry,
+		recognizer_id,
+		user,
+		face_store,
+		face_profiles,
+	)
+}
+
+/// Captures samples of the face and proposes the lowest similarity threshold which accepts faces
+/// of other people at most as often as targeted. The threshold is saved into the face unless it is
+/// a dry run
+#[allow(clippy::too_many_arguments)]
+fn calibrate_face(
+	name: &str,
+	options: &CalibrationOptions,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+	user: &str,
+	face_store: &dyn FaceStore,
+	mut face_profiles: HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	if !(options.target_false_accept_rate > 0.0
+		&& options.target_false_accept_rate <= MAX_TARGET_FALSE_ACCEPT_RATE)
+	{
+		return Err(format!(
+			"The target false accept rate must be above 0 and at most {MAX_TARGET_FALSE_ACCEPT_RATE}"
+		));
+	}
+	let Some(face_profile) = face_profiles.remove(name) else {
+		return Err(String::from(
+			"Face of this name does not exist or could not be loaded.",
+		));
+	};
+	let mut face_profile = unseal_face_profile(name, face_profile)?;
+	let projection = load_projection(user)?;
+	if face_profile.projection_id.as_deref() != projection.as_ref().map(Projection::id) {
+		return Err(String::from("The templates of this face were not transformed using the current projection key - run `sudo oblichey-cli store project` or re-scan the face"));
+	}
+	let gallery: Vec<FaceEmbedding> = face_profile
+		.templates
+		.iter()
+		.filter(|t| t.is_compatible_with(recognizer_id))
+		.map(|t| t.embedding)
+		.collect();
+	if gallery.is_empty() {
+		return Err(String::from(
+			"The face was scanned using a different recognizer model and has to be re-scanned",
+		));
+	}
+	let impostor_embeddings = get_impostor_embeddings(
+		&face_profile,
+		face_profiles,
+		options.impostors.as_deref(),
+		projection.as_ref(),
+		config,
+		model_registry,
+		recognizer_id,
+	)?;
+	// Every impostor is a chance of at most 1 in their number to be accepted, so a lower rate
+	// cannot be shown. The target is positive, so the count is as well
+	#[allow(clippy::cast_sign_loss)]
+	let required_impostor_count = options.target_false_accept_rate.recip().ceil() as usize;
+	if impostor_embeddings.len() < required_impostor_count {
+		return Err(format!("{} faces of other people were found, but {required_impostor_count} are needed to calibrate for a false accept rate of {}. Use --impostors with more images of other people or a higher --target-far.", impostor_embeddings.len(), options.target_false_accept_rate));
+	}
+	let sample_embeddings =
+		capture_calibration_samples(options, projection.as_ref(), config, model_registry)?;
+
+	let scores = get_calibration_scores(
+		&gallery,
+		&sample_embeddings,
+		&impostor_embeddings,
+		config.auth.aggregation,
+	);
+	let Some(error_rates) =
+		scores.get_threshold_for_false_accept_rate(options.target_false_accept_rate)
+	else {
+		return Err(String::from("Not enough samples were captured"));
+	};
+	let user = face_profile.owner.as_deref().unwrap_or(user);
+	let current_rates = scores.get_error_rates(config.auth.get_similarity_threshold(
+		Some(user),
+		face_profile.calibration.map(|c| c.similarity_threshold),
+	));
+
+	println!(
+		"Compared {} samples of {name} with {} faces of other people",
+		sample_embeddings.len(),
+		impostor_embeddings.len()
+	);
+	println!(
+		"Current threshold {:.3}: {current_rates}",
+		current_rates.threshold
+	);
+	println!(
+		"Proposed threshold {:.3}: {error_rates}",
+		error_rates.threshold
+	);
+	println!("A higher threshold accepts other people less often (FAR), but also rejects you more often (FRR). The rates were measured now, with this camera and lighting, so they may differ in other conditions.");
+	if config
+		.auth
+		.users
+		.get(user)
+		.is_some_and(|c| c.similarity_threshold.is_some())
+	{
+		log_and_print_warn!("The similarity threshold is overridden for this user in the config, so the calibrated one is not going to be used");
+	} else if error_rates.threshold < config.auth.similarity_threshold {
+		log_and_print_warn!("The proposed threshold is lower than the configured one, which is going to be used instead");
+	}
+	if options.dry_run {
+		return Ok(());
+	}
+
+	face_profile.calibration = Some(Calibration {
+		similarity_threshold: error_rates.threshold,
+		target_false_accept_rate: options.target_false_accept_rate,
+		false_accept_rate: error_rates.false_accept_rate,
+		false_reject_rate: error_rates.false_reject_rate,
+		calibrated_at: Utc::now(),
+	});
+	face_profile.updated_at = Utc::now();
+	save_face_profile(face_store, name, &face_profile)
+		.map_err(|e| format!("Failed to save face scan: {e}"))?;
+	println!("The calibrated threshold was saved");
+
+	Ok(())
+}
+
+/// Returns the embeddings of other people to compare the face with: the templates of faces of
+/// other users and the images of other people in the given directory. Faces whose owner is not
+/// known are skipped, as they might belong to the same person
+#[allow(clippy::too_many_arguments)]
+fn get_impostor_embeddings(
+	face_profile: &FaceProfile,
+	mut face_profiles: HashMap<String, FaceProfile>,
+	impostors: Option<&Path>,
+	projection: Option<&Projection>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+) -> Result<Vec<FaceEmbedding>, String> {
+	if let Err(e) = unseal_face_profiles(&mut face_profiles) {
+		log_and_print_warn!("Encrypted faces of other users are not used: {e}");
+	}
+	let mut impostor_embeddings: Vec<FaceEmbedding> = face_profiles
+		.values()
+		.filter(|p| p.owner.is_some() && p.owner != face_profile.owner)
+		.filter(|p| p.projection_id == face_profile.projection_id)
+		.flat_map(|p| &p.templates)
+		.filter(|t| t.is_compatible_with(recognizer_id))
+		.map(|t| t.embedding)
+		.collect();
+	let stored_impostor_count = impostor_embeddings.len();
+	if stored_impostor_count == 0 {
+		// Only the faces in the store of the user are loaded, so this is the usual case unless faces
+		// of other people were imported into it
+		log_and_print_warn!("No scanned faces of other users can be compared with, so only the images of other people given using --impostors are used");
+	}
+
+	if let Some(impostors) = impostors {
+		let frame_processor = frame_processor::create_frame_processor(
+			config.inference.backend,
+			model_registry,
+			config.detector,
+		)
+		.map_err(|e| format!("Failed to create frame processor: {e}"))?;
+		let dataset = Dataset::load(impostors, |frame| {
+			get_largest_face_embedding(frame_processor.process_frame(frame))
+		})
+		.map_err(|e| format!("Failed to load the images of other people: {e}"))?;
+		impostor_embeddings.extend(
+			dataset
+				.samples
+				.into_iter()
+				.map(|s| projection.map_or(s.embedding, |p| p.project(&s.embedding))),
+		);
+	}
+	println!(
+		"Using {stored_impostor_count} templates of scanned faces of other users and {} images of other people",
+		impostor_embeddings.len() - stored_impostor_count
+	);
+
+	Ok(impostor_embeddings)
+}
+
+/// Captures samples of the face in front of the camera or in the given images and transforms them
+/// using the projection, so that they can be compared with the templates
+fn capture_calibration_samples(
+	options: &CalibrationOptions,
+	projection: Option<&Projection>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+) -> Result<Vec<FaceEmbedding>, String> {
+	let calibration_processor =
+		Arc::new(Mutex::new(CalibrationProcessor::new(options.sample_count)));
+	if let Some(from) = &options.from {
+		if let Err(e) = replay_frames(from, &calibration_processor, config, model_registry) {
+			return Err(format!(
+				"Failed to capture samples from {}: {e}",
+				from.display()
+			));
+		}
+	} else {
+		println!("Look at the camera and move your head slightly");
+		start_threads(calibration_processor.clone(), config, model_registry, true);
+	}
+	let result = match calibration_processor.lock() {
+		Ok(l) => l.get_result(),
+		Err(e) => return Err(format!("Failed to get lock: {e}")),
+	};
+
+	let Some(result) = result else {
+		return Err(String::from("Calibration was interrupted"));
+	};
+
+	Ok(result
+		.face_embeddings
+		.iter()
+		.map(|e| projection.map_or(*e, |p| p.project(e)))
+		.collect())
+}
+
+/// Returns the similarities of the samples of the face and of the faces of other people to the
+/// templates of the face, combined the same way as during authentication
+fn get_calibration_scores(
+	gallery: &[FaceEmbedding],
+	sample_embeddings: &[FaceEmbedding],
+	impostor_embeddings: &[FaceEmbedding],
+	aggregation: Aggregation,
+) -> Scores {
+	let get_similarity = |embedding: &FaceEmbedding| {
+		aggregation.aggregate(
+			gallery
+				.iter()
+				.filter_map(|e| e.cosine_similarity(embedding))
+				.collect(),
+		)
+	};
+
+	Scores::from_similarities(
+		sample_embeddings
+			.iter()
+			.filter_map(get_similarity)
+			.collect(),
+		impostor_embeddings
+			.iter()
+			.filter_map(get_similarity)
+			.collect(),
+	)
+}
+
+/// Removes the calibrated threshold of the face
+fn reset_calibration(
+	name: &str,
+	face_store: &dyn FaceStore,
+	mut face_profiles: HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	let Some(face_profile) = face_profiles.remove(name) else {
+		return Err(String::from(
+			"Face of this name does not exist or could not be loaded.",
+		));
+	};
+	let mut face_profile = unseal_face_profile(name, face_profile)?;
+	if face_profile.calibration.take().is_none() {
+		println!("Face scan {name} is not calibrated");
+		return Ok(());
+	}
+	face_profile.updated_at = Utc::now();
+
+	save_face_profile(face_store, name, &face_profile)
+		.map_err(|e| format!("Failed to save face scan: {e}"))
+}
+
+/// Prints the faces found in the images along with the scanned faces they are the most similar
+/// to and saves the annotated images into the output directory, if there is one
+fn identify_faces(
+	images: &[PathBuf],
+	output: Option<&Path>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+	user: &str,
+	face_profiles: HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	let auth_processor = create_auth_processor(face_profiles, config, recognizer_id, user, true)?;
+	let frame_processor = frame_processor::create_frame_processor(
+		config.inference.backend,
+		model_registry,
+		config.detector,
+	)
+	.map_err(|e| format!("Failed to create frame processor: {e}"))?;
+	if let Some(output) = output {
+		fs::create_dir_all(output)
+			.map_err(|e| format!("Failed to create {}: {e}", output.display()))?;
+	}
+
+	let mut output_paths = HashSet::new();
+	for image in images {
+		let image_paths = replay::get_frame_paths(image)
+			.map_err(|e| format!("Failed to read {}: {e}", image.display()))?;
+		for image_path in image_paths {
+			let mut frame = replay::load_frame(&image_path)
+				.map_err(|e| format!("Failed to load {}: {e}", image_path.display()))?;
+			let faces = frame_processor.process_frame(&frame);
+			println!("{}: {} faces found", image_path.display(), faces.len());
+
+			let mut annotated_faces = Vec::new();
+			for face in &faces {
+				let (face_for_gui, closest_face) = auth_processor.identify(face);
+				let recognized = matches!(face_for_gui.annotation, FaceForGUIAnnotation::Name(_));
+				let similarity = closest_face.map_or_else(String::new, |m| {
+					if recognized {
+						format!(" (similarity {:.3})", m.similarity)
+					} else {
+						format!(" (closest {}, similarity {:.3})", m.name, m.similarity)
+					}
+				});
+				let rectangle = face_for_gui.rectangle;
+				println!(
+					"  ({}, {})-({}, {}): {}{similarity}",
+					rectangle.min.x,
+					rectangle.min.y,
+					rectangle.max.x,
+					rectangle.max.y,
+					gui::get_annotation_text(face_for_gui.annotation.clone())
+				);
+				annotated_faces.push(face_for_gui);
 			}
 
-			let scan_processor = Arc::new(Mutex::new(ScanProcessor::new()));
-			start_threads(scan_processor.clone(), config, true);
-			let scan_processor_lock = match scan_processor.lock() {
-				Ok(l) => l,
-				Err(e) => {
-					log_and_print_error!("Failed to get lock: {e}");
-					return ExitCode::FAILURE;
-				}
+			let Some(output) = output else {
+				continue;
 			};
-			let Some(result) = scan_processor_lock.get_result() else {
-				log_and_print_error!("Getting auth result failed - this should never happen!");
-				return ExitCode::FAILURE;
+			let Some(file_stem) = image_path.file_stem() else {
+				continue;
 			};
+			let output_path = output.join(file_stem).with_extension("png");
+			if !output_paths.insert(output_path.clone()) {
+				return Err(format!(
+					"{} would overwrite the annotated image of another one with the same name",
+					image_path.display()
+				));
+			}
+			gui::annotate_frame(&mut frame, &annotated_faces);
+			frame
+				.save(&output_path)
+				.map_err(|e| format!("Failed to save {}: {e}", output_path.display()))?;
+		}
+	}
 
-			drop(scan_processor_lock);
-			if let Err(e) = save_face_embedding(&name, &result.face_embedding) {
-				log_and_print_error!("Failed to save face scan: {e}");
-				return ExitCode::FAILURE;
-			};
-			println!("Face scan was successful!");
+	Ok(())
+}
+
+/// Prints the authentication attempts matching the filters or a summary of them
+fn show_audit_log(
+	config: &Config,
+	user: Option<&str>,
+	since: Option<DateTime<Utc>>,
+	failed: bool,
+	summary: bool,
+) -> Result<(), String> {
+	let entries: Vec<AuditEntry> = open_audit_log(&config.audit, &config.store)
+		.and_then(|l| l.read())
+		.map_err(|e| format!("Failed to read the audit log: {e}"))?
+		.into_iter()
+		.filter(|e| user.map_or(true, |u| e.user.as_deref() == Some(u)))
+		.filter(|e| since.map_or(true, |s| e.timestamp >= s))
+		.filter(|e| !failed || e.outcome != AuditOutcome::Success)
+		.collect();
+
+	if summary {
+		print!("{}", AuditSummary::new(&entries));
+	} else {
+		for entry in entries {
+			println!("{entry}");
 		}
 	}
 
-	ExitCode::SUCCESS
+	Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn scan_face(
+	name: &str,
+	append: bool,
+	guided: bool,
+	from: Option<PathBuf>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+	recognizer_id: &RecognizerId,
+	user: &str,
+	face_store: &dyn FaceStore,
+	mut face_profiles: HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	let existing_face_profile = face_profiles.remove(name);
+	if append && existing_face_profile.is_none() {
+		return Err(String::from("Face of this name does not exist or could not be loaded, so the scan cannot be added to it."));
+	}
+	let projection = load_projection(user)?;
+	let projection_id = projection.as_ref().map(|p| p.id().to_owned());
+	if existing_face_profile
+		.as_ref()
+		.is_some_and(|p| p.projection_id != projection_id)
+	{
+		return Err(String::from("The existing templates of this face were transformed using a different projection key, so the scan cannot be added to them. Remove the face and scan it again."));
+	}
+	if !append {
+		match face_store.contains(name) {
+			Ok(false) => {}
+			Ok(true) => return Err(String::from("Face of this name already exists. Either pick a different name, remove the existing face or add the scan to it using --append.")),
+			Err(e) => return Err(format!("Failed to check whether the face scan exists: {e}")),
+		}
+	}
+
+	let similarity_threshold = config.auth.get_settings(Some(user)).similarity_threshold;
+	let scan_processor = Arc::new(Mutex::new(if guided {
+		ScanProcessor::new_guided(similarity_threshold)
+	} else {
+		ScanProcessor::new(config.scan.sample_count, similarity_threshold)
+	}));
+	if let Some(from) = &from {
+		if let Err(e) = replay_frames(from, &scan_processor, config, model_registry) {
+			return Err(format!("Failed to scan face from {}: {e}", from.display()));
+		}
+	} else {
+		start_threads(scan_processor.clone(), config, model_registry, true);
+	}
+	let result = match scan_processor.lock() {
+		Ok(l) => l.get_result(),
+		Err(e) => return Err(format!("Failed to get lock: {e}")),
+	};
+	let Some(result) = result else {
+		return Err(String::from(
+			"Getting auth result failed - this should never happen!",
+		));
+	};
+
+	let face_templates: Vec<FaceTemplate> = result
+		.face_embeddings
+		.into_iter()
+		.map(|(embedding, pose)| FaceTemplate {
+			recognizer_id: Some(recognizer_id.clone()),
+			embedding: projection
+				.as_ref()
+				.map_or(embedding, |p| p.project(&embedding)),
+			pose,
+			adapted_at: None,
+		})
+		.collect();
+	let camera_id = from.map_or_else(|| config.camera.path.clone(), |p| p.display().to_string());
+	// The face is read again while the store is locked, so that changes made by other processes
+	// during the scan are not lost
+	let update = |existing_face_profile: Option<FaceProfile>| match existing_face_profile {
+		Some(mut p) if append => {
+			if p.projection_id != projection_id {
+				return Err(store::Error::ProjectionKeyUnavailable);
+			}
+			p.append_templates(face_templates.clone());
+			Ok(p)
+		}
+		Some(_) => Err(store::Error::AlreadyExists(name.to_owned())),
+		None if append => Err(store::Error::NotFound(name.to_owned())),
+		None => {
+			let mut p = FaceProfile::new(
+				Some(user.to_owned()),
+				name.to_owned(),
+				Some(camera_id.clone()),
+				face_templates.clone(),
+			);
+			p.projection_id.clone_from(&projection_id);
+			Ok(p)
+		}
+	};
+	if let Err(e) = update_face_profile(face_store, name, &update) {
+		return Err(format!("Failed to save face scan: {e}"));
+	};
+	println!("Face scan was successful!");
+
+	Ok(())
+}
+
+/// Decrypts the templates of encrypted faces. This requires the secret store keys, which only
+/// root can read. Faces which cannot be decrypted are reported and skipped
+fn unseal_face_profiles(face_profiles: &mut HashMap<String, FaceProfile>) -> Result<(), String> {
+	if !face_profiles.values().any(FaceProfile::is_sealed) {
+		return Ok(());
+	}
+	let keys = match StoreKeys::load(Path::new(KEYS_DIRECTORY)) {
+		Ok(k) => k,
+		Err(e) => return Err(format!("Failed to load the store keys: {e}")),
+	};
+
+	face_profiles.retain(|name, face_profile| {
+		if !face_profile.is_sealed() {
+			return true;
+		}
+		let result = keys
+			.as_ref()
+			.map_or(Err(store::Error::KeyUnavailable), |k| {
+				face_profile.unseal(k)
+			});
+		if let Err(e) = result {
+			log_and_print_warn!("Failed to decrypt face scan {name} - skipping: {e}");
+			return false;
+		}
+
+		true
+	});
+
+	Ok(())
+}
+
+/// Decrypts the templates of a single face, if they are encrypted
+fn unseal_face_profile(name: &str, face_profile: FaceProfile) -> Result<FaceProfile, String> {
+	let mut face_profiles = HashMap::from([(name.to_owned(), face_profile)]);
+	unseal_face_profiles(&mut face_profiles)?;
+
+	face_profiles
+		.remove(name)
+		.ok_or_else(|| format!("Failed to decrypt face scan {name}"))
+}
+
+/// Rewrites all faces, so that they are stored in the current format, encrypted using the current
+/// key and transformed using the current projection key
+fn handle_store_command(
+	command: &StoreCommand,
+	user: &str,
+	face_store: &dyn FaceStore,
+	face_profiles: &mut HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	let keys_directory = Path::new(KEYS_DIRECTORY);
+	let keys = match StoreKeys::load(keys_directory) {
+		Ok(k) => k,
+		Err(e) => return Err(format!("Failed to load the store keys: {e}")),
+	};
+	if let StoreCommand::Migrate {
+		from_directory: true,
+		..
+	} = command
+	{
+		add_directory_face_profiles(user, face_profiles)?;
+	}
+	unseal_face_profiles(face_profiles)?;
+
+	let keys = match (command, keys) {
+		(StoreCommand::Migrate { encrypt: true, .. }, None) => Some(StoreKeys::generate()),
+		(StoreCommand::RotateKey, Some(mut k)) => {
+			k.rotate();
+			Some(k)
+		}
+		(StoreCommand::RotateKey, None) => return Err(String::from(
+			"There is no key to rotate - use `store migrate --encrypt` to encrypt the faces first",
+		)),
+		(_, k) => k,
+	};
+	if let Some(keys) = &keys {
+		if let Err(e) = keys.save(keys_directory) {
+			return Err(format!("Failed to save the store keys: {e}"));
+		}
+	}
+	if let StoreCommand::Project { new_key } = command {
+		project_face_profiles(*new_key, user, face_profiles)?;
+	}
+
+	for (name, face_profile) in &*face_profiles {
+		if let Err(e) = save_face_profile(face_store, name, face_profile) {
+			return Err(format!("Failed to save face scan {name}: {e}"));
+		}
+	}
+	println!("{} face scans were rewritten", face_profiles.len());
+	if matches!(command, StoreCommand::RotateKey) {
+		println!("Faces of other users stay encrypted using the previous key until they run `sudo oblichey-cli store migrate`");
+	}
+
+	Ok(())
+}
+
+/// Adds the faces stored in the state directory of the user to the faces which are rewritten. Faces
+/// which already exist in the configured store are kept
+fn add_directory_face_profiles(
+	user: &str,
+	face_profiles: &mut HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	let directory_face_profiles = match get_embeddings_directory(user)
+		.and_then(FilesystemFaceStore::new)
+		.and_then(|s| load_face_profiles(&s))
+	{
+		Ok(p) => p,
+		Err(e) => return Err(format!("Failed to load face scans from the directory: {e}")),
+	};
+
+	for (name, face_profile) in directory_face_profiles {
+		if face_profiles.contains_key(&name) {
+			log_and_print_warn!("Face scan {name} already exists in the store - skipping");
+			continue;
+		}
+		face_profiles.insert(name, face_profile);
+	}
+
+	Ok(())
+}
+
+/// Transforms the templates of all faces using the projection key of the user, generating
+/// it if there is none yet or if a new one is requested. Faces transformed using a previous key
+/// cannot be transformed again, so they are skipped
+fn project_face_profiles(
+	new_key: bool,
+	user: &str,
+	face_profiles: &mut HashMap<String, FaceProfile>,
+) -> Result<(), String> {
+	let keys_directory = Path::new(KEYS_DIRECTORY);
+	let projection_key = match ProjectionKey::load(keys_directory, user) {
+		Ok(Some(k)) if !new_key => k,
+		Ok(_) => ProjectionKey::generate(),
+		Err(e) => return Err(format!("Failed to load the projection key: {e}")),
+	};
+	if let Err(e) = projection_key.save(keys_directory, user) {
+		return Err(format!("Failed to save the projection key: {e}"));
+	}
+
+	let projection = projection_key.get_projection();
+	for (name, face_profile) in face_profiles.iter_mut() {
+		if face_profile.projection_id.as_deref() == Some(projection.id()) {
+			continue;
+		}
+		if let Err(e) = face_profile.project(&projection) {
+			log_and_print_warn!("Failed to transform face scan {name} - skipping: {e}");
+		}
+	}
+
+	Ok(())
+}
+
+/// Loads the projection the templates of the user are transformed with, if they enabled template
+/// projection
+fn load_projection(user: &str) -> Result<Option<Projection>, String> {
+	match ProjectionKey::load(Path::new(KEYS_DIRECTORY), user) {
+		Ok(k) => Ok(k.as_ref().map(ProjectionKey::get_projection)),
+		Err(e) => Err(format!("Failed to load the projection key: {e}")),
+	}
+}
+
+/// Asks the user for a passphrase without echoing it. If `confirm` is set, the user has to enter
+/// it twice
+fn prompt_passphrase(confirm: bool) -> Result<String, String> {
+	let passphrase = match rpassword::prompt_password("Passphrase: ") {
+		Ok(p) => p,
+		Err(e) => return Err(e.to_string()),
+	};
+	if passphrase.is_empty() {
+		return Err(String::from("The passphrase cannot be empty"));
+	}
+	if confirm {
+		match rpassword::prompt_password("Confirm passphrase: ") {
+			Ok(p) if p == passphrase => {}
+			Ok(_) => return Err(String::from("The passphrases do not match")),
+			Err(e) => return Err(e.to_string()),
+		}
+	}
+
+	Ok(passphrase)
+}
+
+/// Runs the processing on frames replayed from an image or a directory of images instead of the
+/// camera
+fn replay_frames(
+	path: &Path,
+	face_processor: &Arc<Mutex<impl FaceProcessor>>,
+	config: &Config,
+	model_registry: &ModelRegistry,
+) -> Result<(), String> {
+	let frames = match camera::replay::replay(path) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("Failed to load frames: {e}")),
+	};
+	let mut face_processor_lock = match face_processor.lock() {
+		Ok(l) => l,
+		Err(e) => return Err(format!("Failed to get lock: {e}")),
+	};
+
+	processors::process_frames(
+		frames,
+		&mut *face_processor_lock,
+		config.inference.backend,
+		model_registry,
+		config.detector,
+	)?;
+	let is_finished = face_processor_lock.is_finished();
+	drop(face_processor_lock);
+	if !is_finished {
+		return Err(String::from(
+			"The frames do not contain enough usable samples of a single face",
+		));
+	}
+
+	Ok(())
 }
 
 /// This starts multiple threads for: reading from camera, processing frames and running the models
@@ -187,6 +1821,7 @@ fn handle_command(
 fn start_threads(
 	face_processor: Arc<Mutex<dyn FaceProcessor + Send + Sync>>,
 	config: &Config,
+	model_registry: &ModelRegistry,
 	gui: bool,
 ) {
 	trace!("Starting threads");
@@ -199,20 +1834,26 @@ fn start_threads(
 
 	let frame_clone = frame.clone();
 	let finished_clone = finished.clone();
-	let camera_path_clone = config.camera.path.clone();
+	let camera_config_clone = config.camera.clone();
 	thread_handles.push(thread::spawn(move || {
-		camera::start(&frame_clone, &finished_clone, &camera_path_clone)
+		camera::start(&frame_clone, &finished_clone, &camera_config_clone)
 	}));
 
 	let faces_for_gui_clone = faces_for_gui.clone();
 	let finished_clone = finished.clone();
 	let frame_clone = frame.clone();
+	let backend = config.inference.backend;
+	let model_registry_clone = model_registry.clone();
+	let detector_config = config.detector;
 	thread_handles.push(thread::spawn(move || {
 		processors::start(
 			&frame_clone,
 			&faces_for_gui_clone,
 			&finished_clone,
 			&face_processor,
+			backend,
+			&model_registry_clone,
+			detector_config,
 		)
 	}));
 
@@ -1,5 +1,6 @@
-use super::get_weights_file;
 use super::imported::detector::Model;
+use super::registry::{ModelSource, Normalization};
+use super::{load_weights, Error};
 use crate::camera::Frame;
 use crate::geometry::{Rectangle, Vec2D};
 use burn::tensor::backend::Backend;
@@ -9,22 +10,31 @@ use mockall::automock;
 
 /// The size of the image the detector model takes as input
 pub const DETECTOR_INPUT_SIZE: Vec2D<u32> = Vec2D { x: 640, y: 480 };
-const CONFIDENCE_THRESHOLD: f32 = 0.95;
+/// Detections with a lower confidence are discarded, unless configured otherwise
+pub const DEFAULT_CONFIDENCE_THRESHOLD: f32 = 0.95;
 
 #[derive(Debug)]
 pub struct FaceDetector<B: Backend> {
 	device: B::Device,
 	model: Model<B>,
+	normalization: Normalization,
+	confidence_threshold: f32,
 }
 
 #[cfg_attr(test, automock)]
 #[cfg_attr(test, allow(unused))]
 impl<B: Backend> FaceDetector<B> {
-	pub fn new(device: &B::Device) -> Self {
-		Self {
-			model: Model::from_file(&get_weights_file("detector"), device),
+	pub fn new(
+		source: &ModelSource,
+		device: &B::Device,
+		confidence_threshold: f32,
+	) -> Result<Self, Error> {
+		Ok(Self {
+			model: load_weights(Model::new(device), source, device)?,
 			device: device.clone(),
-		}
+			normalization: source.metadata.normalization,
+			confidence_threshold,
+		})
 	}
 
 	/// This is going to panic if the frame has a size other than `DETECTOR_INPUT_SIZE`
@@ -42,7 +52,7 @@ impl<B: Backend> FaceDetector<B> {
 
 		let input = self.normalize_input(frame);
 		let output = self.model.forward(input);
-		Self::interpret_output(&output)
+		Self::interpret_output(&output, self.confidence_threshold)
 	}
 
 	fn normalize_input(&self, frame: &Frame) -> Tensor<B, 4> {
@@ -56,8 +66,9 @@ impl<B: Backend> FaceDetector<B> {
 		// Make into a tensor
 		let tensor = Tensor::from_data(TensorData::new(frame.to_vec(), shape), &self.device);
 
-		// Normalize between [-1, 1]
-		let normalized = (tensor - Tensor::full(shape, 127, &self.device)) / 128.0;
+		// Normalize, usually between [-1, 1]
+		let normalized = (tensor - Tensor::full(shape, self.normalization.mean, &self.device))
+			/ self.normalization.std;
 
 		// Reorder dimension to have: channels, height, width
 		let permutated = normalized.permute([2, 0, 1]);
@@ -66,7 +77,10 @@ impl<B: Backend> FaceDetector<B> {
 		permutated.unsqueeze::<4>()
 	}
 
-	fn interpret_output(output: &(Tensor<B, 3>, Tensor<B, 3>)) -> Vec<Rectangle<u32>> {
+	fn interpret_output(
+		output: &(Tensor<B, 3>, Tensor<B, 3>),
+		confidence_threshold: f32,
+	) -> Vec<Rectangle<u32>> {
 		let (confidences, boxes) = output;
 		let confidences = confidences
 			.to_data()
@@ -83,7 +97,7 @@ impl<B: Backend> FaceDetector<B> {
 			let i = n + 1;
 			let j = n * 2;
 
-			if confidences[i] <= CONFIDENCE_THRESHOLD {
+			if confidences[i] <= confidence_threshold {
 				continue;
 			}
 
@@ -112,8 +126,11 @@ mod tests {
 	use crate::{
 		camera::Frame,
 		geometry::{Rectangle, Vec2D},
-		models::detector::{CONFIDENCE_THRESHOLD, DETECTOR_INPUT_SIZE},
-		processors::frame_processor::BurnBackend,
+		models::{
+			detector::{DEFAULT_CONFIDENCE_THRESHOLD, DETECTOR_INPUT_SIZE},
+			registry::ModelRegistry,
+		},
+		processors::frame_processor::NdArrayBackend,
 	};
 	use burn::{
 		backend::{ndarray::NdArrayDevice, NdArray},
@@ -129,7 +146,13 @@ mod tests {
 	}
 
 	fn get_face_detector() -> FaceDetector<NdArray<f32>> {
-		FaceDetector::new(&get_device())
+		let registry = ModelRegistry::built_in().expect("Failed to find built-in models");
+		FaceDetector::new(
+			registry.detector(),
+			&get_device(),
+			DEFAULT_CONFIDENCE_THRESHOLD,
+		)
+		.expect("Failed to create FaceDetector")
 	}
 
 	fn get_frame(data: Vec<u8>) -> Frame {
@@ -214,7 +237,7 @@ mod tests {
 		fn create_confidences_tensor(
 			confidences: &[f32],
 			device: NdArrayDevice,
-		) -> Tensor<BurnBackend, 3> {
+		) -> Tensor<NdArrayBackend, 3> {
 			let tensor_data_vec: Vec<f32> = confidences
 				.iter()
 				.flat_map(|&confidence| [0.0, confidence])
@@ -228,7 +251,7 @@ mod tests {
 		fn create_rectangles_tensor(
 			rectangles: &[Rectangle<u32>],
 			device: NdArrayDevice,
-		) -> Tensor<BurnBackend, 3> {
+		) -> Tensor<NdArrayBackend, 3> {
 			let tensor_data_vec: Vec<f32> = rectangles
 				.iter()
 				.flat_map(|rect| {
@@ -271,19 +294,21 @@ mod tests {
 			let confidences_tensor = create_confidences_tensor(&confidences, device);
 			let rectangles_tensor = create_rectangles_tensor(&rectangles, device);
 			let valid_rectangle_count = confidences.iter().fold(0, |count, confidence| {
-				if *confidence > CONFIDENCE_THRESHOLD {
+				if *confidence > DEFAULT_CONFIDENCE_THRESHOLD {
 					count + 1
 				} else {
 					count
 				}
 			});
 
-			let interpreted =
-				FaceDetector::interpret_output(&(confidences_tensor, rectangles_tensor));
+			let interpreted = FaceDetector::interpret_output(
+				&(confidences_tensor, rectangles_tensor),
+				DEFAULT_CONFIDENCE_THRESHOLD,
+			);
 
 			let mut valid_rectangles = vec![];
 			for (index, rectangle) in rectangles.iter().enumerate() {
-				if confidences[index] < CONFIDENCE_THRESHOLD {
+				if confidences[index] < DEFAULT_CONFIDENCE_THRESHOLD {
 					continue;
 				}
 
@@ -2,24 +2,89 @@ mod imported;
 
 pub mod detector;
 pub mod recognizer;
+pub mod registry;
+pub mod weights;
 
-const WEIGHTS_DIRECTORY_NAME: &str = "weights";
+use burn::{
+	module::Module,
+	record::{FullPrecisionSettings, NamedMpkBytesRecorder, Recorder, RecorderError},
+	tensor::backend::Backend,
+};
+use registry::{ModelRole, ModelSource};
+use std::{fmt::Display, io, path::PathBuf};
+use weights::Weights;
 
-fn get_weights_file(model_name: &str) -> String {
-	const PARENT_DIR_ERROR: &str = "Could not get parent directory!";
+#[derive(Debug)]
+pub enum Error {
+	WeightsIo(PathBuf, io::Error),
+	WeightsNotFound(ModelRole, Vec<PathBuf>),
+	Recorder(Weights, RecorderError),
+	DigestMismatch {
+		weights: Weights,
+		expected: String,
+		actual: String,
+	},
+	MetadataIo(PathBuf, io::Error),
+	MetadataDeserialize(PathBuf, toml::de::Error),
+	InvalidMetadata {
+		role: ModelRole,
+		reason: String,
+	},
+}
 
-	let mut path = std::env::current_exe().expect("Could not get path to executable!");
-	path = path.parent().expect(PARENT_DIR_ERROR).to_path_buf();
+impl Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::WeightsIo(p, e) => {
+				write!(f, "Failed to read weights from {}: {e}", p.display())
+			}
+			Self::WeightsNotFound(r, searched) => {
+				let searched: Vec<String> =
+					searched.iter().map(|p| p.display().to_string()).collect();
+				write!(
+					f,
+					"Failed to find {r} weights, searched: {}",
+					searched.join(", ")
+				)
+			}
+			Self::Recorder(w, e) => write!(f, "Failed to load weights from {w}: {e:?}"),
+			Self::DigestMismatch {
+				weights,
+				expected,
+				actual,
+			} => write!(
+				f,
+				"Integrity check of {weights} failed, expected BLAKE3 digest {expected} but got {actual}"
+			),
+			Self::MetadataIo(p, e) => {
+				write!(f, "Failed to read model metadata from {}: {e}", p.display())
+			}
+			Self::MetadataDeserialize(p, e) => {
+				write!(
+					f,
+					"Failed to parse model metadata from {}: {e}",
+					p.display()
+				)
+			}
+			Self::InvalidMetadata { role, reason } => {
+				write!(f, "Metadata of the {role} model is invalid: {reason}")
+			}
+		}
+	}
+}
 
-	// In tests we need to go further up for some reason
-	#[cfg(test)]
+/// Loads the weights from the given source into the model after checking their integrity
+fn load_weights<B: Backend, M: Module<B>>(
+	model: M,
+	source: &ModelSource,
+	device: &B::Device,
+) -> Result<M, Error> {
+	let bytes = source.read_weights()?.into_owned();
+	let record = match NamedMpkBytesRecorder::<FullPrecisionSettings>::default().load(bytes, device)
 	{
-		path = path.parent().expect(PARENT_DIR_ERROR).to_path_buf();
-	}
+		Ok(r) => r,
+		Err(e) => return Err(Error::Recorder(source.weights.clone(), e)),
+	};
 
-	path.join(WEIGHTS_DIRECTORY_NAME)
-		.join(String::new() + model_name + ".mpk")
-		.to_str()
-		.expect("Failed to get valid UTF-8 string from path!")
-		.to_owned()
+	Ok(model.load_record(record))
 }
@@ -1,4 +1,9 @@
-use super::{get_weights_file, imported::recognizer::Model};
+use super::{
+	imported::recognizer::Model,
+	load_weights,
+	registry::{ModelSource, Normalization},
+	Error,
+};
 use crate::{
 	camera::Frame,
 	geometry::Vec2D,
@@ -15,16 +20,18 @@ pub const RECOGNIZER_INPUT_SIZE: Vec2D<u32> = Vec2D { x: 128, y: 128 };
 pub struct FaceRecognizer<B: Backend> {
 	device: B::Device,
 	model: Model<B>,
+	normalization: Normalization,
 }
 
 #[cfg_attr(test, automock)]
 #[cfg_attr(test, allow(unused))]
 impl<B: Backend> FaceRecognizer<B> {
-	pub fn new(device: &B::Device) -> Self {
-		Self {
-			model: Model::from_file(&get_weights_file("recognizer"), device),
+	pub fn new(source: &ModelSource, device: &B::Device) -> Result<Self, Error> {
+		Ok(Self {
+			model: load_weights(Model::new(device), source, device)?,
 			device: device.clone(),
-		}
+			normalization: source.metadata.normalization,
+		})
 	}
 
 	/// This is going to panic if the frame has a size other than `DETECTOR_INPUT_SIZE`
@@ -56,8 +63,9 @@ impl<B: Backend> FaceRecognizer<B> {
 		// Make into a tensor
 		let tensor = Tensor::from_data(TensorData::new(face_image.to_vec(), shape), &self.device);
 
-		// Normalize between [-1, 1]
-		let normalized = (tensor - Tensor::full(shape, 127, &self.device)) / 128.0;
+		// Normalize, usually between [-1, 1]
+		let normalized = (tensor - Tensor::full(shape, self.normalization.mean, &self.device))
+			/ self.normalization.std;
 
 		// Reorder dimension to have: channels, height, width
 		let permutated = normalized.permute([2, 0, 1]);
@@ -84,9 +92,10 @@ mod tests {
 	use super::{FaceRecognizer, RECOGNIZER_INPUT_SIZE};
 	use crate::{
 		camera::Frame,
+		models::registry::ModelRegistry,
 		processors::{
 			face::{FaceEmbedding, EMBEDDING_LENGTH},
-			frame_processor::BurnBackend,
+			frame_processor::NdArrayBackend,
 		},
 	};
 	use burn::{
@@ -103,7 +112,9 @@ mod tests {
 	}
 
 	fn get_face_recognizer() -> FaceRecognizer<NdArray<f32>> {
-		FaceRecognizer::new(&get_device())
+		let registry = ModelRegistry::built_in().expect("Failed to find built-in models");
+		FaceRecognizer::new(registry.recognizer(), &get_device())
+			.expect("Failed to create FaceRecognizer")
 	}
 
 	fn get_frame(data: Vec<u8>) -> Frame {
@@ -194,7 +205,7 @@ mod tests {
 
 		for test_case in test_cases {
 			let expected_result = FaceEmbedding::new(&test_case);
-			let output: Tensor<BurnBackend, 2> =
+			let output: Tensor<NdArrayBackend, 2> =
 				Tensor::from_data(TensorData::new(test_case.to_vec(), [512, 1]), &device);
 			let result = FaceRecognizer::interpret_output(&output).embedding;
 
@@ -0,0 +1,380 @@
+use super::{
+	detector::DETECTOR_INPUT_SIZE, recognizer::RECOGNIZER_INPUT_SIZE, weights::Weights, Error,
+};
+use crate::{
+	geometry::Vec2D,
+	processors::face::{RecognizerId, EMBEDDING_LENGTH},
+};
+use log::{trace, warn};
+use serde::Deserialize;
+use std::{
+	borrow::Cow,
+	fmt::Display,
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// The extension of the metadata file which is expected to be next to a weights file, unless a
+/// different path is specified in the config
+const METADATA_FILE_EXTENSION: &str = "toml";
+
+/// The task a model is used for
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelRole {
+	Detector,
+	Recognizer,
+}
+
+impl Display for ModelRole {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Detector => write!(f, "detector"),
+			Self::Recognizer => write!(f, "recognizer"),
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputSize {
+	pub width: u32,
+	pub height: u32,
+}
+
+impl From<Vec2D<u32>> for InputSize {
+	fn from(value: Vec2D<u32>) -> Self {
+		Self {
+			width: value.x,
+			height: value.y,
+		}
+	}
+}
+
+/// Describes how pixel values are normalized before being passed into a model:
+/// `(value - mean) / std`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Normalization {
+	pub mean: f32,
+	pub std: f32,
+}
+
+/// Describes what the output of a model looks like
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputLayout {
+	/// Pairs of background/face confidences and boxes with coordinates relative to the input size
+	ConfidencesBoxes,
+	/// A single embedding vector of the given length
+	Embedding { length: usize },
+}
+
+/// Metadata describing a model, so that it can be checked against what this build supports
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ModelMetadata {
+	pub input_size: InputSize,
+	pub normalization: Normalization,
+	pub output_layout: OutputLayout,
+}
+
+impl ModelMetadata {
+	/// Returns the metadata of the model compiled into this build for the given role
+	fn built_in(role: ModelRole) -> Self {
+		let normalization = Normalization {
+			mean: 127.0,
+			std: 128.0,
+		};
+
+		match role {
+			ModelRole::Detector => Self {
+				input_size: InputSize::from(DETECTOR_INPUT_SIZE),
+				normalization,
+				output_layout: OutputLayout::ConfidencesBoxes,
+			},
+			ModelRole::Recognizer => Self {
+				input_size: InputSize::from(RECOGNIZER_INPUT_SIZE),
+				normalization,
+				output_layout: OutputLayout::Embedding {
+					length: EMBEDDING_LENGTH,
+				},
+			},
+		}
+	}
+
+	/// Checks that a model described by this metadata can be used for the given role. The
+	/// architecture of the models is compiled in, so the weights have to have the same input and
+	/// output shapes
+	fn validate(&self, role: ModelRole) -> Result<(), Error> {
+		let built_in = Self::built_in(role);
+		let invalid = |reason: String| Err(Error::InvalidMetadata { role, reason });
+
+		if self.input_size != built_in.input_size {
+			return invalid(format!(
+				"Input size must be {}x{}",
+				built_in.input_size.width, built_in.input_size.height
+			));
+		}
+		if self.output_layout != built_in.output_layout {
+			return invalid(format!(
+				"Output layout must be {:?}",
+				built_in.output_layout
+			));
+		}
+		if !self.normalization.mean.is_finite() {
+			return invalid(String::from("Normalization mean must be a finite number"));
+		}
+		if !self.normalization.std.is_normal() {
+			return invalid(String::from(
+				"Normalization standard deviation must be a non-zero finite number",
+			));
+		}
+
+		Ok(())
+	}
+}
+
+/// Configuration of a single model as found in `/etc/oblichey.toml`
+#[derive(Deserialize, Clone, Debug)]
+pub struct ModelConfig {
+	/// Path to the `.mpk` weights file
+	pub weights: PathBuf,
+	/// Path to the metadata file. Defaults to the weights path with a `.toml` extension
+	pub metadata: Option<PathBuf>,
+	/// The BLAKE3 digest the weights file is expected to have
+	pub digest: Option<String>,
+}
+
+/// The `[models]` section of `/etc/oblichey.toml`
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ModelsConfig {
+	/// Directory to look for the weights of the built-in models in. This has no effect if the
+	/// weights are embedded into the executable
+	pub weights_directory: Option<PathBuf>,
+	pub detector: Option<ModelConfig>,
+	pub recognizer: Option<ModelConfig>,
+}
+
+impl ModelsConfig {
+	fn configured_models(&self) -> Vec<(ModelRole, &ModelConfig)> {
+		[
+			(ModelRole::Detector, &self.detector),
+			(ModelRole::Recognizer, &self.recognizer),
+		]
+		.into_iter()
+		.filter_map(|(role, config)| Some((role, config.as_ref()?)))
+		.collect()
+	}
+}
+
+/// Where to load a model from along with its metadata
+#[derive(Clone, Debug)]
+pub struct ModelSource {
+	pub weights: Weights,
+	pub metadata: ModelMetadata,
+	/// The BLAKE3 digest the weights are expected to have. The weights are not verified if this
+	/// is `None`
+	pub digest: Option<String>,
+}
+
+impl ModelSource {
+	fn built_in(role: ModelRole, weights_directory: Option<&Path>) -> Result<Self, Error> {
+		Ok(Self {
+			weights: Weights::built_in(role, weights_directory)?,
+			metadata: ModelMetadata::built_in(role),
+			digest: Some(String::from(Weights::built_in_digest(role))),
+		})
+	}
+
+	fn from_config(role: ModelRole, config: &ModelConfig) -> Result<Self, Error> {
+		let metadata_path = config
+			.metadata
+			.clone()
+			.unwrap_or_else(|| config.weights.with_extension(METADATA_FILE_EXTENSION));
+		trace!("Loading {role} metadata from {}", metadata_path.display());
+
+		let serialized = match fs::read_to_string(&metadata_path) {
+			Ok(s) => s,
+			Err(e) => return Err(Error::MetadataIo(metadata_path, e)),
+		};
+		let metadata: ModelMetadata = match toml::from_str(&serialized) {
+			Ok(m) => m,
+			Err(e) => return Err(Error::MetadataDeserialize(metadata_path, e)),
+		};
+		metadata.validate(role)?;
+
+		if config.digest.is_none() {
+			warn!("No digest configured for the {role} model - its integrity will not be verified");
+		}
+
+		Ok(Self {
+			weights: Weights::File(config.weights.clone()),
+			metadata,
+			digest: config.digest.clone(),
+		})
+	}
+
+	/// Reads the weights and checks they have the expected digest
+	pub fn read_weights(&self) -> Result<Cow<'static, [u8]>, Error> {
+		let bytes = self.weights.read()?;
+		if let Some(digest) = &self.digest {
+			self.weights.verify_digest(&bytes, digest)?;
+		}
+
+		Ok(bytes)
+	}
+
+	/// Returns the BLAKE3 digest of the weights, computing it if it was not configured
+	pub fn weights_digest(&self) -> Result<String, Error> {
+		if let Some(digest) = &self.digest {
+			return Ok(digest.to_lowercase());
+		}
+
+		Ok(blake3::hash(&self.weights.read()?).to_hex().to_string())
+	}
+}
+
+/// Keeps track of which model is used for which role
+#[derive(Clone, Debug)]
+pub struct ModelRegistry {
+	detector: ModelSource,
+	recognizer: ModelSource,
+}
+
+impl ModelRegistry {
+	/// Creates a registry using the models compiled into this build
+	pub fn built_in() -> Result<Self, Error> {
+		Self::from_config(&ModelsConfig::default())
+	}
+
+	/// Creates a registry using the models from the config, falling back to the built-in ones for
+	/// roles which are not configured. The metadata of the configured models is validated
+	pub fn from_config(config: &ModelsConfig) -> Result<Self, Error> {
+		let weights_directory = config.weights_directory.as_deref();
+		let mut detector = None;
+		let mut recognizer = None;
+
+		for (role, model_config) in config.configured_models() {
+			let source = ModelSource::from_config(role, model_config)?;
+			match role {
+				ModelRole::Detector => detector = Some(source),
+				ModelRole::Recognizer => recognizer = Some(source),
+			}
+		}
+
+		Ok(Self {
+			detector: match detector {
+				Some(d) => d,
+				None => ModelSource::built_in(ModelRole::Detector, weights_directory)?,
+			},
+			recognizer: match recognizer {
+				Some(r) => r,
+				None => ModelSource::built_in(ModelRole::Recognizer, weights_directory)?,
+			},
+		})
+	}
+
+	/// Checks the integrity of the weights of all models without loading them
+	pub fn verify_integrity(&self) -> Result<(), Error> {
+		self.detector.read_weights()?;
+		self.recognizer.read_weights()?;
+
+		Ok(())
+	}
+
+	/// Returns the identity of the recognizer, so that embeddings produced by different models are
+	/// not compared with each other
+	pub fn recognizer_id(&self) -> Result<RecognizerId, Error> {
+		Ok(RecognizerId::new(self.recognizer.weights_digest()?))
+	}
+
+	pub const fn detector(&self) -> &ModelSource {
+		&self.detector
+	}
+
+	pub const fn recognizer(&self) -> &ModelSource {
+		&self.recognizer
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		InputSize, ModelMetadata, ModelRole, Normalization, OutputLayout, EMBEDDING_LENGTH,
+	};
+
+	#[test]
+	fn validates_metadata() {
+		let detector = ModelMetadata::built_in(ModelRole::Detector);
+		let recognizer = ModelMetadata::built_in(ModelRole::Recognizer);
+		let test_cases = vec![
+			(detector, ModelRole::Detector, true),
+			(recognizer, ModelRole::Recognizer, true),
+			(detector, ModelRole::Recognizer, false),
+			(recognizer, ModelRole::Detector, false),
+			(
+				ModelMetadata {
+					input_size: InputSize {
+						width: 112,
+						height: 112,
+					},
+					..recognizer
+				},
+				ModelRole::Recognizer,
+				false,
+			),
+			(
+				ModelMetadata {
+					output_layout: OutputLayout::Embedding {
+						length: EMBEDDING_LENGTH / 2,
+					},
+					..recognizer
+				},
+				ModelRole::Recognizer,
+				false,
+			),
+			(
+				ModelMetadata {
+					normalization: Normalization {
+						mean: 0.0,
+						std: 0.0,
+					},
+					..recognizer
+				},
+				ModelRole::Recognizer,
+				false,
+			),
+			(
+				ModelMetadata {
+					normalization: Normalization {
+						mean: 0.5,
+						std: 255.0,
+					},
+					..recognizer
+				},
+				ModelRole::Recognizer,
+				true,
+			),
+		];
+
+		for (metadata, role, expected_result) in test_cases {
+			assert_eq!(metadata.validate(role).is_ok(), expected_result);
+		}
+	}
+
+	#[test]
+	fn parses_metadata() {
+		let serialized = r"
+			output_layout = { embedding = { length = 512 } }
+
+			[input_size]
+			width = 128
+			height = 128
+
+			[normalization]
+			mean = 127.0
+			std = 128.0
+		";
+
+		let metadata: ModelMetadata = toml::from_str(serialized).expect("Failed to parse");
+		assert_eq!(metadata, ModelMetadata::built_in(ModelRole::Recognizer));
+	}
+}
@@ -0,0 +1,207 @@
+use super::{registry::ModelRole, Error};
+use std::{
+	borrow::Cow,
+	fmt::Display,
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// The environment variable which can be used to point Oblichey to a directory with weights
+#[cfg(not(feature = "embedded-weights"))]
+const WEIGHTS_DIRECTORY_ENV_VAR: &str = "OBLICHEY_WEIGHTS_DIR";
+/// The system-wide directory where packages are expected to install weights into
+#[cfg(not(feature = "embedded-weights"))]
+const SYSTEM_WEIGHTS_DIRECTORY: &str = "/usr/share/oblichey";
+/// The name of the directory next to the executable which the build script copies weights into
+#[cfg(not(feature = "embedded-weights"))]
+const WEIGHTS_DIRECTORY_NAME: &str = "weights";
+const WEIGHTS_FILE_EXTENSION: &str = "mpk";
+
+/// BLAKE3 digests of the built-in weights computed by the build script
+mod digests {
+	include!(concat!(env!("OUT_DIR"), "/weights_digests.rs"));
+}
+
+#[cfg(feature = "embedded-weights")]
+mod embedded {
+	pub const DETECTOR: &[u8] = include_bytes!(concat!(
+		env!("OUT_DIR"),
+		"/src/models/imported/detector.mpk"
+	));
+	pub const RECOGNIZER: &[u8] = include_bytes!(concat!(
+		env!("OUT_DIR"),
+		"/src/models/imported/recognizer.mpk"
+	));
+}
+
+/// Where the weights of a model can be found
+#[derive(Clone, Debug)]
+pub enum Weights {
+	File(PathBuf),
+	Embedded {
+		role: ModelRole,
+		bytes: &'static [u8],
+	},
+}
+
+impl Display for Weights {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::File(p) => write!(f, "{}", p.display()),
+			Self::Embedded { role, bytes: _ } => write!(f, "embedded {role} weights"),
+		}
+	}
+}
+
+impl Weights {
+	/// Returns the serialized weights
+	pub fn read(&self) -> Result<Cow<'static, [u8]>, Error> {
+		match self {
+			Self::File(p) => match fs::read(p) {
+				Ok(b) => Ok(Cow::Owned(b)),
+				Err(e) => Err(Error::WeightsIo(p.clone(), e)),
+			},
+			Self::Embedded { role: _, bytes } => Ok(Cow::Borrowed(bytes)),
+		}
+	}
+
+	/// Returns the BLAKE3 digest the built-in weights for the given role are expected to have
+	pub const fn built_in_digest(role: ModelRole) -> &'static str {
+		match role {
+			ModelRole::Detector => digests::DETECTOR,
+			ModelRole::Recognizer => digests::RECOGNIZER,
+		}
+	}
+
+	/// Checks that the serialized weights have the expected BLAKE3 digest
+	pub fn verify_digest(&self, bytes: &[u8], expected_digest: &str) -> Result<(), Error> {
+		let digest = blake3::hash(bytes).to_hex();
+		if !digest.eq_ignore_ascii_case(expected_digest) {
+			return Err(Error::DigestMismatch {
+				weights: self.clone(),
+				expected: expected_digest.to_owned(),
+				actual: digest.to_string(),
+			});
+		}
+
+		Ok(())
+	}
+
+	/// Returns the weights of a built-in model which were embedded into the executable
+	#[cfg(feature = "embedded-weights")]
+	pub const fn built_in(
+		role: ModelRole,
+		_weights_directory: Option<&Path>,
+	) -> Result<Self, Error> {
+		let bytes = match role {
+			ModelRole::Detector => embedded::DETECTOR,
+			ModelRole::Recognizer => embedded::RECOGNIZER,
+		};
+
+		Ok(Self::Embedded { role, bytes })
+	}
+
+	/// Looks for the weights of a built-in model in the directories returned by
+	/// `get_search_path()`
+	#[cfg(not(feature = "embedded-weights"))]
+	pub fn built_in(role: ModelRole, weights_directory: Option<&Path>) -> Result<Self, Error> {
+		let file_name = get_weights_file_name(role);
+		let candidates: Vec<PathBuf> = get_search_path(weights_directory)
+			.iter()
+			.map(|directory| directory.join(&file_name))
+			.collect();
+
+		if let Some(path) = candidates.iter().find(|candidate| candidate.is_file()) {
+			return Ok(Self::File(path.clone()));
+		}
+
+		Err(Error::WeightsNotFound(role, candidates))
+	}
+}
+
+fn get_weights_file_name(role: ModelRole) -> String {
+	format!("{role}.{WEIGHTS_FILE_EXTENSION}")
+}
+
+/// Returns the directories to look for weights in ordered by priority: the directory from the
+/// config, the directory from the environment, the system-wide directory and the directory next
+/// to the executable
+#[cfg(not(feature = "embedded-weights"))]
+fn get_search_path(weights_directory: Option<&Path>) -> Vec<PathBuf> {
+	let mut search_path = Vec::new();
+
+	if let Some(directory) = weights_directory {
+		search_path.push(directory.to_path_buf());
+	}
+	if let Some(directory) = std::env::var_os(WEIGHTS_DIRECTORY_ENV_VAR) {
+		search_path.push(PathBuf::from(directory));
+	}
+	search_path.push(PathBuf::from(SYSTEM_WEIGHTS_DIRECTORY));
+
+	if let Some(executable_directory) = std::env::current_exe()
+		.ok()
+		.as_deref()
+		.and_then(Path::parent)
+	{
+		// In tests the executable lives in the `deps` directory, so we need to go further up
+		#[cfg(test)]
+		let executable_directory = executable_directory
+			.parent()
+			.unwrap_or(executable_directory);
+
+		search_path.push(executable_directory.join(WEIGHTS_DIRECTORY_NAME));
+	}
+
+	search_path
+}
+
+#[cfg(test)]
+mod tests {
+	#[cfg(not(feature = "embedded-weights"))]
+	use super::{get_search_path, SYSTEM_WEIGHTS_DIRECTORY};
+	use super::{get_weights_file_name, Weights};
+	use crate::models::{registry::ModelRole, Error};
+	#[cfg(not(feature = "embedded-weights"))]
+	use std::path::Path;
+	use std::path::PathBuf;
+
+	#[test]
+	fn verifies_digest() {
+		let weights = Weights::File(PathBuf::from("/some/weights.mpk"));
+		let bytes = b"weights";
+		let digest = blake3::hash(bytes).to_hex().to_string();
+
+		assert!(weights.verify_digest(bytes, &digest).is_ok());
+		assert!(weights.verify_digest(bytes, &digest.to_uppercase()).is_ok());
+		assert!(matches!(
+			weights.verify_digest(b"tampered weights", &digest),
+			Err(Error::DigestMismatch { .. })
+		));
+		assert!(matches!(
+			weights.verify_digest(&bytes[..3], &digest),
+			Err(Error::DigestMismatch { .. })
+		));
+	}
+
+	#[test]
+	#[cfg(not(feature = "embedded-weights"))]
+	fn searches_configured_directory_first() {
+		let configured_directory = Path::new("/some/directory");
+		let search_path = get_search_path(Some(configured_directory));
+
+		assert_eq!(
+			search_path.first(),
+			Some(&configured_directory.to_path_buf())
+		);
+		assert!(search_path.contains(&PathBuf::from(SYSTEM_WEIGHTS_DIRECTORY)));
+	}
+
+	#[test]
+	fn gets_weights_file_name() {
+		assert_eq!(get_weights_file_name(ModelRole::Detector), "detector.mpk");
+		assert_eq!(
+			get_weights_file_name(ModelRole::Recognizer),
+			"recognizer.mpk"
+		);
+	}
+}
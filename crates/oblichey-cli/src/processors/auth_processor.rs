@@ -1,85 +1,415 @@
 use super::{
-	face::{FaceEmbedding, FaceForGUIAnnotationWarning, FaceForProcessing, SIMILARITY_THRESHOLD},
+	consensus::{Consensus, FaceMatch, FaceTracker},
+	face::{
+		Aggregation, FaceEmbedding, FaceForGUIAnnotationWarning, FaceForProcessing, FaceTemplate,
+		RecognizerId,
+	},
 	face_processor::FaceProcessor,
+	projection::Projection,
 	FaceForGUI,
 };
-use crate::processors::face::{FaceForGUIAnnotation, FaceRecognitionError};
-use std::{collections::HashMap, fmt::Debug, time::Instant};
+use crate::{
+	camera::Frame,
+	geometry::Rectangle,
+	processors::face::{FaceForGUIAnnotation, FaceRecognitionError},
+};
+use image::imageops::crop_imm;
+use log::warn;
+use serde::Deserialize;
+use std::{
+	collections::HashMap,
+	fmt::{Debug, Display},
+	time::{Duration, Instant},
+};
 
-const AUTH_TIMEOUT: u64 = 10; // In seconds
+/// How long to look for a known face before giving up in seconds, unless configured otherwise
+pub const DEFAULT_AUTH_TIMEOUT: u64 = 10;
 
+/// How faces are matched and when authentication succeeds or gives up
 #[derive(Debug, Clone, Copy)]
+pub struct AuthSettings {
+	/// Faces at least this similar to a scanned face are recognized as it
+	pub similarity_threshold: f32,
+	/// How the similarities to the templates of a face are combined
+	pub aggregation: Aggregation,
+	/// How many frames have to agree before the user is authenticated
+	pub consensus: Consensus,
+	pub timeout: Duration,
+	pub multi_face_policy: MultiFacePolicy,
+	/// Whether to keep the frame which shows who attempted to authenticate the best
+	pub keep_snapshot: bool,
+}
+
+/// What to do when there are multiple faces in a frame, for example because someone is looking
+/// over the shoulder of the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultiFacePolicy {
+	/// Authenticate if any of the faces is recognized
+	#[default]
+	Allow,
+	/// Only authenticate if all of the faces are recognized
+	RequireAllKnown,
+	/// Only authenticate if the recognized face is the largest one, which usually means it is the
+	/// closest to the camera
+	RequireLargest,
+	/// Never authenticate if there is more than one face
+	Deny,
+}
+
+impl MultiFacePolicy {
+	/// Checks whether the user recognized as the given face can be authenticated with the given
+	/// faces in the frame
+	fn check(
+		self,
+		faces: &[(Rectangle<u32>, Option<FaceMatch>)],
+		name: &str,
+	) -> Result<(), MultiFacePolicyViolation> {
+		match self {
+			Self::Allow => Ok(()),
+			Self::RequireAllKnown => {
+				if faces.iter().any(|(_, m)| m.is_none()) {
+					return Err(MultiFacePolicyViolation::UnknownFacePresent);
+				}
+				Ok(())
+			}
+			Self::RequireLargest => {
+				let largest_face = faces
+					.iter()
+					.max_by_key(|(r, _)| r.size().map_or(0, |s| u64::from(s.x) * u64::from(s.y)));
+				if largest_face.is_some_and(|(_, m)| m.as_ref().map_or(true, |m| m.name != name)) {
+					return Err(MultiFacePolicyViolation::NotLargest);
+				}
+				Ok(())
+			}
+			Self::Deny => {
+				if faces.len() > 1 {
+					return Err(MultiFacePolicyViolation::TooManyFaces);
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiFacePolicyViolation {
+	TooManyFaces,
+	UnknownFacePresent,
+	NotLargest,
+}
+
+impl From<MultiFacePolicyViolation> for FaceForGUIAnnotationWarning {
+	fn from(value: MultiFacePolicyViolation) -> Self {
+		match value {
+			MultiFacePolicyViolation::TooManyFaces => Self::TooManyFaces,
+			MultiFacePolicyViolation::UnknownFacePresent => Self::UnknownFacePresent,
+			MultiFacePolicyViolation::NotLargest => Self::NotLargest,
+		}
+	}
+}
+
+/// Why authentication failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailureReason {
+	/// No known face was recognized in time
+	TimedOut,
+	/// A known face was recognized, but the multi-face policy did not allow authenticating
+	MultiFacePolicy(MultiFacePolicyViolation),
+}
+
+impl Display for AuthFailureReason {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::TimedOut => write!(f, "No known face was recognized in time"),
+			Self::MultiFacePolicy(MultiFacePolicyViolation::TooManyFaces) => {
+				write!(
+					f,
+					"A known face was recognized, but there were multiple faces"
+				)
+			}
+			Self::MultiFacePolicy(MultiFacePolicyViolation::UnknownFacePresent) => write!(
+				f,
+				"A known face was recognized, but an unknown face was present as well"
+			),
+			Self::MultiFacePolicy(MultiFacePolicyViolation::NotLargest) => write!(
+				f,
+				"A known face was recognized, but it was not the closest face"
+			),
+		}
+	}
+}
+
+/// The frame which shows who attempted to authenticate the best
+#[derive(Debug, Clone)]
+pub struct AuthSnapshot {
+	pub frame: Frame,
+	/// The face most similar to a scanned face or any face, if none could be compared
+	pub face_rectangle: Option<Rectangle<u32>>,
+}
+
+impl AuthSnapshot {
+	/// Returns the image of the face, if there is one
+	pub fn get_face_image(&self) -> Option<Frame> {
+		let rectangle = self.face_rectangle?;
+		let size = rectangle.size()?;
+
+		Some(
+			crop_imm(
+				&self.frame,
+				rectangle.min.x,
+				rectangle.min.y,
+				size.x,
+				size.y,
+			)
+			.to_image(),
+		)
+	}
+}
+
+/// How well a frame shows who attempted to authenticate. Frames with faces are preferred and among
+/// them the ones with the face most similar to a scanned face
+type SnapshotRank = (bool, f32);
+
+#[derive(Debug, Clone)]
 pub struct AuthProcessorResult {
 	pub authenticated: bool,
+	/// This is `None` if the user was authenticated
+	pub failure_reason: Option<AuthFailureReason>,
+	/// The name of the scanned face the user was authenticated as
+	pub matched_face: Option<String>,
+	/// The highest similarity of any face to any scanned face, even if it was not recognized
+	pub best_similarity: Option<f32>,
+	/// The embedding of the matched face in the frame the user was authenticated in, as it was
+	/// compared with the templates, along with its similarity to them. This is `None` if the face
+	/// was not recognized in that frame
+	pub matched_embedding: Option<(FaceEmbedding, f32)>,
+	/// The number of processed frames
+	pub frame_count: usize,
+	/// This is `None` unless keeping snapshots was enabled
+	pub snapshot: Option<AuthSnapshot>,
 }
 
 #[derive(Debug)]
 pub struct AuthProcessor {
 	result: Option<AuthProcessorResult>,
-	stored_face_embeddings: HashMap<String, FaceEmbedding>,
+	/// A gallery of embeddings for each face
+	stored_face_embeddings: HashMap<String, Vec<FaceEmbedding>>,
+	similarity_threshold: f32,
+	/// Thresholds which replace `similarity_threshold` for individual faces
+	face_similarity_thresholds: HashMap<String, f32>,
+	aggregation: Aggregation,
+	timeout: Duration,
+	multi_face_policy: MultiFacePolicy,
+	/// The most recent reason for not authenticating a recognized face
+	last_policy_violation: Option<MultiFacePolicyViolation>,
+	/// The projection the stored templates were transformed with, if template projection is
+	/// enabled. Embeddings are projected the same way before being compared
+	projection: Option<Projection>,
+	/// Decides when enough frames agree on who a face is
+	face_tracker: FaceTracker,
 	start_time: Instant,
+	frame_count: usize,
+	best_similarity: Option<f32>,
+	keep_snapshot: bool,
+	snapshot: Option<(SnapshotRank, AuthSnapshot)>,
+	/// The rank and the face of the frame passed to the last `process_faces()` call, if it is
+	/// better than the snapshot, until the frame itself is passed to `keep_frame()`
+	snapshot_candidate: Option<(SnapshotRank, Option<Rectangle<u32>>)>,
 	testing_mode: bool,
 }
 
 impl AuthProcessor {
-	pub fn new(face_embeddings: HashMap<String, FaceEmbedding>, testing_mode: bool) -> Self {
+	/// Templates produced by a different recognizer than the given one are skipped as their
+	/// embeddings cannot be meaningfully compared
+	pub fn new(
+		face_templates: HashMap<String, Vec<FaceTemplate>>,
+		recognizer_id: &RecognizerId,
+		settings: AuthSettings,
+		projection: Option<Projection>,
+		testing_mode: bool,
+	) -> Self {
+		let mut stored_face_embeddings = HashMap::new();
+		for (name, templates) in face_templates {
+			let mut embeddings = Vec::new();
+			for template in templates {
+				if !template.is_compatible_with(recognizer_id) {
+					warn!("Skipping a template of face scan {name} as it was made by a different recognizer - it needs to be re-scanned");
+					continue;
+				}
+				if template.recognizer_id.is_none() {
+					warn!("A template of face scan {name} was made by an unknown recognizer - it should be re-scanned");
+				}
+				embeddings.push(template.embedding);
+			}
+			if !embeddings.is_empty() {
+				stored_face_embeddings.insert(name, embeddings);
+			}
+		}
+
 		Self {
 			result: None,
-			stored_face_embeddings: face_embeddings,
+			stored_face_embeddings,
+			similarity_threshold: settings.similarity_threshold,
+			face_similarity_thresholds: HashMap::new(),
+			aggregation: settings.aggregation,
+			timeout: settings.timeout,
+			multi_face_policy: settings.multi_face_policy,
+			last_policy_violation: None,
+			projection,
+			face_tracker: FaceTracker::new(settings.consensus),
 			testing_mode,
 			start_time: Instant::now(),
+			frame_count: 0,
+			best_similarity: None,
+			keep_snapshot: settings.keep_snapshot,
+			snapshot: None,
+			snapshot_candidate: None,
 		}
 	}
 
-	pub const fn get_result(&self) -> Option<AuthProcessorResult> {
-		self.result
+	/// Uses the given similarity thresholds instead of the one from the settings for the faces
+	/// they are given for, such as the ones calibrated for them
+	pub fn with_face_similarity_thresholds(
+		mut self,
+		face_similarity_thresholds: HashMap<String, f32>,
+	) -> Self {
+		self.face_similarity_thresholds = face_similarity_thresholds;
+		self
+	}
+
+	/// The snapshot is only added to the result now, as the frame which ended the attempt is passed
+	/// to `keep_frame()` after the result was made
+	pub fn get_result(&self) -> Option<AuthProcessorResult> {
+		self.result.clone().map(|r| AuthProcessorResult {
+			snapshot: self.snapshot.as_ref().map(|(_, s)| s.clone()),
+			..r
+		})
 	}
 
-	fn process_face(&self, face_for_processing: &FaceForProcessing) -> FaceForGUI {
+	/// Returns the scanned face the face is the most similar to regardless of the threshold, along
+	/// with how it is annotated in the GUI
+	pub fn identify(
+		&self,
+		face_for_processing: &FaceForProcessing,
+	) -> (FaceForGUI, Option<FaceMatch>) {
+		let (face_for_gui, closest_face, _) = self.process_face(face_for_processing);
+		(face_for_gui, closest_face)
+	}
+
+	fn get_similarity_threshold(&self, name: &str) -> f32 {
+		self.face_similarity_thresholds
+			.get(name)
+			.copied()
+			.unwrap_or(self.similarity_threshold)
+	}
+
+	fn finish(
+		&mut self,
+		matched_face: Option<String>,
+		matched_embedding: Option<(&FaceEmbedding, f32)>,
+		failure_reason: Option<AuthFailureReason>,
+	) {
+		self.result = Some(AuthProcessorResult {
+			authenticated: failure_reason.is_none(),
+			failure_reason,
+			matched_face,
+			best_similarity: self.best_similarity,
+			matched_embedding: matched_embedding.map(|(e, s)| (*e, s)),
+			frame_count: self.frame_count,
+			snapshot: None,
+		});
+	}
+
+	/// Remembers the given frame as a candidate for the snapshot, if it is better than the current
+	/// one
+	fn rank_frame(
+		&mut self,
+		closest_face: Option<(Rectangle<u32>, f32)>,
+		any_face: Option<Rectangle<u32>>,
+	) {
+		let face_rectangle = closest_face.map(|(r, _)| r).or(any_face);
+		let rank = (
+			face_rectangle.is_some(),
+			closest_face.map_or(f32::NEG_INFINITY, |(_, s)| s),
+		);
+		self.snapshot_candidate = self
+			.snapshot
+			.as_ref()
+			.map_or(true, |(r, _)| rank > *r)
+			.then_some((rank, face_rectangle));
+	}
+
+	/// Returns the face annotated for the GUI along with the most similar scanned face, even if it
+	/// is not similar enough for the face to be recognized, and the embedding of the face as it was
+	/// compared with the templates
+	fn process_face(
+		&self,
+		face_for_processing: &FaceForProcessing,
+	) -> (FaceForGUI, Option<FaceMatch>, Option<FaceEmbedding>) {
 		let face_data = match face_for_processing.face_data {
 			Ok(d) => d,
 			Err(e) => match e {
 				FaceRecognitionError::TooSmall => {
-					return FaceForGUI {
-						rectangle: face_for_processing.rectangle,
-						annotation: FaceForGUIAnnotation::Warning(
-							FaceForGUIAnnotationWarning::TooSmall,
-						),
-					}
+					return (
+						FaceForGUI {
+							rectangle: face_for_processing.rectangle,
+							annotation: FaceForGUIAnnotation::Warning(
+								FaceForGUIAnnotationWarning::TooSmall,
+							),
+						},
+						None,
+						None,
+					)
 				}
 			},
 		};
 
-		let mut best_match: Option<(String, f32)> = None;
-		for (stored_face_embedding_name, stored_face_embedding) in &self.stored_face_embeddings {
-			let similarity = face_data
-				.embedding
-				.cosine_similarity(stored_face_embedding)
-				.expect("Similarity should never be None");
-			if similarity < SIMILARITY_THRESHOLD {
+		let embedding = self
+			.projection
+			.as_ref()
+			.map_or(face_data.embedding, |p| p.project(&face_data.embedding));
+
+		let mut best_match: Option<FaceMatch> = None;
+		for (stored_face_embedding_name, stored_face_embeddings) in &self.stored_face_embeddings {
+			let similarities = stored_face_embeddings
+				.iter()
+				.map(|stored_face_embedding| {
+					embedding
+						.cosine_similarity(stored_face_embedding)
+						.expect("Similarity should never be None")
+				})
+				.collect();
+			let Some(similarity) = self.aggregation.aggregate(similarities) else {
 				continue;
-			}
-			match best_match {
-				Some((_, best_match_similarity)) => {
-					if similarity > best_match_similarity {
-						best_match = Some((stored_face_embedding_name.clone(), similarity));
-					}
-				}
-				None => best_match = Some((stored_face_embedding_name.clone(), similarity)),
+			};
+			if best_match
+				.as_ref()
+				.map_or(true, |m| similarity > m.similarity)
+			{
+				best_match = Some(FaceMatch {
+					name: stored_face_embedding_name.clone(),
+					similarity,
+				});
 			}
 		}
 
-		FaceForGUI {
+		let face_for_gui = FaceForGUI {
 			rectangle: face_for_processing.rectangle,
-			annotation: match best_match {
-				Some((name, _)) => FaceForGUIAnnotation::Name(name),
-				None => FaceForGUIAnnotation::Warning(FaceForGUIAnnotationWarning::NotRecognized),
-			},
-		}
+			annotation: best_match
+				.as_ref()
+				.filter(|m| m.similarity >= self.get_similarity_threshold(&m.name))
+				.map_or(
+					FaceForGUIAnnotation::Warning(FaceForGUIAnnotationWarning::NotRecognized),
+					|m| FaceForGUIAnnotation::Name(m.name.clone()),
+				),
+		};
+
+		(face_for_gui, best_match, Some(embedding))
 	}
 
 	fn have_timed_out(&self) -> bool {
-		if !self.testing_mode && self.start_time.elapsed().as_secs() > AUTH_TIMEOUT {
+		if !self.testing_mode && self.start_time.elapsed() > self.timeout {
 			return true;
 		}
 
@@ -94,44 +424,149 @@ impl FaceProcessor for AuthProcessor {
 
 	fn process_faces(&mut self, faces_for_processing: Vec<FaceForProcessing>) -> Vec<FaceForGUI> {
 		if self.have_timed_out() {
-			self.result = Some(AuthProcessorResult {
-				authenticated: false,
-			});
+			self.finish(
+				None,
+				None,
+				Some(self.last_policy_violation.map_or(
+					AuthFailureReason::TimedOut,
+					AuthFailureReason::MultiFacePolicy,
+				)),
+			);
 		}
+		self.frame_count += 1;
 
 		let mut processed_faces = Vec::new();
+		let mut face_matches = Vec::new();
+		let mut embeddings = Vec::new();
+		let mut closest_face_in_frame: Option<(Rectangle<u32>, f32)> = None;
 		for face_for_processing in faces_for_processing {
-			let processed_face = self.process_face(&face_for_processing);
-			if !self.testing_mode {
-				if let FaceForGUIAnnotation::Name(_) = processed_face.annotation {
-					self.result = Some(AuthProcessorResult {
-						authenticated: true,
-					});
+			let (processed_face, closest_face, embedding) = self.process_face(&face_for_processing);
+			if let Some(closest_face) = &closest_face {
+				if self
+					.best_similarity
+					.map_or(true, |s| closest_face.similarity > s)
+				{
+					self.best_similarity = Some(closest_face.similarity);
+				}
+				if closest_face_in_frame.map_or(true, |(_, s)| closest_face.similarity > s) {
+					closest_face_in_frame =
+						Some((face_for_processing.rectangle, closest_face.similarity));
 				}
 			}
-
+			let face_match =
+				closest_face.filter(|m| m.similarity >= self.get_similarity_threshold(&m.name));
+			face_matches.push((face_for_processing.rectangle, face_match));
+			embeddings.push(embedding);
 			processed_faces.push(processed_face);
 		}
 
+		if self.keep_snapshot {
+			self.rank_frame(closest_face_in_frame, face_matches.first().map(|(r, _)| *r));
+		}
+
+		// Show why recognized faces would not be authenticated
+		for (processed_face, (_, face_match)) in processed_faces.iter_mut().zip(&face_matches) {
+			let Some(face_match) = face_match else {
+				continue;
+			};
+			if let Err(violation) = self
+				.multi_face_policy
+				.check(&face_matches, &face_match.name)
+			{
+				processed_face.annotation = FaceForGUIAnnotation::Warning(violation.into());
+			}
+		}
+
+		let Some(name) = self.face_tracker.add_frame(face_matches.clone()) else {
+			return processed_faces;
+		};
+		match self.multi_face_policy.check(&face_matches, &name) {
+			Ok(()) => {
+				if !self.testing_mode {
+					let matched_embedding = face_matches
+						.iter()
+						.zip(&embeddings)
+						.filter_map(|((_, m), e)| Some((e.as_ref()?, m.as_ref()?)))
+						.filter(|(_, m)| m.name == name)
+						.max_by(|(_, a), (_, b)| a.similarity.total_cmp(&b.similarity))
+						.map(|(e, m)| (e, m.similarity));
+					self.finish(Some(name), matched_embedding, None);
+				}
+			}
+			Err(violation) => {
+				warn!("Face scan {name} was recognized, but not authenticated: {violation:?}");
+				self.last_policy_violation = Some(violation);
+			}
+		}
+
 		processed_faces
 	}
+
+	fn keep_frame(&mut self, frame: &Frame) {
+		if let Some((rank, face_rectangle)) = self.snapshot_candidate.take() {
+			self.snapshot = Some((
+				rank,
+				AuthSnapshot {
+					frame: frame.clone(),
+					face_rectangle,
+				},
+			));
+		}
+	}
 }
 
 #[cfg(test)]
 mod tests {
-	use super::AuthProcessor;
+	use super::{
+		AuthFailureReason, AuthProcessor, AuthSettings, MultiFacePolicy, MultiFacePolicyViolation,
+		DEFAULT_AUTH_TIMEOUT,
+	};
 	use crate::{
+		camera::Frame,
 		geometry::{Rectangle, Vec2D},
 		processors::{
+			consensus::Consensus,
 			face::{
-				FaceEmbedding, FaceForGUIAnnotation, FaceForGUIAnnotationWarning,
-				FaceForProcessing, FaceRecognitionData, FaceRecognitionError, EMBEDDING_LENGTH,
+				Aggregation, FaceEmbedding, FaceForGUIAnnotation, FaceForGUIAnnotationWarning,
+				FaceForProcessing, FaceRecognitionData, FaceRecognitionError, FaceTemplate,
+				RecognizerId, DEFAULT_SIMILARITY_THRESHOLD, EMBEDDING_LENGTH,
 			},
 			face_processor::FaceProcessor,
+			projection::Projection,
 		},
 	};
 	use core::panic;
-	use std::collections::HashMap;
+	use image::Rgb;
+	use std::{collections::HashMap, time::Duration};
+
+	fn get_recognizer_id() -> RecognizerId {
+		RecognizerId::new(String::from("recognizer"))
+	}
+
+	/// Authenticates on the first matching frame, so that each test only needs a single frame
+	const fn get_settings(aggregation: Aggregation) -> AuthSettings {
+		AuthSettings {
+			similarity_threshold: DEFAULT_SIMILARITY_THRESHOLD,
+			aggregation,
+			consensus: Consensus {
+				required_matches: 1,
+				window: 1,
+				min_mean_similarity: None,
+			},
+			timeout: Duration::from_secs(DEFAULT_AUTH_TIMEOUT),
+			multi_face_policy: MultiFacePolicy::Allow,
+			keep_snapshot: false,
+		}
+	}
+
+	fn get_face_templates(embedding: &FaceEmbedding) -> Vec<FaceTemplate> {
+		vec![FaceTemplate {
+			recognizer_id: Some(get_recognizer_id()),
+			embedding: *embedding,
+			pose: None,
+			adapted_at: None,
+		}]
+	}
 
 	#[test]
 	fn categorizes_faces() {
@@ -144,10 +579,16 @@ mod tests {
 		});
 		let face_embeddings = {
 			let mut embeddings = HashMap::new();
-			embeddings.insert(face_name.clone(), embedding);
+			embeddings.insert(face_name.clone(), get_face_templates(&embedding));
 			embeddings
 		};
-		let mut processor = AuthProcessor::new(face_embeddings, false);
+		let mut processor = AuthProcessor::new(
+			face_embeddings,
+			&get_recognizer_id(),
+			get_settings(Aggregation::Max),
+			None,
+			false,
+		);
 
 		let result = processor.process_faces(vec![
 			FaceForProcessing {
@@ -184,6 +625,9 @@ mod tests {
 		}
 		if let Some(result) = processor.get_result() {
 			assert!(result.authenticated);
+			assert_eq!(result.matched_face, Some(face_name));
+			assert_eq!(result.matched_embedding.map(|(e, _)| e), Some(embedding));
+			assert_eq!(result.frame_count, 1);
 		} else {
 			panic!();
 		}
@@ -208,12 +652,24 @@ mod tests {
 
 		let face_embeddings = {
 			let mut embeddings = HashMap::new();
-			embeddings.insert(face_name.clone(), correct_embedding);
-			embeddings.insert(String::from("bob"), incorrect_embedding_a);
-			embeddings.insert(String::from("charlie"), incorrect_embedding_b);
+			embeddings.insert(face_name.clone(), get_face_templates(&correct_embedding));
+			embeddings.insert(
+				String::from("bob"),
+				get_face_templates(&incorrect_embedding_a),
+			);
+			embeddings.insert(
+				String::from("charlie"),
+				get_face_templates(&incorrect_embedding_b),
+			);
 			embeddings
 		};
-		let mut processor = AuthProcessor::new(face_embeddings, false);
+		let mut processor = AuthProcessor::new(
+			face_embeddings,
+			&get_recognizer_id(),
+			get_settings(Aggregation::Max),
+			None,
+			false,
+		);
 
 		let result = processor.process_faces(vec![FaceForProcessing {
 			rectangle,
@@ -234,4 +690,397 @@ mod tests {
 			panic!();
 		}
 	}
+
+	#[test]
+	fn skips_templates_of_other_recognizers() {
+		let rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(0, 0));
+		let embedding = FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]);
+		let test_cases = vec![
+			(Some(get_recognizer_id()), true),
+			(None, true),
+			(Some(RecognizerId::new(String::from("other"))), false),
+		];
+
+		for (recognizer_id, expected_result) in test_cases {
+			let face_templates = HashMap::from([(
+				String::from("alice"),
+				vec![FaceTemplate {
+					recognizer_id,
+					embedding,
+					pose: None,
+					adapted_at: None,
+				}],
+			)]);
+			let mut processor = AuthProcessor::new(
+				face_templates,
+				&get_recognizer_id(),
+				get_settings(Aggregation::Max),
+				None,
+				false,
+			);
+
+			let result = processor.process_faces(vec![FaceForProcessing {
+				rectangle,
+				face_data: Ok(FaceRecognitionData { embedding }),
+			}]);
+
+			assert_eq!(result.len(), 1);
+			assert_eq!(
+				matches!(result[0].annotation, FaceForGUIAnnotation::Name(_)),
+				expected_result
+			);
+			assert_eq!(processor.get_result().is_some(), expected_result);
+		}
+	}
+
+	#[test]
+	fn matches_against_gallery() {
+		let rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(0, 0));
+		let get_embedding = |index: usize| {
+			FaceEmbedding::new(&{
+				let mut embedding = [0.0; EMBEDDING_LENGTH];
+				embedding[index] = 1.0;
+				embedding
+			})
+		};
+		let face_templates = HashMap::from([(
+			String::from("alice"),
+			[get_embedding(0), get_embedding(1)]
+				.iter()
+				.flat_map(get_face_templates)
+				.collect::<Vec<FaceTemplate>>(),
+		)]);
+		let test_cases = vec![
+			(Aggregation::Max, get_embedding(0), true),
+			(Aggregation::Max, get_embedding(1), true),
+			(Aggregation::Max, get_embedding(2), false),
+			(Aggregation::TopKMean(1), get_embedding(1), true),
+			(Aggregation::TopKMean(2), get_embedding(1), false),
+		];
+
+		for (aggregation, embedding, expected_result) in test_cases {
+			let mut processor = AuthProcessor::new(
+				face_templates.clone(),
+				&get_recognizer_id(),
+				get_settings(aggregation),
+				None,
+				false,
+			);
+
+			let result = processor.process_faces(vec![FaceForProcessing {
+				rectangle,
+				face_data: Ok(FaceRecognitionData { embedding }),
+			}]);
+
+			assert_eq!(result.len(), 1);
+			assert_eq!(
+				matches!(result[0].annotation, FaceForGUIAnnotation::Name(_)),
+				expected_result
+			);
+		}
+	}
+
+	#[test]
+	fn matches_projected_templates() {
+		let rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(0, 0));
+		let projection = Projection::new(String::from("key"), [1; 32]);
+		let embedding = FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]);
+		let face_templates = HashMap::from([(
+			String::from("alice"),
+			get_face_templates(&projection.project(&embedding)),
+		)]);
+		let test_cases = vec![(Some(projection), true), (None, false)];
+
+		for (projection, expected_result) in test_cases {
+			let mut processor = AuthProcessor::new(
+				face_templates.clone(),
+				&get_recognizer_id(),
+				get_settings(Aggregation::Max),
+				projection,
+				false,
+			);
+
+			let result = processor.process_faces(vec![FaceForProcessing {
+				rectangle,
+				face_data: Ok(FaceRecognitionData { embedding }),
+			}]);
+
+			assert_eq!(result.len(), 1);
+			assert_eq!(
+				matches!(result[0].annotation, FaceForGUIAnnotation::Name(_)),
+				expected_result
+			);
+		}
+	}
+
+	#[test]
+	fn requires_consensus() {
+		let rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(100, 100));
+		let embedding = FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]);
+		let face_templates =
+			HashMap::from([(String::from("alice"), get_face_templates(&embedding))]);
+		let mut processor = AuthProcessor::new(
+			face_templates,
+			&get_recognizer_id(),
+			AuthSettings {
+				consensus: Consensus {
+					required_matches: 3,
+					window: 5,
+					min_mean_similarity: None,
+				},
+				..get_settings(Aggregation::Max)
+			},
+			None,
+			false,
+		);
+
+		for expected_result in [false, false, true] {
+			processor.process_faces(vec![FaceForProcessing {
+				rectangle,
+				face_data: Ok(FaceRecognitionData { embedding }),
+			}]);
+			assert_eq!(processor.get_result().is_some(), expected_result);
+		}
+	}
+
+	#[test]
+	fn applies_multi_face_policy() {
+		let small_rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(100, 100));
+		let large_rectangle = Rectangle::new(Vec2D::new(200, 0), Vec2D::new(400, 200));
+		let known_embedding = FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]);
+		let unknown_embedding = FaceEmbedding::new(&{
+			let mut embedding = [0.0; EMBEDDING_LENGTH];
+			embedding[0] = 1.0;
+			embedding
+		});
+		let face_templates =
+			HashMap::from([(String::from("alice"), get_face_templates(&known_embedding))]);
+		let get_face = |rectangle, embedding| FaceForProcessing {
+			rectangle,
+			face_data: Ok(FaceRecognitionData { embedding }),
+		};
+		let known_small_unknown_large = vec![
+			get_face(small_rectangle, known_embedding),
+			get_face(large_rectangle, unknown_embedding),
+		];
+		let known_large_unknown_small = vec![
+			get_face(large_rectangle, known_embedding),
+			get_face(small_rectangle, unknown_embedding),
+		];
+		let test_cases = vec![
+			(
+				MultiFacePolicy::Allow,
+				known_small_unknown_large.clone(),
+				None,
+			),
+			(
+				MultiFacePolicy::RequireAllKnown,
+				known_large_unknown_small.clone(),
+				Some(MultiFacePolicyViolation::UnknownFacePresent),
+			),
+			(
+				MultiFacePolicy::RequireLargest,
+				known_small_unknown_large,
+				Some(MultiFacePolicyViolation::NotLargest),
+			),
+			(
+				MultiFacePolicy::RequireLargest,
+				known_large_unknown_small.clone(),
+				None,
+			),
+			(
+				MultiFacePolicy::Deny,
+				known_large_unknown_small,
+				Some(MultiFacePolicyViolation::TooManyFaces),
+			),
+			(
+				MultiFacePolicy::Deny,
+				vec![get_face(small_rectangle, known_embedding)],
+				None,
+			),
+		];
+
+		for (multi_face_policy, faces, expected_violation) in test_cases {
+			let mut processor = AuthProcessor::new(
+				face_templates.clone(),
+				&get_recognizer_id(),
+				AuthSettings {
+					multi_face_policy,
+					..get_settings(Aggregation::Max)
+				},
+				None,
+				false,
+			);
+
+			let result = processor.process_faces(faces);
+
+			assert_eq!(
+				processor.get_result().is_some(),
+				expected_violation.is_none()
+			);
+			assert_eq!(
+				result
+					.iter()
+					.any(|f| matches!(f.annotation, FaceForGUIAnnotation::Name(_))),
+				expected_violation.is_none()
+			);
+			if let Some(violation) = expected_violation {
+				assert!(result.iter().any(|f| matches!(
+					&f.annotation,
+					FaceForGUIAnnotation::Warning(w) if *w == violation.into()
+				)));
+			}
+		}
+	}
+
+	#[test]
+	fn reports_failure_reason() {
+		let rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(100, 100));
+		let embedding = FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]);
+		let face_templates =
+			HashMap::from([(String::from("alice"), get_face_templates(&embedding))]);
+		let mut processor = AuthProcessor::new(
+			face_templates,
+			&get_recognizer_id(),
+			AuthSettings {
+				timeout: Duration::ZERO,
+				multi_face_policy: MultiFacePolicy::Deny,
+				..get_settings(Aggregation::Max)
+			},
+			None,
+			false,
+		);
+		let face = FaceForProcessing {
+			rectangle,
+			face_data: Ok(FaceRecognitionData { embedding }),
+		};
+
+		processor.process_faces(vec![]);
+		let timed_out_result = processor.get_result();
+		processor.process_faces(vec![face.clone(), face]);
+		processor.process_faces(vec![]);
+		let rejected_result = processor.get_result();
+
+		assert_eq!(
+			timed_out_result.and_then(|r| r.failure_reason),
+			Some(AuthFailureReason::TimedOut)
+		);
+		assert_eq!(
+			rejected_result.and_then(|r| r.failure_reason),
+			Some(AuthFailureReason::MultiFacePolicy(
+				MultiFacePolicyViolation::TooManyFaces
+			))
+		);
+	}
+
+	#[test]
+	fn uses_face_similarity_thresholds() {
+		let rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(0, 0));
+		let stored_embedding = FaceEmbedding::new(&{
+			let mut embedding = [0.0; EMBEDDING_LENGTH];
+			embedding[0] = 1.0;
+			embedding
+		});
+		// 0.6 similar to the stored embedding, which is below the default threshold
+		let scanned_embedding = FaceEmbedding::new(&{
+			let mut embedding = [0.0; EMBEDDING_LENGTH];
+			embedding[0] = 0.6;
+			embedding[1] = 0.8;
+			embedding
+		});
+		let test_cases = vec![(None, false), (Some(0.5), true), (Some(0.7), false)];
+
+		for (face_similarity_threshold, expected_authenticated) in test_cases {
+			let mut face_templates = HashMap::new();
+			face_templates.insert(String::from("alice"), get_face_templates(&stored_embedding));
+			let mut processor = AuthProcessor::new(
+				face_templates,
+				&get_recognizer_id(),
+				get_settings(Aggregation::Max),
+				None,
+				false,
+			)
+			.with_face_similarity_thresholds(
+				face_similarity_threshold
+					.map(|t| (String::from("alice"), t))
+					.into_iter()
+					.collect(),
+			);
+
+			let face = FaceForProcessing {
+				rectangle,
+				face_data: Ok(FaceRecognitionData {
+					embedding: scanned_embedding,
+				}),
+			};
+
+			// The closest face is returned even if it is not recognized
+			let (face_for_gui, closest_face) = processor.identify(&face);
+			assert!(closest_face
+				.is_some_and(|m| m.name == "alice" && (m.similarity - 0.6).abs() < 1e-6));
+			assert_eq!(
+				matches!(face_for_gui.annotation, FaceForGUIAnnotation::Name(_)),
+				expected_authenticated
+			);
+
+			processor.process_faces(vec![face]);
+			assert_eq!(
+				processor.get_result().is_some_and(|r| r.authenticated),
+				expected_authenticated
+			);
+		}
+	}
+
+	#[test]
+	fn keeps_best_snapshot() {
+		let get_embedding = |similarity: f32| {
+			let mut embedding = [0.0; EMBEDDING_LENGTH];
+			embedding[0] = similarity;
+			embedding[1] = similarity.mul_add(-similarity, 1.0).sqrt();
+			FaceEmbedding::new(&embedding)
+		};
+		let face_templates = HashMap::from([(
+			String::from("alice"),
+			get_face_templates(&get_embedding(1.0)),
+		)]);
+		// Authentication times out on every frame, so that there always is a result
+		let mut processor = AuthProcessor::new(
+			face_templates,
+			&get_recognizer_id(),
+			AuthSettings {
+				similarity_threshold: 0.99,
+				timeout: Duration::ZERO,
+				keep_snapshot: true,
+				..get_settings(Aggregation::Max)
+			},
+			None,
+			false,
+		);
+		let rectangle = Rectangle::new(Vec2D::new(1, 1), Vec2D::new(3, 3));
+		// Each frame is filled with its index, so that it can be told which one was kept
+		let frames = vec![None, Some(0.3), Some(0.8), Some(0.5), Some(0.9)];
+
+		for (index, similarity) in (0_u8..).zip(frames) {
+			let faces = similarity
+				.map(|s| FaceForProcessing {
+					rectangle,
+					face_data: Ok(FaceRecognitionData {
+						embedding: get_embedding(s),
+					}),
+				})
+				.into_iter()
+				.collect();
+			processor.process_faces(faces);
+			processor.keep_frame(&Frame::from_pixel(4, 4, Rgb([index; 3])));
+		}
+		let Some(snapshot) = processor.get_result().and_then(|r| r.snapshot) else {
+			panic!("No snapshot was kept");
+		};
+		let face_image = snapshot.get_face_image().expect("No face image");
+
+		// The best frame is the last one, which ended the attempt
+		assert_eq!(snapshot.frame.get_pixel(0, 0), &Rgb([4; 3]));
+		assert_eq!(snapshot.face_rectangle, Some(rectangle));
+		assert_eq!(face_image.dimensions(), (2, 2));
+	}
 }
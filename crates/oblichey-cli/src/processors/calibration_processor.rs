@@ -0,0 +1,140 @@
+use super::{
+	face::{
+		FaceEmbedding, FaceForGUIAnnotation, FaceForGUIAnnotationWarning, FaceForProcessing,
+		FaceRecognitionError,
+	},
+	face_processor::FaceProcessor,
+	FaceForGUI,
+};
+use std::fmt::Debug;
+
+/// The number of samples of the user captured during calibration, unless configured otherwise
+pub const DEFAULT_CALIBRATION_SAMPLE_COUNT: usize = 30;
+/// A sample is only captured every this many frames, as consecutive frames are almost identical
+/// and would make the samples look more consistent than authentications are
+const SAMPLE_FRAME_INTERVAL: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct CalibrationProcessorResult {
+	pub face_embeddings: Vec<FaceEmbedding>,
+}
+
+/// Captures samples of a single face as they are, unlike `ScanProcessor` which only keeps samples
+/// similar to each other, so that they show how similar authentications of the user are
+#[derive(Debug)]
+pub struct CalibrationProcessor {
+	result: Option<CalibrationProcessorResult>,
+	embedding_samples: Vec<FaceEmbedding>,
+	sample_count: usize,
+	/// The number of frames with a single usable face until the next sample is captured
+	frames_until_sample: usize,
+}
+
+impl CalibrationProcessor {
+	pub const fn new(sample_count: usize) -> Self {
+		Self {
+			result: None,
+			embedding_samples: Vec::new(),
+			sample_count,
+			frames_until_sample: 0,
+		}
+	}
+
+	pub fn get_result(&self) -> Option<CalibrationProcessorResult> {
+		self.result.clone()
+	}
+}
+
+impl FaceProcessor for CalibrationProcessor {
+	fn is_finished(&self) -> bool {
+		self.result.is_some()
+	}
+
+	fn process_faces(&mut self, faces: Vec<FaceForProcessing>) -> Vec<FaceForGUI> {
+		// It cannot be told which of multiple faces belongs to the user
+		if faces.len() > 1 {
+			return faces
+				.into_iter()
+				.map(|f| FaceForGUI {
+					rectangle: f.rectangle,
+					annotation: FaceForGUIAnnotation::Warning(
+						FaceForGUIAnnotationWarning::TooManyFaces,
+					),
+				})
+				.collect();
+		}
+		let Some(face) = faces.first() else {
+			return vec![];
+		};
+		let face_data = match face.face_data {
+			Ok(f) => f,
+			Err(FaceRecognitionError::TooSmall) => {
+				return vec![FaceForGUI {
+					rectangle: face.rectangle,
+					annotation: FaceForGUIAnnotation::Warning(
+						FaceForGUIAnnotationWarning::TooSmall,
+					),
+				}];
+			}
+		};
+
+		if self.frames_until_sample == 0 {
+			self.embedding_samples.push(face_data.embedding);
+			self.frames_until_sample = SAMPLE_FRAME_INTERVAL;
+		}
+		self.frames_until_sample -= 1;
+		if self.embedding_samples.len() >= self.sample_count {
+			self.result = Some(CalibrationProcessorResult {
+				face_embeddings: self.embedding_samples.clone(),
+			});
+		}
+
+		vec![FaceForGUI {
+			rectangle: face.rectangle,
+			annotation: FaceForGUIAnnotation::ScanningState {
+				scanned_sample_count: self.embedding_samples.len(),
+				required_sample_count: self.sample_count,
+				pose: None,
+				captured_pose_count: 0,
+				required_pose_count: 0,
+			},
+		}]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CalibrationProcessor, SAMPLE_FRAME_INTERVAL};
+	use crate::{
+		geometry::{Rectangle, Vec2D},
+		processors::{
+			face::{FaceEmbedding, FaceForProcessing, FaceRecognitionData, EMBEDDING_LENGTH},
+			face_processor::FaceProcessor,
+		},
+	};
+
+	#[test]
+	fn collects_samples() {
+		let sample_count = 4;
+		let mut processor = CalibrationProcessor::new(sample_count);
+		let face = FaceForProcessing {
+			rectangle: Rectangle::new(Vec2D::new(0, 0), Vec2D::new(0, 0)),
+			face_data: Ok(FaceRecognitionData {
+				embedding: FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]),
+			}),
+		};
+
+		for _ in 0..(sample_count - 1) * SAMPLE_FRAME_INTERVAL {
+			processor.process_faces(vec![face.clone()]);
+		}
+		// Frames with multiple faces are skipped
+		processor.process_faces(vec![face.clone(), face.clone()]);
+		assert!(!processor.is_finished());
+
+		processor.process_faces(vec![face]);
+		let Some(result) = processor.get_result() else {
+			panic!("Calibration did not finish");
+		};
+		assert_eq!(result.face_embeddings.len(), sample_count);
+	}
+}
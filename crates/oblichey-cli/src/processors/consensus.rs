@@ -0,0 +1,287 @@
+use crate::geometry::Rectangle;
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+/// Faces in consecutive frames whose rectangles overlap at least this much are considered to be
+/// the same face
+const TRACKING_INTERSECTION_OVER_UNION_THRESHOLD: f32 = 0.3;
+
+/// How many frames have to agree on who a face is before the user is authenticated, so that a
+/// single noisy frame cannot unlock
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Consensus {
+	/// The number of frames in which the face has to match the same scanned face
+	pub required_matches: usize,
+	/// The number of most recent frames of the face which are considered
+	pub window: usize,
+	/// The minimum mean similarity of the matching frames
+	pub min_mean_similarity: Option<f32>,
+}
+
+impl Default for Consensus {
+	fn default() -> Self {
+		Self {
+			required_matches: 3,
+			window: 5,
+			min_mean_similarity: None,
+		}
+	}
+}
+
+/// The scanned face a face in a frame was recognized as
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaceMatch {
+	pub name: String,
+	pub similarity: f32,
+}
+
+/// A face followed across frames
+#[derive(Debug)]
+struct Track {
+	rectangle: Rectangle<u32>,
+	/// The matches of the face in the most recent frames, oldest first. This is `None` for frames
+	/// in which the face was not recognized or not found at all
+	matches: VecDeque<Option<FaceMatch>>,
+}
+
+impl Track {
+	/// Returns the name of the scanned face the track matches according to the consensus
+	fn get_consensus(&self, consensus: &Consensus) -> Option<&str> {
+		let required_matches = consensus.required_matches.max(1);
+
+		self.matches.iter().flatten().find_map(|candidate| {
+			let similarities: Vec<f32> = self
+				.matches
+				.iter()
+				.flatten()
+				.filter(|m| m.name == candidate.name)
+				.map(|m| m.similarity)
+				.collect();
+			if similarities.len() < required_matches {
+				return None;
+			}
+			let mean_similarity = similarities.iter().sum::<f32>() / similarities.len() as f32;
+			if consensus
+				.min_mean_similarity
+				.is_some_and(|s| mean_similarity < s)
+			{
+				return None;
+			}
+
+			Some(candidate.name.as_str())
+		})
+	}
+}
+
+/// Follows faces across frames by how much their rectangles overlap and decides when enough frames
+/// agree on who one of them is
+#[derive(Debug)]
+pub struct FaceTracker {
+	consensus: Consensus,
+	tracks: Vec<Track>,
+}
+
+impl FaceTracker {
+	pub const fn new(consensus: Consensus) -> Self {
+		Self {
+			consensus,
+			tracks: Vec::new(),
+		}
+	}
+
+	/// Adds the faces found in a frame along with what they were recognized as. Returns the name
+	/// of the scanned face one of the faces matches according to the consensus
+	pub fn add_frame(&mut self, faces: Vec<(Rectangle<u32>, Option<FaceMatch>)>) -> Option<String> {
+		let window = self.consensus.window.max(1);
+		let mut tracks = Vec::new();
+		for (rectangle, face_match) in faces {
+			let closest_track_index = self
+				.tracks
+				.iter()
+				.enumerate()
+				.filter_map(|(i, t)| Some((i, t.rectangle.intersection_over_union(&rectangle)?)))
+				.filter(|(_, iou)| *iou >= TRACKING_INTERSECTION_OVER_UNION_THRESHOLD)
+				.max_by(|(_, a), (_, b)| a.total_cmp(b))
+				.map(|(i, _)| i);
+			let mut track = closest_track_index.map_or_else(
+				|| Track {
+					rectangle,
+					matches: VecDeque::new(),
+				},
+				|i| self.tracks.swap_remove(i),
+			);
+			track.rectangle = rectangle;
+			track.matches.push_back(face_match);
+			tracks.push(track);
+		}
+		// Faces which were not found in this frame count as not matching
+		for mut track in self.tracks.drain(..) {
+			track.matches.push_back(None);
+			tracks.push(track);
+		}
+		for track in &mut tracks {
+			while track.matches.len() > window {
+				track.matches.pop_front();
+			}
+		}
+		tracks.retain(|t| t.matches.iter().any(Option::is_some));
+		self.tracks = tracks;
+
+		self.tracks
+			.iter()
+			.find_map(|t| t.get_consensus(&self.consensus))
+			.map(ToOwned::to_owned)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Consensus, FaceMatch, FaceTracker};
+	use crate::geometry::{Rectangle, Vec2D};
+
+	fn get_rectangle(x: u32) -> Rectangle<u32> {
+		Rectangle::new(Vec2D::new(x, 0), Vec2D::new(x + 100, 100))
+	}
+
+	fn get_match(name: &str, similarity: f32) -> FaceMatch {
+		FaceMatch {
+			name: name.to_owned(),
+			similarity,
+		}
+	}
+
+	/// Adds frames with a single face and returns the index of the first frame in which consensus
+	/// was reached
+	fn get_consensus_frame(
+		consensus: Consensus,
+		frames: Vec<(Rectangle<u32>, Option<FaceMatch>)>,
+	) -> Option<usize> {
+		let mut face_tracker = FaceTracker::new(consensus);
+		frames
+			.into_iter()
+			.position(|face| face_tracker.add_frame(vec![face]).is_some())
+	}
+
+	#[test]
+	fn ignores_single_outliers() {
+		let consensus = Consensus::default();
+		let rectangle = get_rectangle(0);
+		let test_cases = vec![
+			// A single matching frame does not unlock
+			(
+				vec![
+					(rectangle, None),
+					(rectangle, Some(get_match("alice", 0.9))),
+					(rectangle, None),
+					(rectangle, None),
+					(rectangle, None),
+					(rectangle, None),
+				],
+				None,
+			),
+			// A single frame which does not match does not prevent unlocking
+			(
+				vec![
+					(rectangle, Some(get_match("alice", 0.9))),
+					(rectangle, None),
+					(rectangle, Some(get_match("alice", 0.9))),
+					(rectangle, Some(get_match("alice", 0.9))),
+				],
+				Some(3),
+			),
+			// A single frame matching someone else does not count
+			(
+				vec![
+					(rectangle, Some(get_match("alice", 0.9))),
+					(rectangle, Some(get_match("bob", 0.9))),
+					(rectangle, Some(get_match("alice", 0.9))),
+					(rectangle, Some(get_match("bob", 0.9))),
+					(rectangle, Some(get_match("alice", 0.9))),
+				],
+				Some(4),
+			),
+		];
+
+		for (frames, expected_result) in test_cases {
+			assert_eq!(get_consensus_frame(consensus, frames), expected_result);
+		}
+	}
+
+	#[test]
+	fn ignores_flickering_matches() {
+		let consensus = Consensus {
+			required_matches: 3,
+			window: 4,
+			min_mean_similarity: None,
+		};
+		let rectangle = get_rectangle(0);
+		let flickering_frames = (0..20)
+			.map(|i| (rectangle, (i % 2 == 0).then(|| get_match("alice", 0.9))))
+			.collect();
+		let flickering_identity_frames = (0..20)
+			.map(|i| {
+				let name = if i % 2 == 0 { "alice" } else { "bob" };
+				(rectangle, Some(get_match(name, 0.9)))
+			})
+			.collect();
+
+		assert_eq!(get_consensus_frame(consensus, flickering_frames), None);
+		assert_eq!(
+			get_consensus_frame(consensus, flickering_identity_frames),
+			None
+		);
+	}
+
+	#[test]
+	fn requires_mean_similarity() {
+		let consensus = Consensus {
+			required_matches: 2,
+			window: 2,
+			min_mean_similarity: Some(0.6),
+		};
+		let rectangle = get_rectangle(0);
+		let test_cases = vec![
+			(vec![0.5, 0.5, 0.5], None),
+			(vec![0.5, 0.8, 0.5], Some(1)),
+			(vec![0.5, 0.6, 0.65], Some(2)),
+		];
+
+		for (similarities, expected_result) in test_cases {
+			let frames = similarities
+				.into_iter()
+				.map(|s| (rectangle, Some(get_match("alice", s))))
+				.collect();
+			assert_eq!(get_consensus_frame(consensus, frames), expected_result);
+		}
+	}
+
+	#[test]
+	fn tracks_faces_by_overlap() {
+		let consensus = Consensus::default();
+		let moving_frames = (0..3)
+			.map(|i| (get_rectangle(i * 20), Some(get_match("alice", 0.9))))
+			.collect();
+		let jumping_frames = (0..5)
+			.map(|i| (get_rectangle(i * 200), Some(get_match("alice", 0.9))))
+			.collect();
+
+		assert_eq!(get_consensus_frame(consensus, moving_frames), Some(2));
+		assert_eq!(get_consensus_frame(consensus, jumping_frames), None);
+
+		// Two faces matching different scanned faces in the same frames are kept apart
+		let mut face_tracker = FaceTracker::new(consensus);
+		for _ in 0..2 {
+			let result = face_tracker.add_frame(vec![
+				(get_rectangle(0), Some(get_match("alice", 0.9))),
+				(get_rectangle(300), Some(get_match("bob", 0.9))),
+			]);
+			assert_eq!(result, None);
+		}
+		let result = face_tracker.add_frame(vec![
+			(get_rectangle(300), Some(get_match("bob", 0.9))),
+			(get_rectangle(10), Some(get_match("alice", 0.9))),
+		]);
+		assert!(result.is_some());
+	}
+}
@@ -1,11 +1,16 @@
 use crate::geometry::Rectangle;
+use chrono::{DateTime, Utc};
 use num::pow::Pow;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
-use std::ops::{AddAssign, Div};
+use std::{
+	fmt::Display,
+	ops::{AddAssign, Div, Mul},
+};
 
 pub const EMBEDDING_LENGTH: usize = 512;
-pub const SIMILARITY_THRESHOLD: f32 = 0.9;
+/// Faces at least this similar to a scanned face are recognized as it, unless configured otherwise
+pub const DEFAULT_SIMILARITY_THRESHOLD: f32 = 0.9;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum FaceRecognitionError {
@@ -53,11 +58,28 @@ impl Div<f32> for FaceEmbedding {
 	}
 }
 
+impl Mul<f32> for FaceEmbedding {
+	type Output = Self;
+
+	fn mul(self, rhs: f32) -> Self::Output {
+		let mut out = Self::default();
+		for index in 0..EMBEDDING_LENGTH {
+			out.data[index] = self.data[index] * rhs;
+		}
+
+		out
+	}
+}
+
 impl FaceEmbedding {
 	pub const fn new(data: &FaceEmbeddingData) -> Self {
 		Self { data: *data }
 	}
 
+	pub const fn data(&self) -> &FaceEmbeddingData {
+		&self.data
+	}
+
 	pub fn magnitude(&self) -> f32 {
 		let mut sum: f32 = 0.0;
 		for element in self.data {
@@ -85,6 +107,16 @@ impl FaceEmbedding {
 		}
 	}
 
+	/// Returns the embedding scaled to a magnitude of 1, unless all of its elements are 0
+	pub fn normalized(&self) -> Self {
+		let magnitude = self.magnitude();
+		if magnitude == 0.0 {
+			return *self;
+		}
+
+		*self / magnitude
+	}
+
 	pub fn average_embedding(embeddings: &[Self]) -> Self {
 		let mut sum_embedding = Self::default();
 
@@ -96,6 +128,135 @@ impl FaceEmbedding {
 	}
 }
 
+/// Identifies the recognizer model which produced a `FaceEmbedding`. This is the BLAKE3 digest of
+/// the model's weights. Embeddings produced by different models cannot be compared
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RecognizerId(String);
+
+impl RecognizerId {
+	pub const fn new(digest: String) -> Self {
+		Self(digest)
+	}
+}
+
+impl Display for RecognizerId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// A pose the user is asked to take during guided enrollment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pose {
+	Straight,
+	Left,
+	Right,
+	Up,
+	Down,
+}
+
+impl Display for Pose {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Straight => write!(f, "straight"),
+			Self::Left => write!(f, "left"),
+			Self::Right => write!(f, "right"),
+			Self::Up => write!(f, "up"),
+			Self::Down => write!(f, "down"),
+		}
+	}
+}
+
+impl Pose {
+	/// The poses guided enrollment goes through. The straight pose has to be first as the other
+	/// ones are compared with it
+	pub const GUIDED_SEQUENCE: [Self; 5] = [
+		Self::Straight,
+		Self::Left,
+		Self::Right,
+		Self::Up,
+		Self::Down,
+	];
+
+	pub const fn instruction(self) -> &'static str {
+		match self {
+			Self::Straight => "Look straight at the camera",
+			Self::Left => "Slightly turn your head to the left",
+			Self::Right => "Slightly turn your head to the right",
+			Self::Up => "Slightly tilt your head up",
+			Self::Down => "Slightly tilt your head down",
+		}
+	}
+}
+
+/// A stored `FaceEmbedding` along with the identity of the recognizer which produced it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaceTemplate {
+	/// This is `None` for templates which were created before recognizer identities were recorded
+	pub recognizer_id: Option<RecognizerId>,
+	pub embedding: FaceEmbedding,
+	/// The pose the user was asked to take when the template was captured during guided
+	/// enrollment. There is no head pose estimate, so it is not verified that they actually took it
+	#[serde(default)]
+	pub pose: Option<Pose>,
+	/// When the template was learned from a successful authentication. This is `None` for
+	/// templates captured when the face was scanned
+	#[serde(default)]
+	pub adapted_at: Option<DateTime<Utc>>,
+}
+
+impl FaceTemplate {
+	/// Returns `true` if the template can be compared with embeddings produced by the given
+	/// recognizer. Templates of unknown origin are assumed to be compatible
+	pub fn is_compatible_with(&self, recognizer_id: &RecognizerId) -> bool {
+		self.recognizer_id
+			.as_ref()
+			.map_or(true, |id| id == recognizer_id)
+	}
+
+	/// Returns `true` if the template should be re-enrolled to be sure it is compared with
+	/// embeddings produced by the same recognizer
+	pub fn needs_reenrollment(&self, recognizer_id: &RecognizerId) -> bool {
+		self.recognizer_id.as_ref() != Some(recognizer_id)
+	}
+
+	pub const fn is_adapted(&self) -> bool {
+		self.adapted_at.is_some()
+	}
+}
+
+/// How the similarities of a face to each template in a gallery are combined into a single score
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Aggregation {
+	/// Use the similarity of the best matching template
+	#[default]
+	Max,
+	/// Use the mean similarity of the given number of best matching templates. This is more robust
+	/// against a single template matching by chance
+	TopKMean(usize),
+}
+
+impl Aggregation {
+	/// Returns `None` if there are no similarities to aggregate
+	pub fn aggregate(&self, mut similarities: Vec<f32>) -> Option<f32> {
+		similarities.sort_by(|a, b| b.total_cmp(a));
+
+		match self {
+			Self::Max => similarities.first().copied(),
+			Self::TopKMean(k) => {
+				let top = &similarities[..similarities.len().min(*k)];
+				if top.is_empty() {
+					return None;
+				}
+
+				Some(top.iter().sum::<f32>() / top.len() as f32)
+			}
+		}
+	}
+}
+
 /// This is the data produced by the recognition model and in the future by other models (such as
 /// those for liveness detection)
 #[derive(Debug, Clone, Copy)]
@@ -116,6 +277,10 @@ pub enum FaceForGUIAnnotationWarning {
 	NotRecognized,
 	TooSmall,
 	TooManyFaces,
+	/// The face was recognized, but an unknown face is present as well
+	UnknownFacePresent,
+	/// The face was recognized, but it is not the largest face
+	NotLargest,
 }
 
 #[derive(Clone, Debug)]
@@ -125,6 +290,10 @@ pub enum FaceForGUIAnnotation {
 	ScanningState {
 		scanned_sample_count: usize,
 		required_sample_count: usize,
+		/// The pose the user should take during guided enrollment
+		pose: Option<Pose>,
+		captured_pose_count: usize,
+		required_pose_count: usize,
 	},
 }
 
@@ -137,7 +306,7 @@ pub struct FaceForGUI {
 
 #[cfg(test)]
 mod tests {
-	use super::{FaceEmbedding, EMBEDDING_LENGTH};
+	use super::{Aggregation, FaceEmbedding, EMBEDDING_LENGTH};
 	use core::f32;
 
 	#[test]
@@ -296,4 +465,27 @@ mod tests {
 			assert!((magnitude_difference) <= f32::EPSILON);
 		}
 	}
+
+	#[test]
+	fn aggregates_similarities() {
+		let test_cases = vec![
+			(Aggregation::Max, vec![], None),
+			(Aggregation::Max, vec![0.5, 0.9, 0.7], Some(0.9)),
+			(Aggregation::TopKMean(2), vec![], None),
+			(Aggregation::TopKMean(0), vec![0.5], None),
+			(Aggregation::TopKMean(2), vec![0.5, 0.9, 0.7], Some(0.8)),
+			(Aggregation::TopKMean(5), vec![0.5, 0.9, 0.7], Some(0.7)),
+		];
+
+		for (aggregation, similarities, expected_result) in test_cases {
+			let result = aggregation.aggregate(similarities);
+			match (result, expected_result) {
+				(Some(result), Some(expected_result)) => {
+					assert!((expected_result - result).abs() <= f32::EPSILON);
+				}
+				(None, None) => {}
+				_ => panic!(),
+			}
+		}
+	}
 }
@@ -1,8 +1,12 @@
 use super::{face::FaceForProcessing, FaceForGUI};
+use crate::camera::Frame;
 use std::fmt::Debug;
 
 pub trait FaceProcessor: Debug {
 	/// Processes faces found by `FrameProcessor`
 	fn process_faces(&mut self, detected_faces: Vec<FaceForProcessing>) -> Vec<FaceForGUI>;
+	/// Receives the frame the faces passed to the last `process_faces()` call were found in, so
+	/// that it can be kept. Most processors do not need frames, so this does nothing by default
+	fn keep_frame(&mut self, _frame: &Frame) {}
 	fn is_finished(&self) -> bool;
 }
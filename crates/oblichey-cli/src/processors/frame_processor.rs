@@ -4,21 +4,51 @@ use crate::models::detector::FaceDetector;
 #[double]
 use crate::models::recognizer::FaceRecognizer;
 use crate::{
-	camera::Frame, geometry::Rectangle, models::recognizer::RECOGNIZER_INPUT_SIZE,
+	camera::Frame,
+	config::{self, DetectorConfig},
+	geometry::Rectangle,
+	models::{self, recognizer::RECOGNIZER_INPUT_SIZE, registry::ModelRegistry},
 	processors::face::FaceRecognitionError,
 };
-#[cfg(test)]
-use burn::backend::{ndarray::NdArrayDevice, NdArray};
-#[cfg(not(test))]
+#[cfg(feature = "ndarray")]
+use burn::backend::ndarray::NdArrayDevice;
+#[cfg(any(feature = "ndarray", test))]
+use burn::backend::NdArray;
+#[cfg(feature = "wgpu")]
 use burn::backend::{wgpu::WgpuDevice, Wgpu};
+use burn::tensor::backend::Backend;
 use image::imageops::{crop, resize, FilterType};
-use log::trace;
+use log::{trace, warn};
 use mockall_double::double;
+use std::fmt::Display;
 
-#[cfg(not(test))]
-pub type BurnBackend = Wgpu<f32, i32>;
-#[cfg(test)]
-pub type BurnBackend = NdArray<f32>;
+#[cfg(feature = "wgpu")]
+pub type WgpuBackend = Wgpu<f32, i32>;
+#[cfg(any(feature = "ndarray", test))]
+pub type NdArrayBackend = NdArray<f32>;
+
+#[derive(Debug)]
+pub enum Error {
+	BackendNotCompiled(config::Backend),
+	Model(models::Error),
+}
+
+impl From<models::Error> for Error {
+	fn from(value: models::Error) -> Self {
+		Self::Model(value)
+	}
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::BackendNotCompiled(b) => {
+				write!(f, "Support for the {b} backend was not compiled in")
+			}
+			Self::Model(e) => write!(f, "Model error: {e}"),
+		}
+	}
+}
 
 /// Checks whether a `Rectangle` is large enough to be passed into the recognizer model. We would
 /// not want to pass an upscaled image to it
@@ -54,27 +84,38 @@ fn get_face_image(frame: &mut Frame, face_rectangle: &Rectangle<u32>) -> Frame {
 	)
 }
 
-#[derive(Debug)]
-pub struct FrameProcessor {
-	detector: FaceDetector<BurnBackend>,
-	recognizer: FaceRecognizer<BurnBackend>,
+/// Allows for working with `FrameProcessor`s regardless of the backend they run on, so that the
+/// backend can be picked at runtime
+pub trait FrameProcessing {
+	/// Process a frame from the `Camera` and return found faces
+	fn process_frame(&self, frame: &Frame) -> Vec<FaceForProcessing>;
 }
 
-impl FrameProcessor {
-	pub fn new() -> Self {
-		#[cfg(not(test))]
-		let device = WgpuDevice::default();
-		#[cfg(test)]
-		let device = NdArrayDevice::default();
+#[derive(Debug)]
+pub struct FrameProcessor<B: Backend> {
+	detector: FaceDetector<B>,
+	recognizer: FaceRecognizer<B>,
+}
 
-		Self {
-			detector: FaceDetector::new(&device),
-			recognizer: FaceRecognizer::new(&device),
-		}
+impl<B: Backend> FrameProcessor<B> {
+	pub fn new(
+		registry: &ModelRegistry,
+		device: &B::Device,
+		detector_config: DetectorConfig,
+	) -> Result<Self, models::Error> {
+		Ok(Self {
+			detector: FaceDetector::new(
+				registry.detector(),
+				device,
+				detector_config.confidence_threshold,
+			)?,
+			recognizer: FaceRecognizer::new(registry.recognizer(), device)?,
+		})
 	}
+}
 
-	/// Process a frame from the `Camera` and return found faces
-	pub fn process_frame(&self, frame: &Frame) -> Vec<FaceForProcessing> {
+impl<B: Backend> FrameProcessing for FrameProcessor<B> {
+	fn process_frame(&self, frame: &Frame) -> Vec<FaceForProcessing> {
 		let face_rectangles = self.detector.forward(frame);
 
 		let mut detected_faces = Vec::new();
@@ -97,6 +138,73 @@ impl FrameProcessor {
 	}
 }
 
+/// Checks whether WGPU can actually be used on this machine. Burn panics when it fails to find an
+/// adapter, so we run a tiny computation and catch the panic. Note that the panic is still going
+/// to be logged by the panic hook
+#[cfg(feature = "wgpu")]
+fn is_wgpu_available() -> bool {
+	use burn::tensor::Tensor;
+
+	trace!("Checking whether WGPU is available");
+	std::panic::catch_unwind(|| {
+		let device = WgpuDevice::default();
+		Tensor::<WgpuBackend, 1>::zeros([1], &device).to_data();
+	})
+	.is_ok()
+}
+
+#[cfg(feature = "wgpu")]
+fn create_wgpu_frame_processor(
+	registry: &ModelRegistry,
+	detector_config: DetectorConfig,
+) -> Result<Box<dyn FrameProcessing>, Error> {
+	trace!("Creating FrameProcessor with the WGPU backend");
+	Ok(Box::new(FrameProcessor::<WgpuBackend>::new(
+		registry,
+		&WgpuDevice::default(),
+		detector_config,
+	)?))
+}
+
+#[cfg(feature = "ndarray")]
+fn create_ndarray_frame_processor(
+	registry: &ModelRegistry,
+	detector_config: DetectorConfig,
+) -> Result<Box<dyn FrameProcessing>, Error> {
+	trace!("Creating FrameProcessor with the ndarray backend");
+	Ok(Box::new(FrameProcessor::<NdArrayBackend>::new(
+		registry,
+		&NdArrayDevice::default(),
+		detector_config,
+	)?))
+}
+
+/// Creates a `FrameProcessor` running on the given backend. With `Backend::Auto`, WGPU is
+/// preferred and the CPU is used as a fallback
+pub fn create_frame_processor(
+	backend: config::Backend,
+	registry: &ModelRegistry,
+	detector_config: DetectorConfig,
+) -> Result<Box<dyn FrameProcessing>, Error> {
+	match backend {
+		#[cfg(feature = "wgpu")]
+		config::Backend::Wgpu => create_wgpu_frame_processor(registry, detector_config),
+		#[cfg(feature = "ndarray")]
+		config::Backend::Ndarray => create_ndarray_frame_processor(registry, detector_config),
+		config::Backend::Auto => {
+			#[cfg(feature = "wgpu")]
+			if is_wgpu_available() {
+				return create_wgpu_frame_processor(registry, detector_config);
+			}
+
+			warn!("WGPU is not available - falling back to the CPU backend");
+			create_frame_processor(config::Backend::Ndarray, registry, detector_config)
+		}
+		#[allow(unreachable_patterns)]
+		b => Err(Error::BackendNotCompiled(b)),
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use image::ImageBuffer;
@@ -111,7 +219,9 @@ mod tests {
 		processors::face::{FaceEmbedding, FaceRecognitionData, FaceRecognitionError},
 	};
 
-	use super::{rectangle_large_enough_for_recognition, FrameProcessor};
+	use super::{
+		rectangle_large_enough_for_recognition, FrameProcessing, FrameProcessor, NdArrayBackend,
+	};
 
 	#[test]
 	fn determines_if_rectangle_is_large_enough_for_recognition() {
@@ -150,8 +260,8 @@ mod tests {
 	#[test]
 	fn processes_frame() {
 		let frame = ImageBuffer::from_vec(0, 0, vec![]).expect("Failed to create frame");
-		let mut detector = MockFaceDetector::default();
-		let mut recognizer = MockFaceRecognizer::default();
+		let mut detector = MockFaceDetector::<NdArrayBackend>::default();
+		let mut recognizer = MockFaceRecognizer::<NdArrayBackend>::default();
 
 		detector
 			.expect_forward()
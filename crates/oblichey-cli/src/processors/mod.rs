@@ -1,26 +1,67 @@
 pub mod auth_processor;
+pub mod calibration_processor;
+pub mod consensus;
 pub mod face;
 pub mod face_processor;
 pub mod frame_processor;
+pub mod projection;
 pub mod scan_processor;
 
 use log::warn;
 
-use self::{face::FaceForGUI, face_processor::FaceProcessor, frame_processor::FrameProcessor};
-use crate::camera::Frame;
+use self::{face::FaceForGUI, face_processor::FaceProcessor};
+use crate::{
+	camera::Frame,
+	config::{Backend, DetectorConfig},
+	models::registry::ModelRegistry,
+};
 use std::sync::{
 	atomic::{AtomicBool, Ordering},
 	Arc, Mutex,
 };
 
+/// Runs the processing on the given frames instead of frames from the camera. This returns once
+/// the `FaceProcessor` is finished or there are no frames left
+pub fn process_frames(
+	frames: impl Iterator<Item = Frame>,
+	face_processor: &mut dyn FaceProcessor,
+	backend: Backend,
+	model_registry: &ModelRegistry,
+	detector_config: DetectorConfig,
+) -> Result<(), String> {
+	let frame_processor =
+		match frame_processor::create_frame_processor(backend, model_registry, detector_config) {
+			Ok(p) => p,
+			Err(e) => return Err(format!("Failed to create frame processor: {e}")),
+		};
+
+	for frame in frames {
+		let faces_for_processing = frame_processor.process_frame(&frame);
+		face_processor.process_faces(faces_for_processing);
+		face_processor.keep_frame(&frame);
+		if face_processor.is_finished() {
+			return Ok(());
+		}
+	}
+
+	Ok(())
+}
+
 /// Starts the processing loop
 pub fn start(
 	frame: &Arc<Mutex<Option<Frame>>>,
 	faces_for_gui: &Arc<Mutex<Vec<FaceForGUI>>>,
 	finished: &Arc<AtomicBool>,
 	face_processor: &Arc<Mutex<dyn FaceProcessor + Send + Sync>>,
+	backend: Backend,
+	model_registry: &ModelRegistry,
+	detector_config: DetectorConfig,
 ) -> Result<(), String> {
-	let frame_processor = FrameProcessor::new();
+	let frame_processor =
+		match frame_processor::create_frame_processor(backend, model_registry, detector_config) {
+			Ok(p) => p,
+			Err(e) => return Err(format!("Failed to create frame processor: {e}")),
+		};
 
 	loop {
 		if finished.load(Ordering::SeqCst) {
@@ -43,6 +84,7 @@ pub fn start(
 			Err(e) => panic!("Failed to get lock: {e}"),
 		};
 		let new_faces_for_gui = face_processor_lock.process_faces(faces_for_processing);
+		face_processor_lock.keep_frame(&new_frame);
 		if face_processor_lock.is_finished() {
 			return Ok(());
 		}
@@ -0,0 +1,151 @@
+use super::face::{FaceEmbedding, FaceEmbeddingData, EMBEDDING_LENGTH};
+use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+use rand_distr::{Distribution, StandardNormal};
+
+pub const PROJECTION_SEED_LENGTH: usize = 32;
+
+/// A keyed random orthogonal projection of embeddings. Orthogonal projections preserve the dot
+/// products and magnitudes of vectors, so the cosine similarity of two projected embeddings is the
+/// same as of the original ones. Stored templates can thus be projected, so that a template copied
+/// without the seed cannot be compared with faces. Anyone who has the seed can undo the projection
+#[derive(Debug)]
+pub struct Projection {
+	id: String,
+	/// The rows of the orthogonal matrix
+	rows: Vec<FaceEmbedding>,
+}
+
+impl Projection {
+	/// Generates the projection from the seed. The rows are orthonormalized Gaussian random
+	/// vectors, which makes the matrix a uniformly random orthogonal matrix
+	pub fn new(id: String, seed: [u8; PROJECTION_SEED_LENGTH]) -> Self {
+		let mut rng = ChaCha20Rng::from_seed(seed);
+		let mut rows: Vec<[f64; EMBEDDING_LENGTH]> = Vec::with_capacity(EMBEDDING_LENGTH);
+
+		// Modified Gram-Schmidt process
+		for _ in 0..EMBEDDING_LENGTH {
+			let mut row = [0.0; EMBEDDING_LENGTH];
+			for element in &mut row {
+				*element = StandardNormal.sample(&mut rng);
+			}
+			for previous_row in &rows {
+				let dot_product: f64 = row.iter().zip(previous_row).map(|(a, b)| a * b).sum();
+				for (element, previous_element) in row.iter_mut().zip(previous_row) {
+					*element -= dot_product * previous_element;
+				}
+			}
+			let magnitude = row.iter().map(|e| e * e).sum::<f64>().sqrt();
+			for element in &mut row {
+				*element /= magnitude;
+			}
+			rows.push(row);
+		}
+
+		Self {
+			id,
+			rows: rows
+				.iter()
+				.map(|row| FaceEmbedding::new(&row.map(|e| e as f32)))
+				.collect(),
+		}
+	}
+
+	pub fn id(&self) -> &str {
+		&self.id
+	}
+
+	pub fn project(&self, embedding: &FaceEmbedding) -> FaceEmbedding {
+		let mut projected: FaceEmbeddingData = [0.0; EMBEDDING_LENGTH];
+		for (element, row) in projected.iter_mut().zip(&self.rows) {
+			*element = row.dot_product(embedding);
+		}
+
+		FaceEmbedding::new(&projected)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Projection;
+	use crate::processors::face::{FaceEmbedding, DEFAULT_SIMILARITY_THRESHOLD, EMBEDDING_LENGTH};
+	use rand_chacha::{
+		rand_core::{RngCore, SeedableRng},
+		ChaCha20Rng,
+	};
+
+	const EPSILON: f32 = 1e-4;
+
+	fn get_embedding(rng: &mut ChaCha20Rng) -> FaceEmbedding {
+		let mut data = [0.0; EMBEDDING_LENGTH];
+		for element in &mut data {
+			*element = (rng.next_u32() as f32 / u32::MAX as f32) - 0.5;
+		}
+
+		FaceEmbedding::new(&data)
+	}
+
+	/// Returns an embedding which is a mix of the given ones, so that pairs with similarities on
+	/// both sides of the threshold can be generated
+	fn mix(a: &FaceEmbedding, b: &FaceEmbedding, weight: f32) -> FaceEmbedding {
+		let mut data = [0.0; EMBEDDING_LENGTH];
+		for ((element, a), b) in data.iter_mut().zip(a.data()).zip(b.data()) {
+			*element = a * (1.0 - weight) + b * weight;
+		}
+
+		FaceEmbedding::new(&data)
+	}
+
+	#[test]
+	fn preserves_match_decisions() {
+		let projection = Projection::new(String::from("key"), [1; 32]);
+		let mut rng = ChaCha20Rng::seed_from_u64(0);
+		let test_cases: Vec<(FaceEmbedding, FaceEmbedding)> = [0.0, 0.1, 0.2, 0.3, 0.5, 0.9]
+			.into_iter()
+			.map(|weight| {
+				let a = get_embedding(&mut rng);
+				let b = get_embedding(&mut rng);
+				(a, mix(&a, &b, weight))
+			})
+			.collect();
+
+		let mut decisions = Vec::new();
+		for (a, b) in test_cases {
+			let similarity = a.cosine_similarity(&b).expect("Similarity is None");
+			let projected_similarity = projection
+				.project(&a)
+				.cosine_similarity(&projection.project(&b))
+				.expect("Similarity is None");
+
+			assert!((similarity - projected_similarity).abs() < EPSILON);
+			assert_eq!(
+				similarity >= DEFAULT_SIMILARITY_THRESHOLD,
+				projected_similarity >= DEFAULT_SIMILARITY_THRESHOLD
+			);
+			decisions.push(projected_similarity >= DEFAULT_SIMILARITY_THRESHOLD);
+		}
+		assert!(decisions.contains(&true));
+		assert!(decisions.contains(&false));
+	}
+
+	#[test]
+	fn separates_keys() {
+		let projection = Projection::new(String::from("key"), [1; 32]);
+		let other_projection = Projection::new(String::from("other key"), [2; 32]);
+		let embedding = get_embedding(&mut ChaCha20Rng::seed_from_u64(0));
+
+		let projected = projection.project(&embedding);
+		assert!((projected.magnitude() - embedding.magnitude()).abs() < EPSILON);
+		assert!(
+			projected
+				.cosine_similarity(&embedding)
+				.expect("Similarity is None")
+				< DEFAULT_SIMILARITY_THRESHOLD
+		);
+		assert!(
+			projected
+				.cosine_similarity(&other_projection.project(&embedding))
+				.expect("Similarity is None")
+				< DEFAULT_SIMILARITY_THRESHOLD
+		);
+	}
+}
@@ -1,35 +1,177 @@
 use super::{
-	face::{FaceEmbedding, FaceForGUIAnnotationWarning, FaceForProcessing},
+	face::{
+		FaceEmbedding, FaceForGUIAnnotationWarning, FaceForProcessing, FaceRecognitionData, Pose,
+	},
 	face_processor::FaceProcessor,
 	FaceForGUI,
 };
-use crate::processors::face::{FaceForGUIAnnotation, FaceRecognitionError, SIMILARITY_THRESHOLD};
-use std::fmt::Debug;
+use crate::{
+	geometry::Rectangle,
+	log_and_print_info,
+	processors::face::{FaceForGUIAnnotation, FaceRecognitionError},
+};
+use std::{cmp::Reverse, fmt::Debug};
 
-const SCAN_SAMPLE_COUNT: usize = 16;
+/// The number of samples captured during a scan, unless configured otherwise
+pub const DEFAULT_SCAN_SAMPLE_COUNT: usize = 16;
+/// The number of samples captured for each pose during guided enrollment
+const GUIDED_SCAN_SAMPLE_COUNT: usize = 8;
+/// Samples at least this similar to the average of a cluster are merged into it
+const CLUSTER_SIMILARITY_THRESHOLD: f32 = 0.97;
+/// The maximum number of templates a single scan produces
+const MAX_CLUSTER_COUNT: usize = 3;
+/// Samples of a non-straight pose have to be less similar than this to the straight pose to show
+/// that the head has moved
+const POSE_CHANGE_SIMILARITY_THRESHOLD: f32 = 0.97;
 
 #[derive(Debug, Clone)]
 pub struct ScanProcessorResult {
-	pub face_embedding: FaceEmbedding,
+	/// The captured embeddings along with the pose the user was asked to take during guided
+	/// enrollment.
+	/// Otherwise, these are the averages of clusters of similar samples, so that some of the
+	/// variation between the samples is preserved
+	pub face_embeddings: Vec<(FaceEmbedding, Option<Pose>)>,
 }
 
 #[derive(Debug)]
 pub struct ScanProcessor {
 	result: Option<ScanProcessorResult>,
 	embedding_samples: Vec<FaceEmbedding>,
+	/// The poses the user is guided through. This is empty if the scan is not guided
+	poses: Vec<Pose>,
+	/// The averaged samples of the poses which have already been captured
+	captured_poses: Vec<(FaceEmbedding, Option<Pose>)>,
+	/// The last pose the user was asked to take
+	announced_pose: Option<Pose>,
+	/// The number of samples captured when the scan is not guided
+	sample_count: usize,
+	/// All samples have to be at least this similar to each other
+	similarity_threshold: f32,
 }
 
 impl ScanProcessor {
-	pub const fn new() -> Self {
+	pub const fn new(sample_count: usize, similarity_threshold: f32) -> Self {
 		Self {
 			result: None,
 			embedding_samples: Vec::new(),
+			poses: Vec::new(),
+			captured_poses: Vec::new(),
+			announced_pose: None,
+			sample_count,
+			similarity_threshold,
+		}
+	}
+
+	/// Creates a `ScanProcessor` which guides the user through multiple poses and captures a
+	/// template for each of them
+	pub fn new_guided(similarity_threshold: f32) -> Self {
+		Self {
+			poses: Pose::GUIDED_SEQUENCE.to_vec(),
+			..Self::new(GUIDED_SCAN_SAMPLE_COUNT, similarity_threshold)
 		}
 	}
 
 	pub fn get_result(&self) -> Option<ScanProcessorResult> {
 		self.result.clone()
 	}
+
+	fn get_current_pose(&self) -> Option<Pose> {
+		self.poses.get(self.captured_poses.len()).copied()
+	}
+
+	fn get_scanning_state(&self, required_sample_count: usize) -> FaceForGUIAnnotation {
+		FaceForGUIAnnotation::ScanningState {
+			scanned_sample_count: self.embedding_samples.len(),
+			required_sample_count,
+			pose: self.get_current_pose(),
+			captured_pose_count: self.captured_poses.len(),
+			required_pose_count: self.poses.len(),
+		}
+	}
+
+	/// Adds a sample if it is similar enough to the previous ones. Otherwise, the scan starts over
+	/// and `false` is returned
+	fn add_sample(&mut self, embedding: &FaceEmbedding) -> bool {
+		// We require multiple samples during the scan for safety purposes. All of these samples
+		// have to satisfy the similarity requirement. To avoid having to check each two samples we
+		// take and an average of all of them. We calculate the average in each run of this
+		// function to give all samples the same weight
+		if !self.embedding_samples.is_empty() {
+			let average_embedding = FaceEmbedding::average_embedding(&self.embedding_samples);
+			let similarity = average_embedding
+				.cosine_similarity(embedding)
+				.expect("Similarity should never be None");
+
+			if similarity < self.similarity_threshold {
+				self.embedding_samples.clear();
+				return false;
+			}
+		}
+		self.embedding_samples.push(*embedding);
+
+		true
+	}
+
+	/// Tells the user in the terminal which pose to take, if they have not been told yet. The GUI
+	/// shows the instruction as a part of the scanning state
+	fn announce_pose(&mut self, pose: Pose) {
+		if self.announced_pose == Some(pose) {
+			return;
+		}
+
+		log_and_print_info!("{}", pose.instruction());
+		self.announced_pose = Some(pose);
+	}
+
+	/// Checks whether the face has moved away from the straight pose (which is captured first).
+	/// All poses are compared with it to make sure the face belongs to the same person. There is no
+	/// head pose estimate, so we cannot check in which direction the head has moved
+	fn has_changed_pose(&self, face_data: &FaceRecognitionData) -> bool {
+		let Some((straight_embedding, _)) = self.captured_poses.first() else {
+			return true;
+		};
+
+		let similarity = straight_embedding
+			.cosine_similarity(&face_data.embedding)
+			.expect("Similarity should never be None");
+
+		similarity >= self.similarity_threshold && similarity < POSE_CHANGE_SIMILARITY_THRESHOLD
+	}
+
+	fn process_guided_face(
+		&mut self,
+		rectangle: Rectangle<u32>,
+		face_data: &FaceRecognitionData,
+	) -> Vec<FaceForGUI> {
+		let Some(pose) = self.get_current_pose() else {
+			return vec![];
+		};
+		self.announce_pose(pose);
+
+		if self.has_changed_pose(face_data)
+			&& self.add_sample(&face_data.embedding)
+			&& self.embedding_samples.len() >= GUIDED_SCAN_SAMPLE_COUNT
+		{
+			self.captured_poses.push((
+				FaceEmbedding::average_embedding(&self.embedding_samples),
+				Some(pose),
+			));
+			self.embedding_samples.clear();
+
+			if self.captured_poses.len() == self.poses.len() {
+				self.result = Some(ScanProcessorResult {
+					face_embeddings: self.captured_poses.clone(),
+				});
+			} else if let Some(next_pose) = self.get_current_pose() {
+				self.announce_pose(next_pose);
+			}
+		}
+
+		vec![FaceForGUI {
+			rectangle,
+			annotation: self.get_scanning_state(GUIDED_SCAN_SAMPLE_COUNT),
+		}]
+	}
 }
 
 impl FaceProcessor for ScanProcessor {
@@ -55,8 +197,8 @@ impl FaceProcessor for ScanProcessor {
 			self.embedding_samples.clear();
 			return vec![];
 		};
-		let embedding = match face.face_data {
-			Ok(f) => f.embedding,
+		let face_data = match face.face_data {
+			Ok(f) => f,
 			Err(e) => match e {
 				FaceRecognitionError::TooSmall => {
 					self.embedding_samples.clear();
@@ -70,50 +212,69 @@ impl FaceProcessor for ScanProcessor {
 			},
 		};
 
-		// We require multiple samples during the scan for safety purposes. All of these samples
-		// have to satisfy the similarity requirement. To avoid having to check each two samples we
-		// take and an average of all of them. We calculate the average in each run of this
-		// function to give all samples the same weight
-		if !self.embedding_samples.is_empty() {
-			let average_embedding = FaceEmbedding::average_embedding(&self.embedding_samples);
-			let similarity = average_embedding
-				.cosine_similarity(&embedding)
-				.expect("Similarity should never be None");
+		if !self.poses.is_empty() {
+			return self.process_guided_face(face.rectangle, &face_data);
+		}
 
-			if similarity < SIMILARITY_THRESHOLD {
-				self.embedding_samples.clear();
-				return vec![];
-			}
+		if !self.add_sample(&face_data.embedding) {
+			return vec![];
 		}
-		self.embedding_samples.push(embedding);
 
 		// If we have enough samples, we consider the scan to be successful, so we set the result
-		if self.embedding_samples.len() > SCAN_SAMPLE_COUNT {
+		if self.embedding_samples.len() > self.sample_count {
 			self.result = Some(ScanProcessorResult {
-				face_embedding: FaceEmbedding::average_embedding(&self.embedding_samples),
+				face_embeddings: cluster_samples(&self.embedding_samples)
+					.into_iter()
+					.map(|embedding| (embedding, None))
+					.collect(),
 			});
 		}
 
 		// Return info to be displayed in the GUI
 		vec![FaceForGUI {
 			rectangle: face.rectangle,
-			annotation: FaceForGUIAnnotation::ScanningState {
-				scanned_sample_count: self.embedding_samples.len(),
-				required_sample_count: SCAN_SAMPLE_COUNT,
-			},
+			annotation: self.get_scanning_state(self.sample_count),
 		}]
 	}
 }
 
+/// Greedily groups samples into clusters of similar ones and returns the averages of the largest
+/// clusters
+fn cluster_samples(samples: &[FaceEmbedding]) -> Vec<FaceEmbedding> {
+	let mut clusters: Vec<Vec<FaceEmbedding>> = Vec::new();
+	for sample in samples {
+		let cluster = clusters.iter_mut().find(|cluster| {
+			FaceEmbedding::average_embedding(cluster)
+				.cosine_similarity(sample)
+				.is_some_and(|similarity| similarity >= CLUSTER_SIMILARITY_THRESHOLD)
+		});
+		match cluster {
+			Some(c) => c.push(*sample),
+			None => clusters.push(vec![*sample]),
+		}
+	}
+
+	clusters.sort_by_key(|cluster| Reverse(cluster.len()));
+	clusters
+		.iter()
+		.take(MAX_CLUSTER_COUNT)
+		.map(|cluster| FaceEmbedding::average_embedding(cluster))
+		.collect()
+}
+
 #[cfg(test)]
 mod tests {
-	use super::ScanProcessor;
+	use super::{
+		cluster_samples, ScanProcessor, DEFAULT_SCAN_SAMPLE_COUNT, GUIDED_SCAN_SAMPLE_COUNT,
+		MAX_CLUSTER_COUNT,
+	};
 	use crate::{
 		geometry::{Rectangle, Vec2D},
 		processors::{
 			face::{
 				FaceEmbedding, FaceForGUIAnnotation, FaceForGUIAnnotationWarning,
-				FaceForProcessing, FaceRecognitionData, FaceRecognitionError, EMBEDDING_LENGTH,
+				FaceForProcessing, FaceRecognitionData, FaceRecognitionError, Pose,
+				DEFAULT_SIMILARITY_THRESHOLD, EMBEDDING_LENGTH,
 			},
 			face_processor::FaceProcessor,
 		},
@@ -123,7 +284,8 @@ mod tests {
 	#[test]
 	fn handles_too_many_faces() {
 		let rectangle = Rectangle::new(Vec2D::new(0, 0), Vec2D::new(0, 0));
-		let mut processor = ScanProcessor::new();
+		let mut processor =
+			ScanProcessor::new(DEFAULT_SCAN_SAMPLE_COUNT, DEFAULT_SIMILARITY_THRESHOLD);
 
 		let result = processor.process_faces(vec![
 			FaceForProcessing {
@@ -159,7 +321,8 @@ mod tests {
 			rectangle,
 			face_data: Ok(FaceRecognitionData { embedding }),
 		}];
-		let mut processor = ScanProcessor::new();
+		let mut processor =
+			ScanProcessor::new(DEFAULT_SCAN_SAMPLE_COUNT, DEFAULT_SIMILARITY_THRESHOLD);
 
 		let mut i = 1;
 		while !processor.is_finished() {
@@ -169,7 +332,7 @@ mod tests {
 			assert_eq!(result.len(), 1);
 			if let FaceForGUIAnnotation::ScanningState {
 				scanned_sample_count,
-				required_sample_count: _,
+				..
 			} = result[0].annotation
 			{
 				assert_eq!(scanned_sample_count, i);
@@ -180,11 +343,46 @@ mod tests {
 		}
 
 		let result = processor.get_result().expect("Failed to get result");
-		let similarity = result
-			.face_embedding
-			.cosine_similarity(&embedding)
-			.expect("Failed to calculate similarity");
-		assert!((similarity - 1.0).abs() <= f32::EPSILON);
+		assert_eq!(result.face_embeddings.len(), 1);
+		for (face_embedding, pose) in result.face_embeddings {
+			assert_eq!(pose, None);
+			let similarity = face_embedding
+				.cosine_similarity(&embedding)
+				.expect("Failed to calculate similarity");
+			assert!((similarity - 1.0).abs() <= f32::EPSILON);
+		}
+	}
+
+	#[test]
+	fn clusters_samples() {
+		let get_embedding = |index: usize| {
+			FaceEmbedding::new(&{
+				let mut embedding = [0.0; EMBEDDING_LENGTH];
+				embedding[index] = 1.0;
+				embedding
+			})
+		};
+		let test_cases = vec![
+			(vec![get_embedding(0); 4], vec![get_embedding(0)]),
+			(
+				vec![
+					get_embedding(0),
+					get_embedding(1),
+					get_embedding(1),
+					get_embedding(0),
+					get_embedding(1),
+				],
+				vec![get_embedding(1), get_embedding(0)],
+			),
+			(
+				(0..=MAX_CLUSTER_COUNT).map(get_embedding).collect(),
+				(0..MAX_CLUSTER_COUNT).map(get_embedding).collect(),
+			),
+		];
+
+		for (samples, expected_result) in test_cases {
+			assert_eq!(cluster_samples(&samples), expected_result);
+		}
 	}
 
 	#[test]
@@ -196,7 +394,8 @@ mod tests {
 				embedding: FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]),
 			}),
 		};
-		let mut processor = ScanProcessor::new();
+		let mut processor =
+			ScanProcessor::new(DEFAULT_SCAN_SAMPLE_COUNT, DEFAULT_SIMILARITY_THRESHOLD);
 
 		// Different face
 		start_over_scanning(&mut processor);
@@ -240,4 +439,97 @@ mod tests {
 		}]);
 		assert_eq!(processor.embedding_samples.len(), 1);
 	}
+
+	fn get_scanned_sample_count(
+		processor: &mut ScanProcessor,
+		face_data: &FaceRecognitionData,
+	) -> usize {
+		let result = processor.process_faces(vec![FaceForProcessing {
+			rectangle: Rectangle::new(Vec2D::new(0, 0), Vec2D::new(0, 0)),
+			face_data: Ok(*face_data),
+		}]);
+
+		assert_eq!(result.len(), 1);
+		let FaceForGUIAnnotation::ScanningState {
+			scanned_sample_count,
+			..
+		} = result[0].annotation
+		else {
+			panic!();
+		};
+
+		scanned_sample_count
+	}
+
+	#[test]
+	fn guides_through_poses() {
+		let get_embedding = |index: usize| {
+			FaceEmbedding::new(&{
+				let mut embedding = [0.0; EMBEDDING_LENGTH];
+				embedding[0] = 3.0;
+				// Every pose other than the straight one moves the face away from it
+				embedding[index] = 1.0;
+				embedding
+			})
+		};
+		let mut processor = ScanProcessor::new_guided(DEFAULT_SIMILARITY_THRESHOLD);
+
+		for (index, pose) in Pose::GUIDED_SEQUENCE.into_iter().enumerate() {
+			assert_eq!(processor.get_current_pose(), Some(pose));
+			for _ in 0..GUIDED_SCAN_SAMPLE_COUNT {
+				assert!(!processor.is_finished());
+				get_scanned_sample_count(
+					&mut processor,
+					&FaceRecognitionData {
+						embedding: get_embedding(index),
+					},
+				);
+			}
+		}
+
+		let result = processor.get_result().expect("Failed to get result");
+		let poses: Vec<Option<Pose>> = result.face_embeddings.iter().map(|(_, p)| *p).collect();
+		assert_eq!(poses, Pose::GUIDED_SEQUENCE.map(Some).to_vec());
+	}
+
+	#[test]
+	fn requires_head_movement() {
+		let get_embedding = |index: Option<usize>| {
+			FaceEmbedding::new(&{
+				let mut embedding = [0.0; EMBEDDING_LENGTH];
+				embedding[0] = 3.0;
+				if let Some(index) = index {
+					embedding[index] = 1.0;
+				}
+				embedding
+			})
+		};
+		let straight_embedding = get_embedding(None);
+		let mut processor = ScanProcessor::new_guided(DEFAULT_SIMILARITY_THRESHOLD);
+
+		for _ in 0..GUIDED_SCAN_SAMPLE_COUNT {
+			get_scanned_sample_count(
+				&mut processor,
+				&FaceRecognitionData {
+					embedding: straight_embedding,
+				},
+			);
+		}
+		assert_eq!(processor.get_current_pose(), Some(Pose::Left));
+
+		let test_cases = vec![
+			// The head has not moved
+			(straight_embedding, 0),
+			// A different face
+			(FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]), 0),
+			// The head has moved
+			(get_embedding(Some(1)), 1),
+		];
+
+		for (embedding, expected_result) in test_cases {
+			let scanned_sample_count =
+				get_scanned_sample_count(&mut processor, &FaceRecognitionData { embedding });
+			assert_eq!(scanned_sample_count, expected_result);
+		}
+	}
 }
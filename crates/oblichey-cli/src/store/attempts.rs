@@ -0,0 +1,196 @@
+use super::{get_duration, validate_name, Error};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{self, Read, Seek, SeekFrom, Write},
+	os::unix::fs::OpenOptionsExt,
+	path::PathBuf,
+};
+
+/// The directory with the failed attempts of each user. It is only writable by root, so that users
+/// cannot reset their own attempts
+pub const ATTEMPTS_DIRECTORY: &str = "/var/lib/oblichey/attempts";
+const ATTEMPTS_FILE_MODE: u32 = 0o600;
+
+/// How many failed attempts are allowed before authentication is locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Lockout {
+	pub enabled: bool,
+	/// The number of failed attempts within the window after which authentication is locked
+	pub max_failures: usize,
+	/// How far back failed attempts are counted, in seconds
+	pub window: u64,
+	/// How long authentication stays locked, in seconds
+	pub duration: u64,
+	/// Allow authenticating without the lockout when the attempts cannot be counted because
+	/// Oblichey does not run as root
+	pub allow_unprivileged: bool,
+}
+
+impl Default for Lockout {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			max_failures: 5,
+			window: 600,
+			duration: 900,
+			allow_unprivileged: false,
+		}
+	}
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Attempts {
+	failures: Vec<DateTime<Utc>>,
+	locked_until: Option<DateTime<Utc>>,
+}
+
+/// Keeps track of failed authentication attempts of each user across runs
+pub struct AttemptTracker {
+	directory: PathBuf,
+	lockout: Lockout,
+}
+
+impl AttemptTracker {
+	pub const fn new(directory: PathBuf, lockout: Lockout) -> Self {
+		Self { directory, lockout }
+	}
+
+	/// Opens and locks the file with the attempts of the given user until it is dropped
+	fn open(&self, user: &str) -> Result<(File, Attempts), Error> {
+		validate_name(user)?;
+		fs::create_dir_all(&self.directory)?;
+		let mut file = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.mode(ATTEMPTS_FILE_MODE)
+			.open(self.directory.join(user))?;
+		FileExt::lock_exclusive(&file)?;
+
+		let mut serialized = Vec::new();
+		file.read_to_end(&mut serialized)?;
+		let attempts = if serialized.is_empty() {
+			Attempts::default()
+		} else {
+			rmp_serde::from_slice(&serialized)?
+		};
+
+		Ok((file, attempts))
+	}
+
+	fn save(mut file: File, attempts: &Attempts) -> Result<(), Error> {
+		let serialized = rmp_serde::to_vec_named(attempts)?;
+		file.set_len(0)?;
+		file.seek(SeekFrom::Start(0))?;
+		file.write_all(&serialized)?;
+
+		Ok(())
+	}
+
+	/// Starts an attempt of the given user. The attempt is counted as failed until
+	/// `record_success()` is called, so that interrupting the authentication does not avoid the
+	/// lockout. Returns the time until which the user is locked out, if they are
+	pub fn begin_attempt(
+		&self,
+		user: &str,
+		now: DateTime<Utc>,
+	) -> Result<Option<DateTime<Utc>>, Error> {
+		let (file, mut attempts) = self.open(user)?;
+
+		if let Some(locked_until) = attempts.locked_until.filter(|l| *l > now) {
+			return Ok(Some(locked_until));
+		}
+		let window_start = now
+			.checked_sub_signed(get_duration(self.lockout.window))
+			.unwrap_or(DateTime::<Utc>::MIN_UTC);
+		attempts.failures.retain(|f| *f > window_start);
+
+		let locked_until = if attempts.failures.len() >= self.lockout.max_failures {
+			attempts.failures.clear();
+			let locked_until = now
+				.checked_add_signed(get_duration(self.lockout.duration))
+				.unwrap_or(DateTime::<Utc>::MAX_UTC);
+			attempts.locked_until = Some(locked_until);
+			Some(locked_until)
+		} else {
+			attempts.locked_until = None;
+			attempts.failures.push(now);
+			None
+		};
+		Self::save(file, &attempts)?;
+
+		Ok(locked_until)
+	}
+
+	/// Forgets the failed attempts of the given user, including the current one
+	pub fn record_success(&self, user: &str) -> Result<(), Error> {
+		let (file, _) = self.open(user)?;
+
+		Self::save(file, &Attempts::default())
+	}
+
+	/// Lifts the lockout of the given user and forgets their failed attempts
+	pub fn unlock(&self, user: &str) -> Result<(), Error> {
+		validate_name(user)?;
+		match fs::remove_file(self.directory.join(user)) {
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+			r => Ok(r?),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{AttemptTracker, Lockout};
+	use chrono::{Duration, Utc};
+	use std::{env, fs};
+
+	#[test]
+	fn locks_out_after_failures() {
+		let directory = env::temp_dir().join(format!("oblichey-attempts-{}", std::process::id()));
+		let attempt_tracker = AttemptTracker::new(
+			directory.clone(),
+			Lockout {
+				enabled: true,
+				max_failures: 2,
+				window: 60,
+				duration: 120,
+				allow_unprivileged: false,
+			},
+		);
+		let start = Utc::now();
+		let begin_attempt = |user: &str, seconds: i64| {
+			attempt_tracker
+				.begin_attempt(user, start + Duration::seconds(seconds))
+				.expect("Failed to begin attempt")
+				.is_some()
+		};
+
+		// Failures outside of the window are not counted
+		assert!(!begin_attempt("alice", 0));
+		assert!(!begin_attempt("alice", 61));
+		assert!(!begin_attempt("alice", 62));
+		assert!(begin_attempt("alice", 63));
+		assert!(begin_attempt("alice", 182));
+		assert!(!begin_attempt("bob", 63));
+		// The lockout ends after its duration
+		assert!(!begin_attempt("alice", 184));
+
+		// Successful attempts reset the failures
+		attempt_tracker
+			.record_success("alice")
+			.expect("Failed to record success");
+		assert!(!begin_attempt("alice", 185));
+		assert!(!begin_attempt("alice", 186));
+		assert!(begin_attempt("alice", 187));
+
+		attempt_tracker.unlock("alice").expect("Failed to unlock");
+		assert!(!begin_attempt("alice", 188));
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+	}
+}
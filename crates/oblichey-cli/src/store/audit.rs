@@ -0,0 +1,332 @@
+use super::Error;
+use crate::log_and_print_warn;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::BTreeMap,
+	fmt::Display,
+	fs::{self, OpenOptions},
+	io::{self, Write},
+	os::unix::fs::OpenOptionsExt,
+	path::{Path, PathBuf},
+};
+
+/// The audit log reveals when and how users authenticate, so only root can read it
+const AUDIT_LOG_FILE_MODE: u32 = 0o600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOutcome {
+	Success,
+	Failure,
+	/// The user was not allowed to attempt to authenticate after too many failed attempts
+	LockedOut,
+	/// Authentication could not be attempted, for example because no faces were scanned
+	Error,
+}
+
+impl Display for AuditOutcome {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Success => write!(f, "success"),
+			Self::Failure => write!(f, "failure"),
+			Self::LockedOut => write!(f, "locked-out"),
+			Self::Error => write!(f, "error"),
+		}
+	}
+}
+
+/// A single authentication attempt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+	pub timestamp: DateTime<Utc>,
+	/// The PAM service which requested the authentication, if known
+	pub service: Option<String>,
+	pub user: Option<String>,
+	pub outcome: AuditOutcome,
+	/// Why authentication failed or could not be attempted
+	pub reason: Option<String>,
+	/// The name of the scanned face the user was authenticated as
+	pub matched_face: Option<String>,
+	/// The highest similarity of any face to any scanned face, even if it was not recognized
+	pub best_similarity: Option<f32>,
+	pub frame_count: usize,
+	pub duration_ms: u64,
+	/// The verdict of liveness detection. Oblichey cannot detect spoofing yet, so this is always
+	/// `None` for now
+	pub liveness: Option<String>,
+	/// The image of whoever attempted to authenticate, if it was kept
+	pub snapshot: Option<PathBuf>,
+}
+
+impl AuditEntry {
+	/// Creates an entry of an attempt which has not gotten to processing any frames
+	pub const fn new(
+		timestamp: DateTime<Utc>,
+		service: Option<String>,
+		user: Option<String>,
+		outcome: AuditOutcome,
+	) -> Self {
+		Self {
+			timestamp,
+			service,
+			user,
+			outcome,
+			reason: None,
+			matched_face: None,
+			best_similarity: None,
+			frame_count: 0,
+			duration_ms: 0,
+			liveness: None,
+			snapshot: None,
+		}
+	}
+}
+
+impl Display for AuditEntry {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{} {} {} {}",
+			self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+			self.service.as_deref().unwrap_or("-"),
+			self.user.as_deref().unwrap_or("-"),
+			self.outcome,
+		)?;
+		if let Some(matched_face) = &self.matched_face {
+			write!(f, " face={matched_face}")?;
+		}
+		if let Some(best_similarity) = self.best_similarity {
+			write!(f, " similarity={best_similarity:.3}")?;
+		}
+		write!(
+			f,
+			" frames={} duration={}ms",
+			self.frame_count, self.duration_ms
+		)?;
+		if let Some(reason) = &self.reason {
+			write!(f, " reason=\"{reason}\"")?;
+		}
+		if let Some(snapshot) = self.snapshot.as_deref().map(Path::display) {
+			write!(f, " snapshot={snapshot}")?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Where authentication attempts are recorded
+pub trait AuditLog {
+	fn append(&self, entry: &AuditEntry) -> Result<(), Error>;
+
+	/// Reads all entries, oldest first. Entries which cannot be parsed are reported and skipped
+	fn read(&self) -> Result<Vec<AuditEntry>, Error>;
+}
+
+/// An append-only log of authentication attempts with one JSON object per line
+pub struct FileAuditLog {
+	path: PathBuf,
+}
+
+impl FileAuditLog {
+	pub const fn new(path: PathBuf) -> Self {
+		Self { path }
+	}
+}
+
+impl AuditLog for FileAuditLog {
+	fn append(&self, entry: &AuditEntry) -> Result<(), Error> {
+		if let Some(directory) = self.path.parent() {
+			fs::create_dir_all(directory)?;
+		}
+		let mut line = serde_json::to_string(entry)?;
+		line.push('\n');
+
+		// The line is written using a single call, so that concurrent attempts do not interleave
+		OpenOptions::new()
+			.append(true)
+			.create(true)
+			.mode(AUDIT_LOG_FILE_MODE)
+			.open(&self.path)?
+			.write_all(line.as_bytes())?;
+
+		Ok(())
+	}
+
+	fn read(&self) -> Result<Vec<AuditEntry>, Error> {
+		let serialized = match fs::read_to_string(&self.path) {
+			Ok(s) => s,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e) => return Err(Error::from(e)),
+		};
+
+		let mut entries = Vec::new();
+		for (index, line) in serialized.lines().enumerate() {
+			if line.trim().is_empty() {
+				continue;
+			}
+			match serde_json::from_str(line) {
+				Ok(e) => entries.push(e),
+				Err(e) => {
+					log_and_print_warn!(
+						"Failed to parse line {} of the audit log - skipping: {e}",
+						index + 1
+					);
+				}
+			}
+		}
+
+		Ok(entries)
+	}
+}
+
+/// The number of attempts with each outcome
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OutcomeCounts {
+	pub success: usize,
+	pub failure: usize,
+	pub locked_out: usize,
+	pub error: usize,
+}
+
+impl OutcomeCounts {
+	const fn add(&mut self, outcome: AuditOutcome) {
+		match outcome {
+			AuditOutcome::Success => self.success += 1,
+			AuditOutcome::Failure => self.failure += 1,
+			AuditOutcome::LockedOut => self.locked_out += 1,
+			AuditOutcome::Error => self.error += 1,
+		}
+	}
+}
+
+impl Display for OutcomeCounts {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{} succeeded, {} failed, {} locked out, {} errors",
+			self.success, self.failure, self.locked_out, self.error
+		)
+	}
+}
+
+/// Statistics of a set of audit entries
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AuditSummary {
+	pub total: OutcomeCounts,
+	pub users: BTreeMap<String, OutcomeCounts>,
+	/// How often each reason of failed attempts occurred
+	pub failure_reasons: BTreeMap<String, usize>,
+}
+
+impl AuditSummary {
+	pub fn new(entries: &[AuditEntry]) -> Self {
+		let mut summary = Self::default();
+		for entry in entries {
+			summary.total.add(entry.outcome);
+			summary
+				.users
+				.entry(entry.user.clone().unwrap_or_else(|| String::from("-")))
+				.or_default()
+				.add(entry.outcome);
+			if let Some(reason) = &entry.reason {
+				*summary.failure_reasons.entry(reason.clone()).or_default() += 1;
+			}
+		}
+
+		summary
+	}
+}
+
+impl Display for AuditSummary {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		writeln!(f, "Total: {}", self.total)?;
+		for (user, counts) in &self.users {
+			writeln!(f, "{user}: {counts}")?;
+		}
+		if !self.failure_reasons.is_empty() {
+			writeln!(f, "Failure reasons:")?;
+		}
+		for (reason, count) in &self.failure_reasons {
+			writeln!(f, "{count}x {reason}")?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{AuditEntry, AuditLog, AuditOutcome, AuditSummary, FileAuditLog, OutcomeCounts};
+	use chrono::Utc;
+	use std::{env, fs, path::PathBuf};
+
+	fn get_entry(user: &str, outcome: AuditOutcome, reason: Option<&str>) -> AuditEntry {
+		AuditEntry {
+			reason: reason.map(ToOwned::to_owned),
+			..AuditEntry::new(
+				Utc::now(),
+				Some(String::from("sudo")),
+				Some(user.to_owned()),
+				outcome,
+			)
+		}
+	}
+
+	#[test]
+	fn appends_entries() {
+		let directory = env::temp_dir().join(format!("oblichey-audit-{}", std::process::id()));
+		let path = directory.join("audit.jsonl");
+		let audit_log = FileAuditLog::new(path.clone());
+		let entries = vec![
+			AuditEntry {
+				matched_face: Some(String::from("alice")),
+				best_similarity: Some(0.95),
+				frame_count: 12,
+				duration_ms: 1500,
+				..get_entry("alice", AuditOutcome::Success, None)
+			},
+			AuditEntry {
+				snapshot: Some(PathBuf::from("/var/lib/oblichey/snapshots/bob.png")),
+				..get_entry("bob", AuditOutcome::Failure, Some("Timed out"))
+			},
+		];
+
+		for entry in &entries {
+			audit_log.append(entry).expect("Failed to append");
+		}
+		fs::write(
+			&path,
+			fs::read_to_string(&path).expect("Failed to read") + "corrupted\n",
+		)
+		.expect("Failed to write");
+		let read_entries = audit_log.read().expect("Failed to read");
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_eq!(read_entries, entries);
+	}
+
+	#[test]
+	fn summarizes_entries() {
+		let entries = vec![
+			get_entry("alice", AuditOutcome::Success, None),
+			get_entry("alice", AuditOutcome::Failure, Some("Timed out")),
+			get_entry("bob", AuditOutcome::Failure, Some("Timed out")),
+			get_entry("bob", AuditOutcome::LockedOut, None),
+		];
+
+		let summary = AuditSummary::new(&entries);
+
+		assert_eq!(
+			summary.total,
+			OutcomeCounts {
+				success: 1,
+				failure: 2,
+				locked_out: 1,
+				error: 0,
+			}
+		);
+		assert_eq!(summary.users.len(), 2);
+		assert_eq!(summary.failure_reasons.get("Timed out"), Some(&2));
+	}
+}
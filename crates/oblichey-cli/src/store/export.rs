@@ -0,0 +1,264 @@
+use super::{profile::FaceProfile, Error};
+use crate::processors::face::RecognizerId;
+use argon2::{Argon2, RECOMMENDED_SALT_LEN};
+use chacha20poly1305::{
+	aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+	XChaCha20Poly1305, XNonce,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+	fs::{self, OpenOptions},
+	io::Write,
+	mem::size_of,
+	os::unix::fs::OpenOptionsExt,
+	path::Path,
+};
+
+/// Every export file starts with these bytes, so that it cannot be confused with a profile file
+const MAGIC: &[u8; 8] = b"OBLEXPRT";
+/// The version of the export format written by this build
+pub const FORMAT_VERSION: u16 = 1;
+const KEY_LENGTH: usize = 32;
+/// Exports contain biometric data, so only the owner should be able to read them
+const EXPORT_FILE_MODE: u32 = 0o600;
+
+/// The exported face. The profile is kept in the profile format, so that exports made by older
+/// versions of Oblichey go through the same migrations as profile files do
+#[derive(Serialize, Deserialize)]
+struct ExportedFace {
+	name: String,
+	profile: Vec<u8>,
+}
+
+/// Parameters needed to decrypt an encrypted export. The key is derived from the passphrase using
+/// Argon2id and the payload is encrypted using XChaCha20-Poly1305
+#[derive(Serialize, Deserialize)]
+struct Encryption {
+	salt: Vec<u8>,
+	nonce: Vec<u8>,
+}
+
+/// A face exported to be moved to a different machine or backed up
+#[derive(Serialize, Deserialize)]
+pub struct ExportFile {
+	/// The recognizers which produced the templates. These are not encrypted, so that
+	/// compatibility can be checked before asking for the passphrase
+	recognizer_ids: Vec<RecognizerId>,
+	encryption: Option<Encryption>,
+	/// The `ExportedFace` serialized using `MessagePack` and encrypted if `encryption` is set
+	payload: Vec<u8>,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LENGTH], Error> {
+	let mut key = [0; KEY_LENGTH];
+	Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)?;
+
+	Ok(key)
+}
+
+impl ExportFile {
+	/// Exports a face, encrypting it if a passphrase is given
+	pub fn new(name: &str, profile: &FaceProfile, passphrase: Option<&str>) -> Result<Self, Error> {
+		let mut recognizer_ids: Vec<RecognizerId> = Vec::new();
+		for recognizer_id in profile
+			.templates
+			.iter()
+			.filter_map(|t| t.recognizer_id.as_ref())
+		{
+			if !recognizer_ids.contains(recognizer_id) {
+				recognizer_ids.push(recognizer_id.clone());
+			}
+		}
+
+		let payload = rmp_serde::to_vec_named(&ExportedFace {
+			name: name.to_owned(),
+			profile: profile.serialize()?,
+		})?;
+		let Some(passphrase) = passphrase else {
+			return Ok(Self {
+				recognizer_ids,
+				encryption: None,
+				payload,
+			});
+		};
+
+		let mut salt = vec![0; RECOMMENDED_SALT_LEN];
+		OsRng.fill_bytes(&mut salt);
+		let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let key = derive_key(passphrase, &salt)?;
+		let payload = XChaCha20Poly1305::new(&key.into())
+			.encrypt(&nonce, payload.as_slice())
+			.map_err(|_| Error::Encryption)?;
+
+		Ok(Self {
+			recognizer_ids,
+			encryption: Some(Encryption {
+				salt,
+				nonce: nonce.to_vec(),
+			}),
+			payload,
+		})
+	}
+
+	/// Returns the recognizers which produced the templates of the exported face. Templates
+	/// migrated from the legacy format do not have a recognizer, so this can be empty
+	pub fn recognizer_ids(&self) -> &[RecognizerId] {
+		&self.recognizer_ids
+	}
+
+	pub const fn is_encrypted(&self) -> bool {
+		self.encryption.is_some()
+	}
+
+	/// Returns the name and the profile of the exported face. A passphrase is required if the
+	/// export is encrypted
+	pub fn open(&self, passphrase: Option<&str>) -> Result<(String, FaceProfile), Error> {
+		let payload = match (&self.encryption, passphrase) {
+			(None, _) => self.payload.clone(),
+			(Some(_), None) => return Err(Error::PassphraseRequired),
+			(Some(encryption), Some(passphrase)) => {
+				if encryption.nonce.len() != size_of::<XNonce>() {
+					return Err(Error::Decryption);
+				}
+				let key = derive_key(passphrase, &encryption.salt)?;
+				XChaCha20Poly1305::new(&key.into())
+					.decrypt(
+						XNonce::from_slice(&encryption.nonce),
+						self.payload.as_slice(),
+					)
+					.map_err(|_| Error::Decryption)?
+			}
+		};
+
+		let exported_face: ExportedFace = rmp_serde::from_slice(&payload)?;
+		let profile =
+			FaceProfile::deserialize(&exported_face.profile, &exported_face.name, Utc::now())?;
+
+		Ok((exported_face.name, profile))
+	}
+
+	/// Writes the export into a new file. Existing files are never overwritten
+	pub fn save(&self, path: &Path) -> Result<(), Error> {
+		let serialized = self.serialize()?;
+		let mut file = OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(EXPORT_FILE_MODE)
+			.open(path)?;
+		file.write_all(&serialized)?;
+
+		Ok(())
+	}
+
+	pub fn load(path: &Path) -> Result<Self, Error> {
+		Self::deserialize(&fs::read(path)?)
+	}
+
+	/// Serializes the export into the current format: the magic bytes, the little-endian format
+	/// version and a `MessagePack` body with named fields
+	pub fn serialize(&self) -> Result<Vec<u8>, Error> {
+		let mut serialized = MAGIC.to_vec();
+		serialized.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+		serialized.extend(rmp_serde::to_vec_named(self)?);
+
+		Ok(serialized)
+	}
+
+	pub fn deserialize(serialized: &[u8]) -> Result<Self, Error> {
+		let Some(rest) = serialized.strip_prefix(MAGIC) else {
+			return Err(Error::NotAnExport);
+		};
+		let Some((version, body)) = rest.split_first_chunk::<{ size_of::<u16>() }>() else {
+			return Err(Error::TruncatedHeader);
+		};
+
+		match u16::from_le_bytes(*version) {
+			1 => Ok(rmp_serde::from_slice(body)?),
+			v => Err(Error::UnsupportedFormatVersion(v)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ExportFile, FORMAT_VERSION, MAGIC};
+	use crate::{
+		processors::face::{FaceEmbedding, FaceTemplate, RecognizerId, EMBEDDING_LENGTH},
+		store::{profile::FaceProfile, Error},
+	};
+
+	fn get_face_profile() -> FaceProfile {
+		let template = FaceTemplate {
+			recognizer_id: Some(RecognizerId::new(String::from("recognizer"))),
+			embedding: FaceEmbedding::new(&[0.5; EMBEDDING_LENGTH]),
+			pose: None,
+			adapted_at: None,
+		};
+
+		FaceProfile::new(
+			Some(String::from("alice")),
+			String::from("Alice"),
+			Some(String::from("/dev/video2")),
+			vec![template.clone(), template],
+		)
+	}
+
+	#[test]
+	fn exports_and_imports_faces() {
+		let profile = get_face_profile();
+		let test_cases = vec![None, Some("correct horse battery staple")];
+
+		for passphrase in test_cases {
+			let serialized = ExportFile::new("alice", &profile, passphrase)
+				.expect("Failed to export")
+				.serialize()
+				.expect("Failed to serialize");
+			let export_file = ExportFile::deserialize(&serialized).expect("Failed to deserialize");
+
+			assert_eq!(export_file.is_encrypted(), passphrase.is_some());
+			assert_eq!(
+				export_file.recognizer_ids(),
+				[RecognizerId::new(String::from("recognizer"))]
+			);
+			let (name, imported_profile) = export_file.open(passphrase).expect("Failed to open");
+			assert_eq!(name, "alice");
+			assert_eq!(imported_profile, profile);
+		}
+	}
+
+	#[test]
+	fn rejects_wrong_passphrase() {
+		let export_file = ExportFile::new("alice", &get_face_profile(), Some("passphrase"))
+			.expect("Failed to export");
+
+		assert!(matches!(
+			export_file.open(None),
+			Err(Error::PassphraseRequired)
+		));
+		assert!(matches!(
+			export_file.open(Some("wrong passphrase")),
+			Err(Error::Decryption)
+		));
+	}
+
+	#[test]
+	fn rejects_invalid_files() {
+		let profile = get_face_profile();
+		let mut unsupported_version = MAGIC.to_vec();
+		unsupported_version.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+		assert!(matches!(
+			ExportFile::deserialize(&profile.serialize().expect("Failed to serialize")),
+			Err(Error::NotAnExport)
+		));
+		assert!(matches!(
+			ExportFile::deserialize(MAGIC),
+			Err(Error::TruncatedHeader)
+		));
+		assert!(matches!(
+			ExportFile::deserialize(&unsupported_version),
+			Err(Error::UnsupportedFormatVersion(_))
+		));
+	}
+}
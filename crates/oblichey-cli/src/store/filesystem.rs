@@ -0,0 +1,271 @@
+use super::{profile::FaceProfile, validate_name, Error, FaceStore};
+use crate::log_and_print_warn;
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{self, Write},
+	os::unix::fs::{chown, fchown, MetadataExt, OpenOptionsExt},
+	path::PathBuf,
+};
+
+/// Other processes (such as a scan running while authenticating) are kept from seeing partially
+/// written faces by locking this file
+const LOCK_FILE_NAME: &str = ".lock";
+/// The lock file is empty and only ever opened for reading, so anyone can see it
+const LOCK_FILE_MODE: u32 = 0o644;
+const TEMPORARY_FILE_EXTENSION: &str = "tmp";
+/// Faces contain biometric data, so only the owner should be able to read them
+const FACE_FILE_MODE: u32 = 0o600;
+
+/// A face file as it was read, before being deserialized
+struct FaceFile {
+	serialized: Vec<u8>,
+	modified_at: DateTime<Utc>,
+}
+
+/// Stores each face in a separate file named after the face. Names are validated, so that they
+/// cannot escape the directory, and files whose names start with a dot are reserved for the store
+/// itself. Everything the store creates is owned by the owner of the directory, so that the user
+/// keeps access to their faces when root changes them, such as when authenticating
+pub struct FilesystemFaceStore {
+	directory: PathBuf,
+}
+
+impl FilesystemFaceStore {
+	/// Creates the directory if it does not exist yet. The created directories are owned by the
+	/// owner of the closest existing one, so that a store created by root on behalf of a user, such
+	/// as when authenticating, can still be used by the user
+	pub fn new(directory: PathBuf) -> Result<Self, Error> {
+		let mut created_directories = Vec::new();
+		let mut existing_directory = directory.as_path();
+		while !existing_directory.try_exists()? {
+			created_directories.push(existing_directory.to_owned());
+			let Some(parent) = existing_directory.parent() else {
+				break;
+			};
+			existing_directory = parent;
+		}
+		fs::create_dir_all(&directory)?;
+
+		let owner = fs::metadata(existing_directory)?;
+		for created_directory in created_directories {
+			let metadata = fs::metadata(&created_directory)?;
+			if (metadata.uid(), metadata.gid()) != (owner.uid(), owner.gid()) {
+				chown(&created_directory, Some(owner.uid()), Some(owner.gid()))?;
+			}
+		}
+
+		Ok(Self { directory })
+	}
+
+	/// Makes the file owned by the owner of the directory
+	fn give_to_owner(&self, file: &File) -> Result<(), Error> {
+		let owner = fs::metadata(&self.directory)?;
+		let metadata = file.metadata()?;
+		if (metadata.uid(), metadata.gid()) != (owner.uid(), owner.gid()) {
+			fchown(file, Some(owner.uid()), Some(owner.gid()))?;
+		}
+
+		Ok(())
+	}
+
+	fn get_path(&self, name: &str) -> Result<PathBuf, Error> {
+		validate_name(name)?;
+
+		Ok(self.directory.join(name))
+	}
+
+	/// Locks the store until the returned file is dropped. Reading only requires a shared lock.
+	/// Locking does not require writing into the file, so it is opened for reading unless it has to
+	/// be created
+	fn lock(&self, exclusive: bool) -> Result<File, Error> {
+		let path = self.directory.join(LOCK_FILE_NAME);
+		let file = match File::open(&path) {
+			Ok(f) => f,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				let file = OpenOptions::new()
+					.write(true)
+					.create(true)
+					.truncate(false)
+					.mode(LOCK_FILE_MODE)
+					.open(&path)?;
+				self.give_to_owner(&file)?;
+				file
+			}
+			Err(e) => return Err(Error::from(e)),
+		};
+		if exclusive {
+			FileExt::lock_exclusive(&file)?;
+		} else {
+			FileExt::lock_shared(&file)?;
+		}
+
+		Ok(file)
+	}
+
+	/// Writes the face into a temporary file first and then renames it, so that the face is never
+	/// partially written. The store has to be locked exclusively
+	fn write(&self, name: &str, face_profile: &FaceProfile) -> Result<(), Error> {
+		let path = self.get_path(name)?;
+		let temporary_path = self
+			.directory
+			.join(format!(".{name}.{TEMPORARY_FILE_EXTENSION}"));
+		let serialized = face_profile.serialize()?;
+
+		let mut file = OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.mode(FACE_FILE_MODE)
+			.open(&temporary_path)?;
+		self.give_to_owner(&file)?;
+		file.write_all(&serialized)?;
+		file.sync_all()?;
+		fs::rename(temporary_path, path)?;
+
+		Ok(())
+	}
+
+	/// Reads the serialized face, returning `None` if there is no face of the given name. The store
+	/// has to be locked
+	fn read(&self, name: &str) -> Result<Option<FaceFile>, Error> {
+		let path = self.get_path(name)?;
+		if !path.try_exists()? {
+			return Ok(None);
+		}
+		let serialized = fs::read(&path)?;
+		let modified_at = fs::metadata(&path)?
+			.modified()
+			.map_or_else(|_| Utc::now(), DateTime::<Utc>::from);
+
+		Ok(Some(FaceFile {
+			serialized,
+			modified_at,
+		}))
+	}
+
+	/// Rewrites a face stored in the legacy format in the current one, so that it does not have to
+	/// be migrated every time it is loaded. The face is only rewritten if no other process has
+	/// replaced it since it was read
+	fn migrate_legacy_file(
+		&self,
+		name: &str,
+		serialized: &[u8],
+		face_profile: &FaceProfile,
+	) -> Result<(), Error> {
+		let path = self.get_path(name)?;
+		let _lock = self.lock(true)?;
+		if fs::read(&path)? != serialized {
+			return Ok(());
+		}
+
+		self.write(name, face_profile)
+	}
+}
+
+impl FaceStore for FilesystemFaceStore {
+	fn list(&self) -> Result<Vec<String>, Error> {
+		let _lock = self.lock(false)?;
+
+		let mut names = Vec::new();
+		for file in fs::read_dir(&self.directory)? {
+			let file = match file {
+				Ok(f) => f,
+				Err(e) => {
+					log_and_print_warn!("Failed to get file - skipping: {e}");
+					continue;
+				}
+			};
+			let Ok(name) = file.file_name().into_string() else {
+				log_and_print_warn!("File name contains invalid unicode - skipping");
+				continue;
+			};
+			if name.starts_with('.') {
+				continue;
+			}
+			names.push(name);
+		}
+		names.sort();
+
+		Ok(names)
+	}
+
+	/// Faces in the legacy format are rewritten in the current one the first time they are loaded
+	fn get(&self, name: &str) -> Result<Option<FaceProfile>, Error> {
+		let lock = self.lock(false)?;
+		let Some(FaceFile {
+			serialized,
+			modified_at,
+		}) = self.read(name)?
+		else {
+			return Ok(None);
+		};
+		drop(lock);
+
+		let face_profile = FaceProfile::deserialize(&serialized, name, modified_at)?;
+		if FaceProfile::is_legacy(&serialized) {
+			if let Err(e) = self.migrate_legacy_file(name, &serialized, &face_profile) {
+				log_and_print_warn!(
+					"Failed to rewrite face scan {name} in the current format: {e}"
+				);
+			}
+		}
+
+		Ok(Some(face_profile))
+	}
+
+	fn put(&self, name: &str, face_profile: &FaceProfile) -> Result<(), Error> {
+		let _lock = self.lock(true)?;
+
+		self.write(name, face_profile)
+	}
+
+	fn delete(&self, name: &str) -> Result<(), Error> {
+		let path = self.get_path(name)?;
+		let _lock = self.lock(true)?;
+
+		match fs::remove_file(path) {
+			Ok(()) => Ok(()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Error::NotFound(name.to_owned())),
+			Err(e) => Err(Error::from(e)),
+		}
+	}
+
+	fn rename(&self, old_name: &str, new_name: &str) -> Result<(), Error> {
+		let old_path = self.get_path(old_name)?;
+		let new_path = self.get_path(new_name)?;
+		let _lock = self.lock(true)?;
+
+		if !old_path.try_exists()? {
+			return Err(Error::NotFound(old_name.to_owned()));
+		}
+		if new_path.try_exists()? {
+			return Err(Error::AlreadyExists(new_name.to_owned()));
+		}
+		fs::rename(old_path, new_path)?;
+
+		Ok(())
+	}
+
+	fn update(
+		&self,
+		name: &str,
+		update: &dyn Fn(Option<FaceProfile>) -> Result<FaceProfile, Error>,
+	) -> Result<(), Error> {
+		let _lock = self.lock(true)?;
+		let face_profile = self
+			.read(name)?
+			.map(|f| FaceProfile::deserialize(&f.serialized, name, f.modified_at))
+			.transpose()?;
+
+		self.write(name, &update(face_profile)?)
+	}
+
+	fn contains(&self, name: &str) -> Result<bool, Error> {
+		let path = self.get_path(name)?;
+		let _lock = self.lock(false)?;
+
+		Ok(path.try_exists()?)
+	}
+}
@@ -0,0 +1,285 @@
+use super::{validate_name, Error};
+use crate::processors::projection::{Projection, PROJECTION_SEED_LENGTH};
+use crypto_box::{
+	aead::{rand_core::RngCore, OsRng},
+	PublicKey, SecretKey, KEY_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	fs::{self, OpenOptions},
+	io::{self, Write},
+	os::unix::fs::{self as unix_fs, OpenOptionsExt},
+	path::{Path, PathBuf},
+};
+
+/// The directory with the store keys. It is only writable by root
+pub const KEYS_DIRECTORY: &str = "/etc/oblichey";
+const SECRET_KEYS_FILE_NAME: &str = "store.key";
+const PUBLIC_KEY_FILE_NAME: &str = "store.pub";
+/// The directory inside the keys directory with the projection keys of the users
+const PROJECTION_KEYS_DIRECTORY_NAME: &str = "projection";
+const PROJECTION_KEY_FILE_EXTENSION: &str = "key";
+/// Only the owner can read the secret keys and the projection keys. The secret keys are owned by
+/// root, while each projection key is owned by its user, so that processes running as the user can
+/// project their templates
+const SECRET_KEYS_FILE_MODE: u32 = 0o600;
+/// Anyone can read the public key, so that users can encrypt their own templates
+const PUBLIC_KEY_FILE_MODE: u32 = 0o644;
+/// The number of bytes of the BLAKE3 digest of the public key or the projection seed used as the
+/// ID of the key
+const KEY_ID_LENGTH: usize = 8;
+
+#[derive(Serialize, Deserialize)]
+struct StoredSecretKey {
+	id: String,
+	secret_key: [u8; KEY_SIZE],
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProjectionKey {
+	id: String,
+	seed: [u8; PROJECTION_SEED_LENGTH],
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPublicKey {
+	id: String,
+	public_key: [u8; KEY_SIZE],
+}
+
+fn get_key_id(public_key: &PublicKey) -> String {
+	blake3::hash(public_key.as_bytes()).to_hex()[..KEY_ID_LENGTH * 2].to_owned()
+}
+
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> Result<(), Error> {
+	let mut file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.mode(mode)
+		.open(path)?;
+	file.write_all(contents)?;
+
+	Ok(())
+}
+
+fn get_projection_key_path(directory: &Path, user: &str) -> Result<PathBuf, Error> {
+	validate_name(user)?;
+
+	Ok(directory
+		.join(PROJECTION_KEYS_DIRECTORY_NAME)
+		.join(user)
+		.with_extension(PROJECTION_KEY_FILE_EXTENSION))
+}
+
+/// Reads a key file, returning `None` if it does not exist
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+	match fs::read(path) {
+		Ok(b) => Ok(Some(b)),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Err(Error::KeyAccessDenied),
+		Err(e) => Err(Error::from(e)),
+	}
+}
+
+/// The key which templates are encrypted with
+pub struct StorePublicKey {
+	pub id: String,
+	pub public_key: PublicKey,
+}
+
+impl StorePublicKey {
+	/// Loads the public key, returning `None` if the store is not encrypted
+	pub fn load(directory: &Path) -> Result<Option<Self>, Error> {
+		let Some(serialized) = read_file(&directory.join(PUBLIC_KEY_FILE_NAME))? else {
+			return Ok(None);
+		};
+		let stored: StoredPublicKey = rmp_serde::from_slice(&serialized)?;
+
+		Ok(Some(Self {
+			id: stored.id,
+			public_key: PublicKey::from_bytes(stored.public_key),
+		}))
+	}
+}
+
+/// The keys which templates can be decrypted with. The last key is the current one which new
+/// templates are encrypted with. The previous keys are kept after rotation, so that templates
+/// which have not been re-encrypted yet can still be decrypted
+pub struct StoreKeys {
+	keys: Vec<(String, SecretKey)>,
+}
+
+impl StoreKeys {
+	pub fn generate() -> Self {
+		let mut keys = Self { keys: Vec::new() };
+		keys.rotate();
+
+		keys
+	}
+
+	/// Loads the secret keys, returning `None` if the store is not encrypted
+	pub fn load(directory: &Path) -> Result<Option<Self>, Error> {
+		let Some(serialized) = read_file(&directory.join(SECRET_KEYS_FILE_NAME))? else {
+			return Ok(None);
+		};
+		let stored: Vec<StoredSecretKey> = rmp_serde::from_slice(&serialized)?;
+
+		Ok(Some(Self {
+			keys: stored
+				.into_iter()
+				.map(|k| (k.id, SecretKey::from_bytes(k.secret_key)))
+				.collect(),
+		}))
+	}
+
+	/// Writes the secret keys and the current public key into the given directory
+	pub fn save(&self, directory: &Path) -> Result<(), Error> {
+		let Some(public_key) = self.get_current_public_key() else {
+			return Err(Error::KeyUnavailable);
+		};
+		let stored: Vec<StoredSecretKey> = self
+			.keys
+			.iter()
+			.map(|(id, secret_key)| StoredSecretKey {
+				id: id.clone(),
+				secret_key: secret_key.to_bytes(),
+			})
+			.collect();
+
+		fs::create_dir_all(directory)?;
+		write_file(
+			&directory.join(SECRET_KEYS_FILE_NAME),
+			&rmp_serde::to_vec_named(&stored)?,
+			SECRET_KEYS_FILE_MODE,
+		)?;
+		write_file(
+			&directory.join(PUBLIC_KEY_FILE_NAME),
+			&rmp_serde::to_vec_named(&StoredPublicKey {
+				id: public_key.id,
+				public_key: public_key.public_key.to_bytes(),
+			})?,
+			PUBLIC_KEY_FILE_MODE,
+		)?;
+
+		Ok(())
+	}
+
+	/// Generates a new key which becomes the current one
+	pub fn rotate(&mut self) {
+		let secret_key = SecretKey::generate(&mut OsRng);
+		self.keys
+			.push((get_key_id(&secret_key.public_key()), secret_key));
+	}
+
+	pub fn get_current_public_key(&self) -> Option<StorePublicKey> {
+		self.keys.last().map(|(id, secret_key)| StorePublicKey {
+			id: id.clone(),
+			public_key: secret_key.public_key(),
+		})
+	}
+
+	pub fn get(&self, id: &str) -> Option<&SecretKey> {
+		self.keys
+			.iter()
+			.find(|(key_id, _)| key_id == id)
+			.map(|(_, secret_key)| secret_key)
+	}
+}
+
+/// The seed of the projection the templates of a user are transformed with, if they enabled it
+pub struct ProjectionKey {
+	id: String,
+	seed: [u8; PROJECTION_SEED_LENGTH],
+}
+
+impl ProjectionKey {
+	pub fn generate() -> Self {
+		let mut seed = [0; PROJECTION_SEED_LENGTH];
+		OsRng.fill_bytes(&mut seed);
+
+		Self {
+			id: blake3::hash(&seed).to_hex()[..KEY_ID_LENGTH * 2].to_owned(),
+			seed,
+		}
+	}
+
+	/// Loads the projection key of the user, returning `None` if they have not enabled template
+	/// projection
+	pub fn load(directory: &Path, user: &str) -> Result<Option<Self>, Error> {
+		let Some(serialized) = read_file(&get_projection_key_path(directory, user)?)? else {
+			return Ok(None);
+		};
+		let stored: StoredProjectionKey = rmp_serde::from_slice(&serialized)?;
+
+		Ok(Some(Self {
+			id: stored.id,
+			seed: stored.seed,
+		}))
+	}
+
+	/// Writes the projection key of the user and makes the user its owner, so that only they and
+	/// root can read it
+	pub fn save(&self, directory: &Path, user: &str) -> Result<(), Error> {
+		let Some(owner) = nix::unistd::User::from_name(user).map_err(io::Error::from)? else {
+			return Err(Error::UnknownUser(user.to_owned()));
+		};
+		let path = get_projection_key_path(directory, user)?;
+
+		fs::create_dir_all(directory.join(PROJECTION_KEYS_DIRECTORY_NAME))?;
+		write_file(
+			&path,
+			&rmp_serde::to_vec_named(&StoredProjectionKey {
+				id: self.id.clone(),
+				seed: self.seed,
+			})?,
+			SECRET_KEYS_FILE_MODE,
+		)?;
+		unix_fs::chown(&path, Some(owner.uid.as_raw()), Some(owner.gid.as_raw()))?;
+
+		Ok(())
+	}
+
+	pub fn get_projection(&self) -> Projection {
+		Projection::new(self.id.clone(), self.seed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{StoreKeys, StorePublicKey};
+	use std::{env, fs};
+
+	#[test]
+	fn saves_and_rotates_keys() {
+		let directory = env::temp_dir().join(format!("oblichey-keys-{}", std::process::id()));
+		assert!(StoreKeys::load(&directory)
+			.expect("Failed to load keys")
+			.is_none());
+
+		let mut keys = StoreKeys::generate();
+		let first_public_key = keys
+			.get_current_public_key()
+			.expect("Failed to get public key");
+		keys.rotate();
+		keys.save(&directory).expect("Failed to save keys");
+		let loaded_keys = StoreKeys::load(&directory)
+			.expect("Failed to load keys")
+			.expect("Keys do not exist");
+		let public_key = StorePublicKey::load(&directory)
+			.expect("Failed to load public key")
+			.expect("Public key does not exist");
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_ne!(public_key.id, first_public_key.id);
+		assert_eq!(
+			loaded_keys
+				.get_current_public_key()
+				.map(|k| (k.id, k.public_key)),
+			Some((public_key.id.clone(), public_key.public_key))
+		);
+		assert!(loaded_keys.get(&first_public_key.id).is_some());
+		assert!(loaded_keys.get(&public_key.id).is_some());
+		assert!(loaded_keys.get("unknown").is_none());
+	}
+}
@@ -0,0 +1,65 @@
+use super::{profile::FaceProfile, validate_name, Error, FaceStore};
+use std::{cell::RefCell, collections::BTreeMap};
+
+/// Keeps faces in memory. This is meant for tests
+#[derive(Default)]
+pub struct MemoryFaceStore {
+	face_profiles: RefCell<BTreeMap<String, FaceProfile>>,
+}
+
+impl FaceStore for MemoryFaceStore {
+	fn list(&self) -> Result<Vec<String>, Error> {
+		Ok(self.face_profiles.borrow().keys().cloned().collect())
+	}
+
+	fn get(&self, name: &str) -> Result<Option<FaceProfile>, Error> {
+		validate_name(name)?;
+
+		Ok(self.face_profiles.borrow().get(name).cloned())
+	}
+
+	fn put(&self, name: &str, face_profile: &FaceProfile) -> Result<(), Error> {
+		validate_name(name)?;
+		self.face_profiles
+			.borrow_mut()
+			.insert(name.to_owned(), face_profile.clone());
+
+		Ok(())
+	}
+
+	fn delete(&self, name: &str) -> Result<(), Error> {
+		validate_name(name)?;
+		match self.face_profiles.borrow_mut().remove(name) {
+			Some(_) => Ok(()),
+			None => Err(Error::NotFound(name.to_owned())),
+		}
+	}
+
+	fn rename(&self, old_name: &str, new_name: &str) -> Result<(), Error> {
+		validate_name(old_name)?;
+		validate_name(new_name)?;
+		let mut face_profiles = self.face_profiles.borrow_mut();
+		if face_profiles.contains_key(new_name) {
+			return Err(Error::AlreadyExists(new_name.to_owned()));
+		}
+		let Some(face_profile) = face_profiles.remove(old_name) else {
+			return Err(Error::NotFound(old_name.to_owned()));
+		};
+		face_profiles.insert(new_name.to_owned(), face_profile);
+
+		Ok(())
+	}
+
+	fn update(
+		&self,
+		name: &str,
+		update: &dyn Fn(Option<FaceProfile>) -> Result<FaceProfile, Error>,
+	) -> Result<(), Error> {
+		validate_name(name)?;
+		let mut face_profiles = self.face_profiles.borrow_mut();
+		let face_profile = update(face_profiles.get(name).cloned())?;
+		face_profiles.insert(name.to_owned(), face_profile);
+
+		Ok(())
+	}
+}
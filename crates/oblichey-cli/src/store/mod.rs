@@ -0,0 +1,660 @@
+pub mod attempts;
+pub mod audit;
+pub mod export;
+pub mod filesystem;
+pub mod keys;
+#[cfg(test)]
+pub mod memory;
+pub mod profile;
+pub mod snapshots;
+pub mod sqlite;
+
+use crate::{
+	config::{AuditConfig, StoreConfig},
+	log_and_print_warn,
+	processors::{face::FaceTemplate, projection::Projection},
+};
+use audit::{AuditLog, FileAuditLog};
+use chrono::Duration;
+use filesystem::FilesystemFaceStore;
+use keys::StorePublicKey;
+use log::info;
+use nix::unistd::{geteuid, getuid, User};
+use profile::FaceProfile;
+use sqlite::{SqliteAuditLog, SqliteFaceStore};
+use std::{
+	collections::HashMap,
+	env,
+	fmt::Display,
+	io,
+	path::{Path, PathBuf},
+};
+
+const OBLICHEY_DIRECTORY_NAME: &str = "oblichey";
+/// The default XDG state directory relative to the home directory
+const STATE_DIRECTORY: &str = ".local/state";
+/// The maximum length of a name in bytes. This keeps file names (including temporary ones) within
+/// the limits of common filesystems
+const MAX_NAME_LENGTH: usize = 128;
+
+#[derive(Debug)]
+pub enum Error {
+	EnvVar(env::VarError),
+	Io(io::Error),
+	Bincode(bincode::Error),
+	MessagePackEncode(rmp_serde::encode::Error),
+	MessagePackDecode(rmp_serde::decode::Error),
+	TruncatedHeader,
+	UnsupportedFormatVersion(u16),
+	NotAnExport,
+	KeyDerivation(argon2::Error),
+	Encryption,
+	Decryption,
+	PassphraseRequired,
+	InvalidName(String),
+	KeyUnavailable,
+	KeyAccessDenied,
+	DatabaseAccessDenied,
+	ProjectionKeyUnavailable,
+	NoScannedTemplates,
+	AdaptationLimitReached,
+	UnknownUser(String),
+	UserNotAllowed(String),
+	NotFound(String),
+	AlreadyExists(String),
+	Sqlite(rusqlite::Error),
+	UnsupportedSchemaVersion(u32),
+	Json(serde_json::Error),
+	Image(image::ImageError),
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::EnvVar(e) => write!(f, "Environment variable error: {e}"),
+			Self::Io(e) => write!(f, "IO error: {e}"),
+			Self::Bincode(e) => write!(f, "Bincode error: {e}"),
+			Self::MessagePackEncode(e) => write!(f, "MessagePack encoding error: {e}"),
+			Self::MessagePackDecode(e) => write!(f, "MessagePack decoding error: {e}"),
+			Self::TruncatedHeader => write!(f, "The header of the face profile is truncated"),
+			Self::UnsupportedFormatVersion(v) => write!(
+				f,
+				"Face profile format version {v} is not supported by this version of Oblichey"
+			),
+			Self::NotAnExport => write!(f, "The file is not an exported face"),
+			Self::KeyDerivation(e) => write!(f, "Failed to derive key from passphrase: {e}"),
+			Self::Encryption => write!(f, "Failed to encrypt the face"),
+			Self::Decryption => write!(
+				f,
+				"Failed to decrypt the face - the passphrase is wrong or the file is corrupted"
+			),
+			Self::PassphraseRequired => {
+				write!(f, "The face is encrypted and requires a passphrase")
+			}
+			Self::KeyUnavailable => write!(
+				f,
+				"The key the face was encrypted with is not available - re-enrollment required"
+			),
+			Self::KeyAccessDenied => write!(
+				f,
+				"The key can only be read by root, so this has to be run as root"
+			),
+			Self::DatabaseAccessDenied => write!(
+				f,
+				"The database of the faces can only be accessed by root, so this has to be run as root"
+			),
+			Self::ProjectionKeyUnavailable => write!(
+				f,
+				"The face was transformed using a projection key which is not available - re-enrollment required"
+			),
+			Self::NoScannedTemplates => write!(f, "The face has no scanned templates to adapt"),
+			Self::AdaptationLimitReached => write!(
+				f,
+				"The face was already adapted the maximum number of times within the last day"
+			),
+			Self::UnknownUser(u) => write!(f, "User {u} does not exist"),
+			Self::UserNotAllowed(u) => {
+				write!(f, "Only root can act on behalf of user {u}")
+			}
+			Self::InvalidName(n) => write!(
+				f,
+				"\"{n}\" cannot be used as the name of a face - names must not be empty, start with a dot or contain slashes or control characters and must be at most {MAX_NAME_LENGTH} bytes long"
+			),
+			Self::NotFound(n) => write!(f, "Face {n} does not exist"),
+			Self::AlreadyExists(n) => write!(f, "Face {n} already exists"),
+			Self::Sqlite(e) => write!(f, "SQLite error: {e}"),
+			Self::UnsupportedSchemaVersion(v) => write!(
+				f,
+				"Database schema version {v} is not supported by this version of Oblichey"
+			),
+			Self::Json(e) => write!(f, "JSON error: {e}"),
+			Self::Image(e) => write!(f, "Image error: {e}"),
+		}
+	}
+}
+
+impl From<env::VarError> for Error {
+	fn from(value: env::VarError) -> Self {
+		Self::EnvVar(value)
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(value: io::Error) -> Self {
+		Self::Io(value)
+	}
+}
+
+impl From<bincode::Error> for Error {
+	fn from(value: bincode::Error) -> Self {
+		Self::Bincode(value)
+	}
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+	fn from(value: rmp_serde::encode::Error) -> Self {
+		Self::MessagePackEncode(value)
+	}
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+	fn from(value: rmp_serde::decode::Error) -> Self {
+		Self::MessagePackDecode(value)
+	}
+}
+
+impl From<argon2::Error> for Error {
+	fn from(value: argon2::Error) -> Self {
+		Self::KeyDerivation(value)
+	}
+}
+
+impl From<rusqlite::Error> for Error {
+	fn from(value: rusqlite::Error) -> Self {
+		Self::Sqlite(value)
+	}
+}
+
+impl From<serde_json::Error> for Error {
+	fn from(value: serde_json::Error) -> Self {
+		Self::Json(value)
+	}
+}
+
+impl From<image::ImageError> for Error {
+	fn from(value: image::ImageError) -> Self {
+		Self::Image(value)
+	}
+}
+
+/// Stores face profiles under unique names
+pub trait FaceStore {
+	/// Returns the names of all faces, including those which cannot be loaded
+	fn list(&self) -> Result<Vec<String>, Error>;
+	/// Returns `None` if there is no face of the given name
+	fn get(&self, name: &str) -> Result<Option<FaceProfile>, Error>;
+	/// Stores a face, replacing any existing face of the same name
+	fn put(&self, name: &str, face_profile: &FaceProfile) -> Result<(), Error>;
+	fn delete(&self, name: &str) -> Result<(), Error>;
+	/// Renames a face. This fails if a face of the new name already exists
+	fn rename(&self, old_name: &str, new_name: &str) -> Result<(), Error>;
+	/// Replaces a face with the one returned by `update`, which is given the currently stored
+	/// face. No other process can change the face in between
+	fn update(
+		&self,
+		name: &str,
+		update: &dyn Fn(Option<FaceProfile>) -> Result<FaceProfile, Error>,
+	) -> Result<(), Error>;
+
+	/// Returns `true` if a face of the given name exists, even if it cannot be loaded
+	fn contains(&self, name: &str) -> Result<bool, Error> {
+		validate_name(name)?;
+
+		Ok(self.list()?.iter().any(|n| n == name))
+	}
+}
+
+/// Checks that a name can be used for a face. Names can come from imported files, so this makes
+/// sure they cannot escape the store or collide with files the store uses internally
+pub fn validate_name(name: &str) -> Result<(), Error> {
+	if name.is_empty()
+		|| name.len() > MAX_NAME_LENGTH
+		|| name.starts_with('.')
+		|| name.contains('/')
+		|| name.contains(char::is_control)
+	{
+		return Err(Error::InvalidName(name.to_owned()));
+	}
+
+	Ok(())
+}
+
+/// Converts a number of seconds from the config into a `Duration`, saturating if it is too large
+fn get_duration(seconds: u64) -> Duration {
+	i64::try_from(seconds)
+		.ok()
+		.and_then(Duration::try_seconds)
+		.unwrap_or(Duration::MAX)
+}
+
+/// Returns the directory with the faces of the user. It is looked up using the home directory of
+/// the user rather than the environment, which belongs to the process and not necessarily to the
+/// user, such as when root authenticates another user
+pub fn get_embeddings_directory(user: &str) -> Result<PathBuf, Error> {
+	let Some(user) = User::from_name(user).map_err(io::Error::from)? else {
+		return Err(Error::UnknownUser(user.to_owned()));
+	};
+
+	Ok(user.dir.join(STATE_DIRECTORY).join(OBLICHEY_DIRECTORY_NAME))
+}
+
+/// Returns the name of the user whose faces are used. Anyone can set arguments and environment
+/// variables, so only processes running as root can act on behalf of another user: the requested
+/// one (such as the user being authenticated by the PAM module) or the user who ran `sudo`. Other
+/// processes act on behalf of the user they run as
+pub fn get_current_user(requested_user: Option<&str>) -> Result<String, Error> {
+	if geteuid().is_root() {
+		if let Some(user) = requested_user {
+			return Ok(user.to_owned());
+		}
+		if let Some(user) = env::var("SUDO_USER").ok().filter(|_| getuid().is_root()) {
+			return Ok(user);
+		}
+	}
+
+	let uid = getuid();
+	let Some(user) = User::from_uid(uid).map_err(io::Error::from)? else {
+		return Err(Error::UnknownUser(uid.to_string()));
+	};
+	if let Some(requested_user) = requested_user.filter(|u| *u != user.name) {
+		return Err(Error::UserNotAllowed(requested_user.to_owned()));
+	}
+
+	Ok(user.name)
+}
+
+/// Opens the face store of the user using the configured backend
+pub fn open_face_store(config: &StoreConfig, user: &str) -> Result<Box<dyn FaceStore>, Error> {
+	match config {
+		StoreConfig::Directory => Ok(Box::new(FilesystemFaceStore::new(
+			get_embeddings_directory(user)?,
+		)?)),
+		StoreConfig::Sqlite { path } => Ok(Box::new(SqliteFaceStore::new(path, user.to_owned())?)),
+	}
+}
+
+/// Opens the audit log. When the faces are stored in a database, the attempts are recorded in it
+/// as well
+pub fn open_audit_log(
+	audit_config: &AuditConfig,
+	store_config: &StoreConfig,
+) -> Result<Box<dyn AuditLog>, Error> {
+	match store_config {
+		StoreConfig::Directory => Ok(Box::new(FileAuditLog::new(audit_config.path.clone()))),
+		StoreConfig::Sqlite { path } => Ok(Box::new(SqliteAuditLog::new(path)?)),
+	}
+}
+
+pub fn get_log_directory() -> Result<PathBuf, Error> {
+	let cache_dir = env::var("XDG_CACHE_HOME")?;
+	let log_dir_path = PathBuf::from(cache_dir).join(OBLICHEY_DIRECTORY_NAME);
+
+	Ok(log_dir_path)
+}
+
+/// Saves a face profile. If the store is encrypted, the templates are encrypted before being
+/// written
+pub fn save_face_profile(
+	face_store: &dyn FaceStore,
+	name: &str,
+	face_profile: &FaceProfile,
+) -> Result<(), Error> {
+	match StorePublicKey::load(Path::new(keys::KEYS_DIRECTORY))? {
+		Some(public_key) => {
+			let mut face_profile = face_profile.clone();
+			face_profile.seal(&public_key)?;
+			face_store.put(name, &face_profile)
+		}
+		None => face_store.put(name, face_profile),
+	}
+}
+
+/// Updates a face while no other process can change it. If the store is encrypted, the templates
+/// returned by `update` are encrypted before being written
+pub fn update_face_profile(
+	face_store: &dyn FaceStore,
+	name: &str,
+	update: &dyn Fn(Option<FaceProfile>) -> Result<FaceProfile, Error>,
+) -> Result<(), Error> {
+	let public_key = StorePublicKey::load(Path::new(keys::KEYS_DIRECTORY))?;
+
+	face_store.update(name, &|face_profile| {
+		let mut face_profile = update(face_profile)?;
+		if let Some(public_key) = &public_key {
+			face_profile.seal(public_key)?;
+		}
+
+		Ok(face_profile)
+	})
+}
+
+/// Returns the templates of all enabled faces. Faces whose templates were not transformed using the
+/// given projection cannot be compared, so they are reported and skipped
+pub fn get_face_templates(
+	face_profiles: HashMap<String, FaceProfile>,
+	projection: Option<&Projection>,
+) -> HashMap<String, Vec<FaceTemplate>> {
+	let projection_id = projection.map(Projection::id);
+	face_profiles
+		.into_iter()
+		.filter_map(|(name, face_profile)| {
+			if face_profile.disabled {
+				info!("Skipping face scan {name} as it is disabled");
+				return None;
+			}
+			if face_profile.projection_id.as_deref() == projection_id {
+				return Some((name, face_profile.templates));
+			}
+			if face_profile.projection_id.is_none() {
+				log_and_print_warn!("Skipping face scan {name} as it is not transformed yet - run `sudo oblichey-cli store project`");
+			} else {
+				log_and_print_warn!(
+					"Skipping face scan {name}: {}",
+					Error::ProjectionKeyUnavailable
+				);
+			}
+
+			None
+		})
+		.collect()
+}
+
+/// Loads all face profiles. Profiles which cannot be loaded are reported and skipped, so that a
+/// single corrupted file does not prevent using the others
+pub fn load_face_profiles(
+	face_store: &dyn FaceStore,
+) -> Result<HashMap<String, FaceProfile>, Error> {
+	let mut face_profiles = HashMap::new();
+	for name in face_store.list()? {
+		let face_profile = match face_store.get(&name) {
+			Ok(Some(p)) => p,
+			Ok(None) => continue,
+			Err(e) => {
+				log_and_print_warn!("Failed to load face scan {name} - skipping: {e}");
+				continue;
+			}
+		};
+
+		face_profiles.insert(name, face_profile);
+	}
+
+	Ok(face_profiles)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		filesystem::FilesystemFaceStore, get_face_templates, load_face_profiles,
+		memory::MemoryFaceStore, profile::FaceProfile, sqlite::SqliteFaceStore, validate_name,
+		Error, FaceStore,
+	};
+	use crate::processors::{
+		face::{FaceEmbedding, FaceTemplate, EMBEDDING_LENGTH},
+		projection::Projection,
+	};
+	use std::{collections::HashMap, env, fs, os::unix::fs::PermissionsExt};
+
+	fn get_face_profile(display_name: &str) -> FaceProfile {
+		FaceProfile::new(None, display_name.to_owned(), None, vec![])
+	}
+
+	fn check_operations(face_store: &dyn FaceStore) {
+		let alice = get_face_profile("Alice");
+		let bob = get_face_profile("Bob");
+
+		face_store.put("alice", &alice).expect("Failed to put");
+		face_store.put("bob", &bob).expect("Failed to put");
+		face_store.put("bob", &alice).expect("Failed to replace");
+		assert_eq!(face_store.list().expect("Failed to list"), ["alice", "bob"]);
+		assert_eq!(
+			face_store.get("bob").expect("Failed to get"),
+			Some(alice.clone())
+		);
+		assert_eq!(face_store.get("charlie").expect("Failed to get"), None);
+		assert!(face_store.contains("alice").expect("Failed to check"));
+
+		assert!(matches!(
+			face_store.rename("alice", "bob"),
+			Err(Error::AlreadyExists(_))
+		));
+		assert!(matches!(
+			face_store.rename("charlie", "dave"),
+			Err(Error::NotFound(_))
+		));
+		face_store
+			.rename("alice", "charlie")
+			.expect("Failed to rename");
+		assert_eq!(
+			face_store.list().expect("Failed to list"),
+			["bob", "charlie"]
+		);
+
+		face_store
+			.update("charlie", &|p| {
+				let mut p = p.ok_or_else(|| Error::NotFound(String::from("charlie")))?;
+				p.notes = Some(String::from("Updated"));
+				Ok(p)
+			})
+			.expect("Failed to update");
+		face_store
+			.update("dave", &|p| Ok(p.unwrap_or_else(|| bob.clone())))
+			.expect("Failed to update");
+		assert!(matches!(
+			face_store.update("bob", &|_| Err(Error::AlreadyExists(String::from("bob")))),
+			Err(Error::AlreadyExists(_))
+		));
+		assert_eq!(
+			face_store
+				.get("charlie")
+				.expect("Failed to get")
+				.and_then(|p| p.notes),
+			Some(String::from("Updated"))
+		);
+		assert_eq!(face_store.get("dave").expect("Failed to get"), Some(bob));
+		face_store.delete("dave").expect("Failed to delete");
+
+		face_store.delete("bob").expect("Failed to delete");
+		assert!(matches!(face_store.delete("bob"), Err(Error::NotFound(_))));
+		assert!(!face_store.contains("bob").expect("Failed to check"));
+		assert!(matches!(
+			face_store.put("../alice", &alice),
+			Err(Error::InvalidName(_))
+		));
+		assert_eq!(face_store.list().expect("Failed to list"), ["charlie"]);
+	}
+
+	#[test]
+	fn performs_operations() {
+		let directory = env::temp_dir().join(format!("oblichey-store-{}", std::process::id()));
+
+		check_operations(&MemoryFaceStore::default());
+		check_operations(
+			&FilesystemFaceStore::new(directory.clone()).expect("Failed to create store"),
+		);
+		check_operations(
+			&SqliteFaceStore::new(&directory.join("faces.db"), String::from("alice"))
+				.expect("Failed to create store"),
+		);
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+	}
+
+	fn check_rename(face_store: &dyn FaceStore) {
+		let alice = get_face_profile("Alice");
+		let bob = get_face_profile("Bob");
+		face_store.put("alice", &alice).expect("Failed to put");
+		face_store.put("bob", &bob).expect("Failed to put");
+
+		face_store
+			.rename("alice", "carol")
+			.expect("Failed to rename");
+		assert!(matches!(
+			face_store.rename("carol", "bob"),
+			Err(Error::AlreadyExists(_))
+		));
+		assert!(matches!(
+			face_store.rename("carol", "../carol"),
+			Err(Error::InvalidName(_))
+		));
+
+		assert_eq!(face_store.get("alice").expect("Failed to get"), None);
+		assert_eq!(face_store.get("carol").expect("Failed to get"), Some(alice));
+		assert_eq!(face_store.get("bob").expect("Failed to get"), Some(bob));
+	}
+
+	#[test]
+	fn renames_faces() {
+		let directory = env::temp_dir().join(format!("oblichey-rename-{}", std::process::id()));
+
+		check_rename(&MemoryFaceStore::default());
+		check_rename(&FilesystemFaceStore::new(directory.clone()).expect("Failed to create store"));
+		check_rename(
+			&SqliteFaceStore::new(&directory.join("faces.db"), String::from("alice"))
+				.expect("Failed to create store"),
+		);
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+	}
+
+	#[test]
+	fn skips_unusable_faces() {
+		let projection = Projection::new(String::from("key"), [1; 32]);
+		let other_projection = Projection::new(String::from("other key"), [2; 32]);
+		let template = FaceTemplate {
+			recognizer_id: None,
+			embedding: FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]),
+			pose: None,
+			adapted_at: None,
+		};
+		let create_face_profile = |disabled: bool, projection: Option<&Projection>| {
+			let mut face_profile =
+				FaceProfile::new(None, String::new(), None, vec![template.clone()]);
+			face_profile.disabled = disabled;
+			if let Some(projection) = projection {
+				face_profile.project(projection).expect("Failed to project");
+			}
+			face_profile
+		};
+		let test_cases = vec![
+			(create_face_profile(false, None), None, true),
+			(create_face_profile(true, None), None, false),
+			(
+				create_face_profile(false, Some(&projection)),
+				Some(&projection),
+				true,
+			),
+			(
+				create_face_profile(true, Some(&projection)),
+				Some(&projection),
+				false,
+			),
+			(create_face_profile(false, None), Some(&projection), false),
+			(
+				create_face_profile(false, Some(&other_projection)),
+				Some(&projection),
+				false,
+			),
+			(create_face_profile(false, Some(&projection)), None, false),
+		];
+
+		for (face_profile, projection, expected_result) in test_cases {
+			let face_templates = get_face_templates(
+				HashMap::from([(String::from("face"), face_profile)]),
+				projection,
+			);
+			assert_eq!(face_templates.contains_key("face"), expected_result);
+		}
+	}
+
+	#[test]
+	fn skips_corrupted_faces() {
+		let directory = env::temp_dir().join(format!("oblichey-corrupted-{}", std::process::id()));
+		let face_store =
+			FilesystemFaceStore::new(directory.clone()).expect("Failed to create store");
+		face_store
+			.put("alice", &get_face_profile("Alice"))
+			.expect("Failed to put");
+		fs::write(directory.join("bob"), [0xff; 100]).expect("Failed to write");
+
+		let face_profiles = load_face_profiles(&face_store).expect("Failed to load");
+		let names = face_store.list().expect("Failed to list");
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_eq!(names, ["alice", "bob"]);
+		assert_eq!(face_profiles.len(), 1);
+		assert!(face_profiles.contains_key("alice"));
+	}
+
+	#[test]
+	fn uses_read_only_lock_file() {
+		let directory = env::temp_dir().join(format!("oblichey-lock-{}", std::process::id()));
+		fs::create_dir_all(&directory).expect("Failed to create directory");
+		let lock_path = directory.join(".lock");
+		fs::write(&lock_path, []).expect("Failed to write");
+		fs::set_permissions(&lock_path, fs::Permissions::from_mode(0o444))
+			.expect("Failed to set permissions");
+		let face_store =
+			FilesystemFaceStore::new(directory.clone()).expect("Failed to create store");
+
+		check_operations(&face_store);
+		let mode = fs::metadata(&lock_path)
+			.expect("Failed to get metadata")
+			.permissions()
+			.mode();
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_eq!(mode & 0o777, 0o444);
+	}
+
+	#[test]
+	fn rewrites_legacy_files() {
+		let directory = env::temp_dir().join(format!("oblichey-legacy-{}", std::process::id()));
+		let face_store =
+			FilesystemFaceStore::new(directory.clone()).expect("Failed to create store");
+		let embedding = FaceEmbedding::new(&[1.0; EMBEDDING_LENGTH]);
+		fs::write(
+			directory.join("alice"),
+			bincode::serialize(&embedding).expect("Failed to serialize"),
+		)
+		.expect("Failed to write");
+
+		let migrated = face_store.get("alice").expect("Failed to get");
+		let serialized = fs::read(directory.join("alice")).expect("Failed to read");
+		let loaded = face_store.get("alice").expect("Failed to get");
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert!(!FaceProfile::is_legacy(&serialized));
+		assert!(migrated.is_some());
+		assert_eq!(loaded, migrated);
+	}
+
+	#[test]
+	fn validates_names() {
+		let test_cases = vec![
+			("alice", true),
+			("Alice Smith", true),
+			("obličej", true),
+			("", false),
+			(".", false),
+			("..", false),
+			(".hidden", false),
+			("../alice", false),
+			("alice/bob", false),
+			("alice\0", false),
+			("alice\n", false),
+		];
+
+		for (name, expected_result) in test_cases {
+			assert_eq!(validate_name(name).is_ok(), expected_result, "{name}");
+		}
+		assert!(validate_name(&"a".repeat(129)).is_err());
+	}
+}
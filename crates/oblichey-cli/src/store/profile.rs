@@ -0,0 +1,587 @@
+use super::{
+	keys::{StoreKeys, StorePublicKey},
+	Error,
+};
+use crate::processors::{
+	face::{FaceEmbedding, FaceEmbeddingData, FaceTemplate, RecognizerId},
+	projection::Projection,
+};
+use chrono::{DateTime, Duration, Utc};
+use crypto_box::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use std::mem::size_of;
+
+/// Every profile file starts with these bytes, so that it can be told apart from files in the
+/// legacy format which only contained raw bincode
+const MAGIC: &[u8; 8] = b"OBLICHEY";
+/// The version of the format written by this build. The body is self-describing, so adding fields
+/// with defaults does not require a new version, but any other change to `FaceProfile` does along
+/// with a migration in `FaceProfile::deserialize()`. Version 2 added sealed templates, version 3
+/// disabled profiles, version 4 adapted templates and version 5 calibrated thresholds, all of
+/// which older versions would silently ignore
+pub const FORMAT_VERSION: u16 = 5;
+
+/// A `FaceTemplate` as it was stored before the profile format was introduced
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct LegacyFaceTemplate {
+	recognizer_id: Option<RecognizerId>,
+	embedding: FaceEmbedding,
+}
+
+/// Templates encrypted using the public store key, so that only root can decrypt them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedTemplates {
+	key_id: String,
+	/// The recognizers which produced the templates, so that it can be checked whether they need
+	/// to be re-enrolled without decrypting them
+	recognizer_ids: Vec<Option<RecognizerId>>,
+	ciphertext: Vec<u8>,
+}
+
+/// A similarity threshold calibrated for a face, along with the error rates it was calibrated for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+	pub similarity_threshold: f32,
+	pub target_false_accept_rate: f32,
+	/// The share of faces of other people which were accepted at the threshold during calibration
+	pub false_accept_rate: f32,
+	/// The share of samples of the face which were rejected at the threshold during calibration
+	pub false_reject_rate: f32,
+	pub calibrated_at: DateTime<Utc>,
+}
+
+/// An enrolled face along with information about how and when it was enrolled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaceProfile {
+	/// The user who enrolled the face. This is `None` for profiles migrated from the legacy format
+	#[serde(default)]
+	pub owner: Option<String>,
+	pub display_name: String,
+	pub created_at: DateTime<Utc>,
+	pub updated_at: DateTime<Utc>,
+	/// The camera the face was scanned with
+	#[serde(default)]
+	pub camera_id: Option<String>,
+	pub templates: Vec<FaceTemplate>,
+	/// Templates which are encrypted and have not been decrypted using `unseal()`
+	#[serde(default)]
+	pub sealed_templates: Vec<SealedTemplates>,
+	/// The ID of the projection key the templates were transformed with. This is `None` if
+	/// template projection was not enabled when the face was scanned
+	#[serde(default)]
+	pub projection_id: Option<String>,
+	#[serde(default)]
+	pub notes: Option<String>,
+	/// Disabled profiles are kept, but not used for authentication
+	#[serde(default)]
+	pub disabled: bool,
+	/// Replaces the configured similarity threshold for this face, unless it is overridden for
+	/// the user
+	#[serde(default)]
+	pub calibration: Option<Calibration>,
+}
+
+impl FaceProfile {
+	pub fn new(
+		owner: Option<String>,
+		display_name: String,
+		camera_id: Option<String>,
+		templates: Vec<FaceTemplate>,
+	) -> Self {
+		let now = Utc::now();
+		Self {
+			owner,
+			display_name,
+			created_at: now,
+			updated_at: now,
+			camera_id,
+			templates,
+			sealed_templates: Vec::new(),
+			projection_id: None,
+			notes: None,
+			disabled: false,
+			calibration: None,
+		}
+	}
+
+	/// Returns `true` if any of the templates should be re-enrolled as it might have been
+	/// produced by a different recognizer
+	pub fn needs_reenrollment(&self, recognizer_id: &RecognizerId) -> bool {
+		self.templates
+			.iter()
+			.any(|t| t.needs_reenrollment(recognizer_id))
+			|| self
+				.sealed_templates
+				.iter()
+				.flat_map(|s| &s.recognizer_ids)
+				.any(|id| id.as_ref() != Some(recognizer_id))
+	}
+
+	/// Returns `true` if any of the templates are encrypted
+	pub const fn is_sealed(&self) -> bool {
+		!self.sealed_templates.is_empty()
+	}
+
+	/// Encrypts the templates which are not encrypted yet using the public store key
+	pub fn seal(&mut self, public_key: &StorePublicKey) -> Result<(), Error> {
+		if self.templates.is_empty() {
+			return Ok(());
+		}
+
+		let ciphertext = public_key
+			.public_key
+			.seal(&mut OsRng, &rmp_serde::to_vec_named(&self.templates)?)
+			.map_err(|_| Error::Encryption)?;
+		self.sealed_templates.push(SealedTemplates {
+			key_id: public_key.id.clone(),
+			recognizer_ids: self
+				.templates
+				.iter()
+				.map(|t| t.recognizer_id.clone())
+				.collect(),
+			ciphertext,
+		});
+		self.templates.clear();
+
+		Ok(())
+	}
+
+	/// Decrypts all templates using the secret store keys
+	pub fn unseal(&mut self, keys: &StoreKeys) -> Result<(), Error> {
+		let mut templates = Vec::new();
+		for sealed_templates in &self.sealed_templates {
+			let Some(secret_key) = keys.get(&sealed_templates.key_id) else {
+				return Err(Error::KeyUnavailable);
+			};
+			let serialized = secret_key
+				.unseal(&sealed_templates.ciphertext)
+				.map_err(|_| Error::Decryption)?;
+			templates.extend(rmp_serde::from_slice::<Vec<FaceTemplate>>(&serialized)?);
+		}
+
+		self.templates.extend(templates);
+		self.sealed_templates.clear();
+
+		Ok(())
+	}
+
+	/// Adds templates to the gallery of the profile. The calibration was measured against the
+	/// previous templates, so it is removed
+	pub fn append_templates(&mut self, templates: Vec<FaceTemplate>) {
+		self.templates.extend(templates);
+		self.calibration = None;
+		self.updated_at = Utc::now();
+	}
+
+	/// Learns from the embedding of a successful authentication, so that the face keeps being
+	/// recognized as it changes over time. The adapted template is the mean of the scanned
+	/// templates moved towards the embedding by `influence`, so that no number of adaptations can
+	/// move it further away from the face as it was scanned. Only the `max_history` most recent
+	/// adapted templates are kept and at most `max_per_day` adaptations are made within a day, so
+	/// that the face cannot be moved quickly. The calibration is cleared, as it was made with the
+	/// previous templates
+	pub fn adapt(
+		&mut self,
+		embedding: &FaceEmbedding,
+		recognizer_id: &RecognizerId,
+		influence: f32,
+		max_history: usize,
+		max_per_day: usize,
+	) -> Result<(), Error> {
+		let now = Utc::now();
+		let recent_adaptation_count = self
+			.templates
+			.iter()
+			.filter_map(|t| t.adapted_at)
+			.filter(|a| now - *a < Duration::days(1))
+			.count();
+		if recent_adaptation_count >= max_per_day {
+			return Err(Error::AdaptationLimitReached);
+		}
+		let scanned_embeddings: Vec<FaceEmbedding> = self
+			.templates
+			.iter()
+			.filter(|t| !t.is_adapted() && t.is_compatible_with(recognizer_id))
+			.map(|t| t.embedding.normalized())
+			.collect();
+		if scanned_embeddings.is_empty() {
+			return Err(Error::NoScannedTemplates);
+		}
+
+		let mut adapted_embedding =
+			FaceEmbedding::average_embedding(&scanned_embeddings).normalized() * (1.0 - influence);
+		adapted_embedding += embedding.normalized() * influence;
+		self.templates.push(FaceTemplate {
+			recognizer_id: Some(recognizer_id.clone()),
+			embedding: adapted_embedding,
+			pose: None,
+			adapted_at: Some(now),
+		});
+		let adapted_count = self.templates.iter().filter(|t| t.is_adapted()).count();
+		self.roll_back(adapted_count.saturating_sub(max_history), true);
+		self.calibration = None;
+		self.updated_at = now;
+
+		Ok(())
+	}
+
+	/// Removes the given number of adapted templates, starting with the most recent ones unless
+	/// `oldest_first` is set. Returns the number of removed templates
+	pub fn roll_back(&mut self, count: usize, oldest_first: bool) -> usize {
+		let mut adapted_templates: Vec<(usize, DateTime<Utc>)> = self
+			.templates
+			.iter()
+			.enumerate()
+			.filter_map(|(i, t)| Some((i, t.adapted_at?)))
+			.collect();
+		adapted_templates.sort_by_key(|(_, adapted_at)| *adapted_at);
+		if !oldest_first {
+			adapted_templates.reverse();
+		}
+		let mut removed_indices: Vec<usize> = adapted_templates
+			.into_iter()
+			.take(count)
+			.map(|(i, _)| i)
+			.collect();
+		removed_indices.sort_unstable();
+		for index in removed_indices.iter().rev() {
+			self.templates.remove(*index);
+		}
+		if !removed_indices.is_empty() {
+			self.updated_at = Utc::now();
+		}
+
+		removed_indices.len()
+	}
+
+	/// Transforms the templates using the given projection. The projection cannot be undone, so
+	/// templates which were already transformed using another projection cannot be transformed
+	/// again and the face has to be re-scanned instead. The templates have to be unsealed
+	pub fn project(&mut self, projection: &Projection) -> Result<(), Error> {
+		if self.projection_id.is_some() {
+			return Err(Error::ProjectionKeyUnavailable);
+		}
+
+		for template in &mut self.templates {
+			template.embedding = projection.project(&template.embedding);
+		}
+		self.projection_id = Some(projection.id().to_owned());
+
+		Ok(())
+	}
+
+	/// Serializes the profile into the current format: the magic bytes, the little-endian format
+	/// version and a `MessagePack` body with named fields
+	pub fn serialize(&self) -> Result<Vec<u8>, Error> {
+		let mut serialized = MAGIC.to_vec();
+		serialized.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+		serialized.extend(rmp_serde::to_vec_named(self)?);
+
+		Ok(serialized)
+	}
+
+	/// Returns `true` if the serialized profile is in the legacy format, which `deserialize()`
+	/// migrates
+	pub fn is_legacy(serialized: &[u8]) -> bool {
+		!serialized.starts_with(MAGIC)
+	}
+
+	/// Deserializes a profile of any known format version, migrating it to the current one. Files
+	/// in the legacy format do not have any metadata, so the name of the file and the time it was
+	/// last modified are used instead
+	pub fn deserialize(
+		serialized: &[u8],
+		name: &str,
+		modified_at: DateTime<Utc>,
+	) -> Result<Self, Error> {
+		let Some(rest) = serialized.strip_prefix(MAGIC) else {
+			return Self::migrate_legacy(serialized, name, modified_at);
+		};
+		let Some((version, body)) = rest.split_first_chunk::<{ size_of::<u16>() }>() else {
+			return Err(Error::TruncatedHeader);
+		};
+
+		match u16::from_le_bytes(*version) {
+			1..=5 => Ok(rmp_serde::from_slice(body)?),
+			v => Err(Error::UnsupportedFormatVersion(v)),
+		}
+	}
+
+	/// Migrates raw bincode files which either contain only a `FaceEmbedding` or a `LegacyFaceTemplate`
+	fn migrate_legacy(
+		serialized: &[u8],
+		name: &str,
+		modified_at: DateTime<Utc>,
+	) -> Result<Self, Error> {
+		let template = if serialized.len() == size_of::<FaceEmbeddingData>() {
+			FaceTemplate {
+				recognizer_id: None,
+				embedding: bincode::deserialize(serialized)?,
+				pose: None,
+				adapted_at: None,
+			}
+		} else {
+			let legacy: LegacyFaceTemplate = bincode::deserialize(serialized)?;
+			FaceTemplate {
+				recognizer_id: legacy.recognizer_id,
+				embedding: legacy.embedding,
+				pose: None,
+				adapted_at: None,
+			}
+		};
+
+		Ok(Self {
+			owner: None,
+			display_name: name.to_owned(),
+			created_at: modified_at,
+			updated_at: modified_at,
+			camera_id: None,
+			templates: vec![template],
+			sealed_templates: Vec::new(),
+			projection_id: None,
+			notes: None,
+			disabled: false,
+			calibration: None,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Calibration, FaceProfile, LegacyFaceTemplate, FORMAT_VERSION, MAGIC};
+	use crate::{
+		processors::face::{FaceEmbedding, FaceTemplate, RecognizerId, EMBEDDING_LENGTH},
+		store::{keys::StoreKeys, Error},
+	};
+	use chrono::{DateTime, Utc};
+
+	fn get_face_template() -> FaceTemplate {
+		FaceTemplate {
+			recognizer_id: Some(RecognizerId::new(String::from("recognizer"))),
+			embedding: FaceEmbedding::new(&[0.5; EMBEDDING_LENGTH]),
+			pose: None,
+			adapted_at: None,
+		}
+	}
+
+	#[test]
+	fn serializes_profile() {
+		let mut profile = FaceProfile::new(
+			Some(String::from("alice")),
+			String::from("Alice"),
+			Some(String::from("/dev/video2")),
+			vec![get_face_template()],
+		);
+		profile.notes = Some(String::from("Without glasses"));
+		profile.disabled = true;
+		profile.calibration = Some(Calibration {
+			similarity_threshold: 0.85,
+			target_false_accept_rate: 0.001,
+			false_accept_rate: 0.0005,
+			false_reject_rate: 0.02,
+			calibrated_at: Utc::now(),
+		});
+
+		let serialized = profile.serialize().expect("Failed to serialize");
+		assert!(serialized.starts_with(MAGIC));
+		assert_eq!(
+			serialized[MAGIC.len()..MAGIC.len() + 2],
+			FORMAT_VERSION.to_le_bytes()
+		);
+
+		let deserialized = FaceProfile::deserialize(&serialized, "alice", DateTime::UNIX_EPOCH)
+			.expect("Failed to deserialize");
+		assert_eq!(deserialized, profile);
+	}
+
+	#[test]
+	fn migrates_legacy_files() {
+		let template = get_face_template();
+		let modified_at = Utc::now();
+		let test_cases = vec![
+			(
+				bincode::serialize(&template.embedding).expect("Failed to serialize"),
+				None,
+			),
+			(
+				bincode::serialize(&LegacyFaceTemplate {
+					recognizer_id: template.recognizer_id.clone(),
+					embedding: template.embedding,
+				})
+				.expect("Failed to serialize"),
+				template.recognizer_id.clone(),
+			),
+		];
+
+		for (serialized, recognizer_id) in test_cases {
+			let profile = FaceProfile::deserialize(&serialized, "alice", modified_at)
+				.expect("Failed to migrate");
+			assert_eq!(profile.display_name, "alice");
+			assert_eq!(profile.owner, None);
+			assert_eq!(profile.created_at, modified_at);
+			assert_eq!(
+				profile.templates,
+				vec![FaceTemplate {
+					recognizer_id,
+					embedding: template.embedding,
+					pose: None,
+					adapted_at: None,
+				}]
+			);
+		}
+	}
+
+	#[test]
+	fn adapts_templates() {
+		let recognizer_id = RecognizerId::new(String::from("recognizer"));
+		let scanned_embedding = {
+			let mut embedding = [0.0; EMBEDDING_LENGTH];
+			embedding[0] = 1.0;
+			FaceEmbedding::new(&embedding)
+		};
+		let new_embedding = {
+			let mut embedding = [0.0; EMBEDDING_LENGTH];
+			embedding[1] = 1.0;
+			FaceEmbedding::new(&embedding)
+		};
+		let mut profile = FaceProfile::new(
+			None,
+			String::from("alice"),
+			None,
+			vec![FaceTemplate {
+				embedding: scanned_embedding,
+				..get_face_template()
+			}],
+		);
+
+		for _ in 0..5 {
+			profile
+				.adapt(&new_embedding, &recognizer_id, 0.2, 3, usize::MAX)
+				.expect("Failed to adapt");
+		}
+		let adapted_templates: Vec<&FaceTemplate> = profile
+			.templates
+			.iter()
+			.filter(|t| t.is_adapted())
+			.collect();
+
+		// The history is capped and each adaptation is anchored to the scanned template
+		assert_eq!(profile.templates.len(), 4);
+		assert_eq!(adapted_templates.len(), 3);
+		for template in adapted_templates {
+			assert!((template.embedding.data()[0] - 0.8).abs() < 1e-6);
+			assert!((template.embedding.data()[1] - 0.2).abs() < 1e-6);
+		}
+
+		assert_eq!(profile.roll_back(1, false), 1);
+		assert_eq!(profile.roll_back(usize::MAX, false), 2);
+		assert_eq!(profile.roll_back(usize::MAX, false), 0);
+		assert_eq!(profile.templates.len(), 1);
+		assert!(!profile.templates[0].is_adapted());
+
+		// Only the given number of adaptations are made within a day
+		let mut limited_profile = FaceProfile::new(
+			None,
+			String::from("carol"),
+			None,
+			vec![FaceTemplate {
+				embedding: scanned_embedding,
+				..get_face_template()
+			}],
+		);
+		limited_profile.calibration = Some(Calibration {
+			similarity_threshold: 0.9,
+			target_false_accept_rate: 0.01,
+			false_accept_rate: 0.0,
+			false_reject_rate: 0.0,
+			calibrated_at: Utc::now(),
+		});
+		limited_profile
+			.adapt(&new_embedding, &recognizer_id, 0.2, 3, 1)
+			.expect("Failed to adapt");
+		assert!(matches!(
+			limited_profile.adapt(&new_embedding, &recognizer_id, 0.2, 3, 1),
+			Err(Error::AdaptationLimitReached)
+		));
+		// The calibration was made with the previous templates
+		assert_eq!(limited_profile.calibration, None);
+
+		let mut empty_profile = FaceProfile::new(None, String::from("bob"), None, vec![]);
+		assert!(matches!(
+			empty_profile.adapt(&new_embedding, &recognizer_id, 0.2, 3, usize::MAX),
+			Err(Error::NoScannedTemplates)
+		));
+	}
+
+	#[test]
+	fn rejects_corrupted_files() {
+		let mut unsupported_version = MAGIC.to_vec();
+		unsupported_version.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+		let mut truncated_body = FaceProfile::new(None, String::from("alice"), None, vec![])
+			.serialize()
+			.expect("Failed to serialize");
+		truncated_body.truncate(truncated_body.len() - 1);
+
+		let test_cases = vec![
+			MAGIC.to_vec(),
+			unsupported_version,
+			truncated_body,
+			vec![],
+			vec![0xff; 100],
+		];
+
+		for serialized in test_cases {
+			assert!(FaceProfile::deserialize(&serialized, "alice", DateTime::UNIX_EPOCH).is_err());
+		}
+		assert!(matches!(
+			FaceProfile::deserialize(MAGIC, "alice", DateTime::UNIX_EPOCH),
+			Err(Error::TruncatedHeader)
+		));
+	}
+
+	#[test]
+	fn seals_templates() {
+		let template = get_face_template();
+		let mut keys = StoreKeys::generate();
+		let mut profile =
+			FaceProfile::new(None, String::from("alice"), None, vec![template.clone()]);
+
+		profile
+			.seal(
+				&keys
+					.get_current_public_key()
+					.expect("Failed to get public key"),
+			)
+			.expect("Failed to seal");
+		keys.rotate();
+		profile.append_templates(vec![template.clone()]);
+		profile
+			.seal(
+				&keys
+					.get_current_public_key()
+					.expect("Failed to get public key"),
+			)
+			.expect("Failed to seal");
+		assert!(profile.is_sealed());
+		assert!(profile.templates.is_empty());
+		assert!(!profile.needs_reenrollment(
+			template
+				.recognizer_id
+				.as_ref()
+				.expect("Recognizer ID is missing")
+		));
+		assert!(profile.needs_reenrollment(&RecognizerId::new(String::from("other"))));
+
+		let serialized = profile.serialize().expect("Failed to serialize");
+		let mut deserialized = FaceProfile::deserialize(&serialized, "alice", DateTime::UNIX_EPOCH)
+			.expect("Failed to deserialize");
+		assert!(matches!(
+			deserialized.clone().unseal(&StoreKeys::generate()),
+			Err(Error::KeyUnavailable)
+		));
+		deserialized.unseal(&keys).expect("Failed to unseal");
+		assert!(!deserialized.is_sealed());
+		assert_eq!(deserialized.templates, vec![template.clone(), template]);
+	}
+}
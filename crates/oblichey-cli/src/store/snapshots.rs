@@ -0,0 +1,130 @@
+use super::{get_duration, validate_name, Error};
+use crate::camera::Frame;
+use chrono::{DateTime, Utc};
+use image::ImageFormat;
+use std::{
+	fs::{self, DirBuilder, OpenOptions},
+	io::{Cursor, Write},
+	os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+	path::PathBuf,
+};
+
+/// The directory with the snapshots of failed authentication attempts. They show whoever was in
+/// front of the camera, so only root can access them
+pub const SNAPSHOTS_DIRECTORY: &str = "/var/lib/oblichey/snapshots";
+const SNAPSHOTS_DIRECTORY_MODE: u32 = 0o700;
+const SNAPSHOT_FILE_MODE: u32 = 0o600;
+const SNAPSHOT_EXTENSION: &str = "png";
+
+/// Keeps images of failed authentication attempts within a limit on their number and age
+pub struct SnapshotStore {
+	directory: PathBuf,
+	max_count: usize,
+	/// In seconds
+	max_age: u64,
+}
+
+impl SnapshotStore {
+	pub const fn new(directory: PathBuf, max_count: usize, max_age: u64) -> Self {
+		Self {
+			directory,
+			max_count,
+			max_age,
+		}
+	}
+
+	/// Saves the image of a failed attempt of the given user and removes the snapshots which are
+	/// over the limits. Returns the path of the saved snapshot
+	pub fn save(&self, image: &Frame, user: &str, now: DateTime<Utc>) -> Result<PathBuf, Error> {
+		validate_name(user)?;
+		DirBuilder::new()
+			.recursive(true)
+			.mode(SNAPSHOTS_DIRECTORY_MODE)
+			.create(&self.directory)?;
+
+		let mut serialized = Cursor::new(Vec::new());
+		image.write_to(&mut serialized, ImageFormat::Png)?;
+		let path = self.directory.join(format!(
+			"{}-{user}.{SNAPSHOT_EXTENSION}",
+			now.format("%Y%m%dT%H%M%S%.3fZ"),
+		));
+		OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(SNAPSHOT_FILE_MODE)
+			.open(&path)?
+			.write_all(serialized.get_ref())?;
+
+		self.prune(now)?;
+
+		Ok(path)
+	}
+
+	/// Removes the snapshots older than the maximum age and then the oldest ones over the maximum
+	/// count
+	pub fn prune(&self, now: DateTime<Utc>) -> Result<(), Error> {
+		let mut snapshots = Vec::new();
+		for entry in fs::read_dir(&self.directory)? {
+			let path = entry?.path();
+			if path.extension().map_or(true, |e| e != SNAPSHOT_EXTENSION) {
+				continue;
+			}
+			let modified = DateTime::<Utc>::from(fs::metadata(&path)?.modified()?);
+			snapshots.push((modified, path));
+		}
+		// Oldest first
+		snapshots.sort();
+
+		let max_age = get_duration(self.max_age);
+		let excess_count = snapshots.len().saturating_sub(self.max_count);
+		for (index, (modified, path)) in snapshots.into_iter().enumerate() {
+			if index < excess_count || now.signed_duration_since(modified) > max_age {
+				fs::remove_file(path)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::SnapshotStore;
+	use crate::camera::Frame;
+	use chrono::{Duration, Utc};
+	use std::{env, fs};
+
+	#[test]
+	fn limits_snapshots() {
+		let directory = env::temp_dir().join(format!("oblichey-snapshots-{}", std::process::id()));
+		let snapshot_store = SnapshotStore::new(directory.clone(), 2, 60);
+		let image = Frame::new(4, 4);
+		let start = Utc::now();
+
+		let paths: Vec<_> = (0..3)
+			.map(|i| {
+				snapshot_store
+					.save(&image, "alice", start + Duration::seconds(i))
+					.expect("Failed to save snapshot")
+			})
+			.collect();
+
+		// The oldest snapshot is removed to stay within the maximum count
+		assert!(!paths[0].exists());
+		assert!(paths[1].exists());
+		assert!(paths[2].exists());
+		assert!(image::open(&paths[2]).is_ok());
+
+		// Expired snapshots are removed
+		snapshot_store
+			.prune(start + Duration::seconds(120))
+			.expect("Failed to prune snapshots");
+		let remaining_count = fs::read_dir(&directory)
+			.expect("Failed to read directory")
+			.count();
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_eq!(remaining_count, 0);
+		assert!(snapshot_store.save(&image, "../alice", start).is_err());
+	}
+}
@@ -0,0 +1,398 @@
+use super::{
+	audit::{AuditEntry, AuditLog},
+	profile::FaceProfile,
+	validate_name, Error, FaceStore,
+};
+use crate::{log_and_print_warn, processors::face::FaceTemplate};
+use chrono::Utc;
+use nix::unistd::geteuid;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use std::{
+	fs::{self, OpenOptions},
+	io,
+	os::unix::fs::OpenOptionsExt,
+	path::Path,
+};
+
+/// The database contains biometric data and the audit log of all users, so only root should be
+/// able to access it. `SQLite` creates the journal with the same permissions
+const DATABASE_FILE_MODE: u32 = 0o600;
+/// The version of the schema created by this build, stored in `user_version`
+const SCHEMA_VERSION: u32 = 2;
+/// The tables of the first version of the schema
+const SCHEMA: &str = "
+	CREATE TABLE users (
+		id INTEGER PRIMARY KEY,
+		name TEXT NOT NULL UNIQUE
+	);
+	CREATE TABLE profiles (
+		id INTEGER PRIMARY KEY,
+		user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+		name TEXT NOT NULL,
+		-- The profile without its unencrypted templates in the profile format
+		profile BLOB NOT NULL,
+		UNIQUE (user_id, name)
+	);
+	CREATE TABLE templates (
+		id INTEGER PRIMARY KEY,
+		profile_id INTEGER NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+		-- A `FaceTemplate` serialized using MessagePack
+		template BLOB NOT NULL
+	);
+";
+/// The tables added by the second version of the schema
+const AUDIT_SCHEMA: &str = "
+	CREATE TABLE audit (
+		id INTEGER PRIMARY KEY,
+		timestamp TEXT NOT NULL,
+		user TEXT,
+		outcome TEXT NOT NULL,
+		-- The `AuditEntry` serialized using JSON
+		entry TEXT NOT NULL
+	);
+";
+
+/// Opens the database, creating it or updating its schema if needed. The database is shared by
+/// all users, so like the store keys, it belongs to root and only root can create it
+fn open_database(path: &Path) -> Result<Connection, Error> {
+	if !geteuid().is_root() && !path.try_exists()? {
+		return Err(Error::DatabaseAccessDenied);
+	}
+	if let Some(directory) = path.parent() {
+		fs::create_dir_all(directory)?;
+	}
+	match OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(false)
+		.mode(DATABASE_FILE_MODE)
+		.open(path)
+	{
+		Ok(_) => {}
+		Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+			return Err(Error::DatabaseAccessDenied)
+		}
+		Err(e) => return Err(Error::from(e)),
+	}
+	let connection = Connection::open(path)?;
+	connection.pragma_update(None, "foreign_keys", true)?;
+
+	let schema_version: u32 = connection.pragma_query_value(None, "user_version", |r| r.get(0))?;
+	let schema = match schema_version {
+		0 => SCHEMA.to_owned() + AUDIT_SCHEMA,
+		1 => AUDIT_SCHEMA.to_owned(),
+		SCHEMA_VERSION => return Ok(connection),
+		v => return Err(Error::UnsupportedSchemaVersion(v)),
+	};
+	let transaction = connection.unchecked_transaction()?;
+	transaction.execute_batch(&schema)?;
+	transaction.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+	transaction.commit()?;
+
+	Ok(connection)
+}
+
+/// Stores the faces of all users in a single `SQLite` database, which is useful for machines shared
+/// by many users. Each instance only sees the faces of the user it was opened for
+pub struct SqliteFaceStore {
+	connection: Connection,
+	user: String,
+}
+
+impl SqliteFaceStore {
+	pub fn new(path: &Path, user: String) -> Result<Self, Error> {
+		Ok(Self {
+			connection: open_database(path)?,
+			user,
+		})
+	}
+
+	fn get_profile_id(
+		transaction: &Transaction,
+		user: &str,
+		name: &str,
+	) -> Result<Option<i64>, Error> {
+		Ok(transaction
+			.query_row(
+				"SELECT profiles.id FROM profiles JOIN users ON users.id = profiles.user_id
+				WHERE users.name = ?1 AND profiles.name = ?2",
+				params![user, name],
+				|r| r.get(0),
+			)
+			.optional()?)
+	}
+
+	fn read(
+		transaction: &Transaction,
+		user: &str,
+		name: &str,
+	) -> Result<Option<FaceProfile>, Error> {
+		let Some(profile_id) = Self::get_profile_id(transaction, user, name)? else {
+			return Ok(None);
+		};
+
+		let serialized: Vec<u8> = transaction.query_row(
+			"SELECT profile FROM profiles WHERE id = ?1",
+			params![profile_id],
+			|r| r.get(0),
+		)?;
+		let mut face_profile = FaceProfile::deserialize(&serialized, name, Utc::now())?;
+		let mut statement = transaction
+			.prepare("SELECT template FROM templates WHERE profile_id = ?1 ORDER BY id")?;
+		for serialized in statement.query_map(params![profile_id], |r| r.get::<_, Vec<u8>>(0))? {
+			face_profile
+				.templates
+				.push(rmp_serde::from_slice::<FaceTemplate>(&serialized?)?);
+		}
+
+		Ok(Some(face_profile))
+	}
+
+	/// Replaces the profile and all of its templates. The transaction has to be committed by the
+	/// caller
+	fn write(
+		transaction: &Transaction,
+		user: &str,
+		name: &str,
+		face_profile: &FaceProfile,
+	) -> Result<(), Error> {
+		let mut profile_without_templates = face_profile.clone();
+		profile_without_templates.templates.clear();
+
+		transaction.execute(
+			"INSERT INTO users (name) VALUES (?1) ON CONFLICT (name) DO NOTHING",
+			params![user],
+		)?;
+		transaction.execute(
+			"DELETE FROM profiles WHERE name = ?2
+			AND user_id = (SELECT id FROM users WHERE name = ?1)",
+			params![user, name],
+		)?;
+		transaction.execute(
+			"INSERT INTO profiles (user_id, name, profile)
+			SELECT id, ?2, ?3 FROM users WHERE name = ?1",
+			params![user, name, profile_without_templates.serialize()?],
+		)?;
+		let profile_id = transaction.last_insert_rowid();
+		for template in &face_profile.templates {
+			transaction.execute(
+				"INSERT INTO templates (profile_id, template) VALUES (?1, ?2)",
+				params![profile_id, rmp_serde::to_vec_named(template)?],
+			)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl FaceStore for SqliteFaceStore {
+	fn list(&self) -> Result<Vec<String>, Error> {
+		let mut statement = self.connection.prepare(
+			"SELECT profiles.name FROM profiles JOIN users ON users.id = profiles.user_id
+			WHERE users.name = ?1 ORDER BY profiles.name",
+		)?;
+		let names = statement
+			.query_map(params![self.user], |r| r.get(0))?
+			.collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+		Ok(names)
+	}
+
+	fn get(&self, name: &str) -> Result<Option<FaceProfile>, Error> {
+		validate_name(name)?;
+		let transaction = self.connection.unchecked_transaction()?;
+
+		Self::read(&transaction, &self.user, name)
+	}
+
+	/// Replaces the profile and all of its templates in a single transaction
+	fn put(&self, name: &str, face_profile: &FaceProfile) -> Result<(), Error> {
+		validate_name(name)?;
+		let transaction = self.connection.unchecked_transaction()?;
+		Self::write(&transaction, &self.user, name, face_profile)?;
+		transaction.commit()?;
+
+		Ok(())
+	}
+
+	fn delete(&self, name: &str) -> Result<(), Error> {
+		validate_name(name)?;
+		let deleted_count = self.connection.execute(
+			"DELETE FROM profiles WHERE name = ?2
+			AND user_id = (SELECT id FROM users WHERE name = ?1)",
+			params![self.user, name],
+		)?;
+		if deleted_count == 0 {
+			return Err(Error::NotFound(name.to_owned()));
+		}
+
+		Ok(())
+	}
+
+	fn rename(&self, old_name: &str, new_name: &str) -> Result<(), Error> {
+		validate_name(old_name)?;
+		validate_name(new_name)?;
+
+		let transaction = self.connection.unchecked_transaction()?;
+		let Some(profile_id) = Self::get_profile_id(&transaction, &self.user, old_name)? else {
+			return Err(Error::NotFound(old_name.to_owned()));
+		};
+		if Self::get_profile_id(&transaction, &self.user, new_name)?.is_some() {
+			return Err(Error::AlreadyExists(new_name.to_owned()));
+		}
+		transaction.execute(
+			"UPDATE profiles SET name = ?2 WHERE id = ?1",
+			params![profile_id, new_name],
+		)?;
+		transaction.commit()?;
+
+		Ok(())
+	}
+
+	/// The transaction takes the write lock of the database immediately, so that no other process
+	/// can change the face after it is read
+	fn update(
+		&self,
+		name: &str,
+		update: &dyn Fn(Option<FaceProfile>) -> Result<FaceProfile, Error>,
+	) -> Result<(), Error> {
+		validate_name(name)?;
+		let transaction =
+			Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+		let face_profile = update(Self::read(&transaction, &self.user, name)?)?;
+		Self::write(&transaction, &self.user, name, &face_profile)?;
+		transaction.commit()?;
+
+		Ok(())
+	}
+}
+
+/// Records authentication attempts in the database of the faces, so that everything about the
+/// users of a shared machine is kept in one place
+pub struct SqliteAuditLog {
+	connection: Connection,
+}
+
+impl SqliteAuditLog {
+	pub fn new(path: &Path) -> Result<Self, Error> {
+		Ok(Self {
+			connection: open_database(path)?,
+		})
+	}
+}
+
+impl AuditLog for SqliteAuditLog {
+	fn append(&self, entry: &AuditEntry) -> Result<(), Error> {
+		self.connection.execute(
+			"INSERT INTO audit (timestamp, user, outcome, entry) VALUES (?1, ?2, ?3, ?4)",
+			params![
+				entry.timestamp.to_rfc3339(),
+				entry.user,
+				entry.outcome.to_string(),
+				serde_json::to_string(entry)?
+			],
+		)?;
+
+		Ok(())
+	}
+
+	fn read(&self) -> Result<Vec<AuditEntry>, Error> {
+		let mut statement = self
+			.connection
+			.prepare("SELECT id, entry FROM audit ORDER BY id")?;
+		let rows = statement
+			.query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?
+			.collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+		let mut entries = Vec::new();
+		for (id, serialized) in rows {
+			match serde_json::from_str(&serialized) {
+				Ok(e) => entries.push(e),
+				Err(e) => {
+					log_and_print_warn!("Failed to parse audit entry {id} - skipping: {e}");
+				}
+			}
+		}
+
+		Ok(entries)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{SqliteAuditLog, SqliteFaceStore};
+	use crate::{
+		processors::face::{FaceEmbedding, FaceTemplate, RecognizerId, EMBEDDING_LENGTH},
+		store::{
+			audit::{AuditEntry, AuditLog, AuditOutcome},
+			profile::FaceProfile,
+			FaceStore,
+		},
+	};
+	use chrono::Utc;
+	use std::{env, fs};
+
+	#[test]
+	fn separates_users() {
+		let directory = env::temp_dir().join(format!("oblichey-sqlite-{}", std::process::id()));
+		let path = directory.join("faces.db");
+		let template = FaceTemplate {
+			recognizer_id: Some(RecognizerId::new(String::from("recognizer"))),
+			embedding: FaceEmbedding::new(&[0.5; EMBEDDING_LENGTH]),
+			pose: None,
+			adapted_at: None,
+		};
+		let profile = FaceProfile::new(
+			Some(String::from("alice")),
+			String::from("Alice"),
+			None,
+			vec![template.clone(), template],
+		);
+
+		let alice_store =
+			SqliteFaceStore::new(&path, String::from("alice")).expect("Failed to create store");
+		let bob_store =
+			SqliteFaceStore::new(&path, String::from("bob")).expect("Failed to create store");
+		alice_store.put("face", &profile).expect("Failed to put");
+		bob_store.put("other", &profile).expect("Failed to put");
+		bob_store.delete("other").expect("Failed to delete");
+		let names = bob_store.list().expect("Failed to list");
+		let loaded_profile = SqliteFaceStore::new(&path, String::from("alice"))
+			.expect("Failed to open store")
+			.get("face")
+			.expect("Failed to get");
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert!(names.is_empty());
+		assert_eq!(loaded_profile, Some(profile));
+	}
+
+	#[test]
+	fn records_audit_entries() {
+		let directory =
+			env::temp_dir().join(format!("oblichey-sqlite-audit-{}", std::process::id()));
+		let path = directory.join("faces.db");
+		let entries = vec![
+			AuditEntry::new(
+				Utc::now(),
+				Some(String::from("sudo")),
+				Some(String::from("alice")),
+				AuditOutcome::Success,
+			),
+			AuditEntry::new(Utc::now(), None, None, AuditOutcome::Error),
+		];
+
+		let audit_log = SqliteAuditLog::new(&path).expect("Failed to open audit log");
+		for entry in &entries {
+			audit_log.append(entry).expect("Failed to append");
+		}
+		SqliteFaceStore::new(&path, String::from("alice")).expect("Failed to create store");
+		let read_entries = SqliteAuditLog::new(&path)
+			.expect("Failed to open audit log")
+			.read()
+			.expect("Failed to read");
+		fs::remove_dir_all(&directory).expect("Failed to remove directory");
+
+		assert_eq!(read_entries, entries);
+	}
+}
@@ -1,23 +1,59 @@
-use pam::constants::{PamFlag, PamResultCode};
-use pam::module::{PamHandle, PamHooks};
+use pam::constants::{PamFlag, PamItemType, PamResultCode, PAM_SERVICE};
+use pam::module::{PamHandle, PamHooks, PamItem};
 use std::ffi::CStr;
 use std::io::{self, Write};
+use std::os::raw::c_char;
 use std::process::Command;
 
 const EXECUTABLE_PATH: &str = "oblichey-cli";
+/// The exit code of `oblichey-cli auth` when the user is locked out after too many failed attempts
+const LOCKED_OUT_EXIT_CODE: i32 = 2;
+
+/// The name of the PAM service, such as `sudo`, which `pam_get_item()` returns as a C string
+#[repr(transparent)]
+struct Service(c_char);
+
+impl PamItem for Service {
+	fn item_type() -> PamItemType {
+		PAM_SERVICE
+	}
+}
+
+/// Returns the name of the PAM service which requested the authentication
+#[allow(unsafe_code)]
+fn get_service(pamh: &PamHandle) -> Option<String> {
+	let service = pamh.get_item::<Service>().ok()?;
+	// SAFETY: The service is set when PAM is started and is a NUL-terminated string which lives as
+	// long as the handle
+	let service = unsafe { CStr::from_ptr(&service.0) };
+
+	service.to_str().ok().map(str::to_owned)
+}
 
 struct OblicheyPamModule;
 
 pam::pam_hooks!(OblicheyPamModule);
 
 impl PamHooks for OblicheyPamModule {
-	fn sm_authenticate(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+	fn sm_authenticate(pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+		// The user is taken from PAM rather than from the environment, which the user controls
+		let Ok(user) = pamh.get_user(None) else {
+			eprintln!("Failed to get the user to authenticate");
+			return PamResultCode::PAM_USER_UNKNOWN;
+		};
 		println!("Starting face recognition");
 
+		let mut command = Command::new(EXECUTABLE_PATH);
+		command.args(["auth", "--user", user.as_str()]);
+		// The service is recorded in the audit log
+		if let Some(service) = get_service(pamh) {
+			command.args(["--service", service.as_str()]);
+		}
+
 		// This is one of the ugliest things I have done recently and there really ought to be a
 		// way to do this other than calling another executable. Ideally, we would make the core
 		// code into some kind of a shared library.
-		match Command::new(EXECUTABLE_PATH).arg("auth").output() {
+		match command.output() {
 			Ok(o) => {
 				if o.status.success() {
 					println!("Face recognition successful");
@@ -30,6 +66,10 @@ impl PamHooks for OblicheyPamModule {
 						eprintln!("Failed to print stderr: {e}");
 					};
 
+					if o.status.code() == Some(LOCKED_OUT_EXIT_CODE) {
+						println!("Face recognition is locked due to too many failed attempts");
+						return PamResultCode::PAM_MAXTRIES;
+					}
 					println!("Face recognition unsuccessful");
 					PamResultCode::PAM_AUTH_ERR
 				}
@@ -26,6 +26,25 @@ in
             description = "Path to the IR camera to be used.";
           };
         };
+        inference = {
+          backend = mkOption {
+            type = types.enum ["auto" "wgpu" "ndarray"];
+            default = "auto";
+            description = "Backend used for running the neural networks.";
+          };
+        };
+        store = {
+          backend = mkOption {
+            type = types.enum ["directory" "sqlite"];
+            default = "directory";
+            description = "Backend used for storing the scanned faces.";
+          };
+          path = mkOption {
+            type = types.str;
+            default = "/var/lib/oblichey/faces.db";
+            description = "Path to the database used by the SQLite store backend.";
+          };
+        };
       };
       pamServices = mkOption {
         type = types.listOf types.str;
@@ -38,6 +57,13 @@ in
         etc."oblichey.toml".text = ''
           [camera]
           path="${cfg.settings.camera.path}"
+
+          [inference]
+          backend="${cfg.settings.inference.backend}"
+
+          [store]
+          backend="${cfg.settings.store.backend}"
+          path="${cfg.settings.store.path}"
         '';
       };
       security.pam.services = lib.genAttrs cfg.pamServices (service: {